@@ -9,7 +9,7 @@ pub fn expand_call(def: CallDef) -> proc_macro2::TokenStream {
 	let fn_name = methods.iter().map(|method| &method.name).collect::<Vec<_>>();
 
 	// This is a nested vector of all the arguments for each of the functions in `fn_name`. It does
-	// not include the `self` or `caller: T::AccountId` parameter, which we always assume are the
+	// not include the `self` or `origin: OriginFor<T>` parameter, which we always assume are the
 	// first two parameters to these calls.
 	let args_name = methods
 		.iter()
@@ -23,13 +23,22 @@ pub fn expand_call(def: CallDef) -> proc_macro2::TokenStream {
 		.map(|method| method.args.iter().map(|(_, type_)| type_.clone()).collect::<Vec<_>>())
 		.collect::<Vec<_>>();
 
+	// This is a vector of whether each of the functions in `fn_name` may be called unsigned.
+	let unsigned = methods.iter().map(|method| method.unsigned).collect::<Vec<_>>();
+
+	// This is a vector of the weight declared by each of the functions in `fn_name`.
+	let weight = methods.iter().map(|method| &method.weight).collect::<Vec<_>>();
+
 	// This quote block creates an `enum Call` which contains all the calls exposed by our pallet,
-	// and the `Dispatch` trait logic to route a `caller` to access those functions.
+	// and the `Dispatch` trait logic to route an `origin` to access those functions.
 	let dispatch_impl = quote! {
 		// The callable functions exposed by this pallet.
 		//
 		// The parsed function names will be `snake_case`, and that will show up in the enum.
+		//
+		// A call is encoded as its index in the enum, followed by its arguments in order.
 		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, crate::codec::Encode, crate::codec::Decode)]
 		pub enum Call<T: Config> {
 			#(
 				#fn_name { #( #args_name: #args_type),* },
@@ -37,24 +46,47 @@ pub fn expand_call(def: CallDef) -> proc_macro2::TokenStream {
 		}
 
 		// Dispatch logic at the pallet level, mapping each of the items in the `Call` enum to the
-		// appropriate function call with all arguments, including the `caller`.
+		// appropriate function call with all arguments, including the `origin`.
 		impl<T: Config> crate::support::Dispatch for #pallet_struct<T> {
-			type Caller = T::AccountId;
+			type RuntimeOrigin = crate::system::OriginFor<T>;
 			type Call = Call<T>;
 
-			fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> crate::support::DispatchResult {
+			fn dispatch(
+				&mut self,
+				origin: Self::RuntimeOrigin,
+				call: Self::Call,
+			) -> crate::support::DispatchResultWithPostInfo {
 				match call {
 					#(
 						Call::#fn_name { #( #args_name ),* } => {
+							// A call may return a `DispatchResult` or a
+							// `DispatchResultWithPostInfo`.
 							self.#fn_name(
-								// Note that we assume the first argument of every call is the `caller`.
-								caller,
+								// Note that we assume the first argument of every call is the
+								// `origin`.
+								origin,
 								#( #args_name ),*
-							)?;
+							)
+							.map(Into::into)
+							.map_err(Into::into)
 						},
 					)*
 				}
-				Ok(())
+			}
+
+			fn allows_unsigned(call: &Self::Call) -> bool {
+				match call {
+					#( Call::#fn_name { .. } => #unsigned, )*
+				}
+			}
+
+			// The weight of each call is the expression in its `#[weight(..)]` attribute, which
+			// can use the args of the call.
+			#[allow(unused_variables)]
+			fn weight(call: &Self::Call) -> crate::support::Weight {
+				match call {
+					#( Call::#fn_name { #( #args_name ),* } => #weight, )*
+				}
 			}
 		}
 	};
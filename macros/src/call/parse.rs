@@ -4,7 +4,7 @@ use syn::spanned::Spanned;
 // Custom keywords we match to when parsing the calls in a pallet.
 mod keyword {
 	syn::custom_keyword!(T);
-	syn::custom_keyword!(AccountId);
+	syn::custom_keyword!(OriginFor);
 }
 
 /// This object will collect all the information we need to keep while parsing the callable
@@ -25,6 +25,12 @@ pub struct CallVariantDef {
 	pub name: syn::Ident,
 	/// Information on args of the function: `(name, type)`.
 	pub args: Vec<(syn::Ident, Box<syn::Type>)>,
+	/// Whether the function is marked `#[unsigned]`. Only these calls may be submitted in
+	/// extrinsics without a signature, in which case their origin is `RawOrigin::None`.
+	pub unsigned: bool,
+	/// The expression in `#[weight(..)]`, which every function must have. It can use the args of
+	/// the function, by reference.
+	pub weight: syn::Expr,
 }
 
 impl CallDef {
@@ -59,22 +65,34 @@ impl CallDef {
 					},
 				}
 
-				// The second argument should be the `caller: T::AccountId` argument.
+				// Calls which may be submitted without a signature are marked with `#[unsigned]`.
+				let unsigned = method.attrs.iter().any(is_unsigned_attr);
+
+				// Every call must declare its weight with `#[weight(..)]`.
+				let weight = match method.attrs.iter().find(|attr| is_weight_attr(attr)) {
+					Some(attr) => attr.parse_args::<syn::Expr>()?,
+					None => {
+						let msg = "Invalid call, expected a `#[weight(..)]` attribute";
+						return Err(syn::Error::new(method.sig.span(), msg))
+					},
+				};
+
+				// The second argument should be the `origin: OriginFor<T>` argument.
 				match method.sig.inputs.iter().skip(1).next() {
 					Some(syn::FnArg::Typed(arg)) => {
 						// Here we specifically check that this argument is as we expect for
-						// `caller: T::AccountId`.
-						check_caller_arg(arg)?;
+						// `origin: OriginFor<T>`.
+						check_origin_arg(arg)?;
 					},
 					_ => {
-						let msg = "Invalid call, second argument should be `caller: T::AccountId`";
+						let msg = "Invalid call, second argument should be `origin: OriginFor<T>`";
 						return Err(syn::Error::new(method.sig.span(), msg))
 					},
 				}
 
 				let fn_name = method.sig.ident.clone();
 
-				// Parsing the rest of the args. Skipping 2 for `self` and `caller`.
+				// Parsing the rest of the args. Skipping 2 for `self` and `origin`.
 				for arg in method.sig.inputs.iter().skip(2) {
 					// All arguments should be typed.
 					let arg = if let syn::FnArg::Typed(arg) = arg {
@@ -96,7 +114,7 @@ impl CallDef {
 				}
 
 				// Store all the function name and the arg data for the function.
-				methods.push(CallVariantDef { name: fn_name, args });
+				methods.push(CallVariantDef { name: fn_name, args, unsigned, weight });
 			}
 		}
 
@@ -105,33 +123,56 @@ impl CallDef {
 	}
 }
 
-/// Check caller arg is exactly: `caller: T::AccountId`.
+/// Whether `attr` is exactly `#[unsigned]`.
+pub fn is_unsigned_attr(attr: &syn::Attribute) -> bool {
+	matches!(&attr.meta, syn::Meta::Path(path) if path.is_ident("unsigned"))
+}
+
+/// Whether `attr` is `#[weight(..)]`.
+pub fn is_weight_attr(attr: &syn::Attribute) -> bool {
+	matches!(&attr.meta, syn::Meta::List(list) if list.path.is_ident("weight"))
+}
+
+/// Remove the attributes we parse from the callable functions, since they are not real
+/// attributes and the compiler would reject them.
+pub fn strip_call_attrs(item: &mut syn::Item) {
+	if let syn::Item::Impl(item_impl) = item {
+		for item in item_impl.items.iter_mut() {
+			if let syn::ImplItem::Fn(method) = item {
+				method.attrs.retain(|attr| !is_unsigned_attr(attr) && !is_weight_attr(attr));
+			}
+		}
+	}
+}
+
+/// Check origin arg is exactly: `origin: OriginFor<T>`.
 ///
 /// This is kept strict to keep the code simple.
-pub fn check_caller_arg(arg: &syn::PatType) -> syn::Result<()> {
+pub fn check_origin_arg(arg: &syn::PatType) -> syn::Result<()> {
 	pub struct CheckDispatchableFirstArg;
 	impl syn::parse::Parse for CheckDispatchableFirstArg {
 		fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+			input.parse::<keyword::OriginFor>()?;
+			input.parse::<syn::Token![<]>()?;
 			input.parse::<keyword::T>()?;
-			input.parse::<syn::Token![::]>()?;
-			input.parse::<keyword::AccountId>()?;
+			input.parse::<syn::Token![>]>()?;
 			Ok(Self)
 		}
 	}
 
-	// This checks the arg name is `caller` or `_caller`.
+	// This checks the arg name is `origin` or `_origin`.
 	if let syn::Pat::Ident(ident) = &*arg.pat {
-		// We also support the name as `_caller` for when the variable is unused.
-		if &ident.ident != "caller" && &ident.ident != "_caller" {
-			let msg = "Invalid name for second parameter: expected `caller: T::AccountId`";
+		// We also support the name as `_origin` for when the variable is unused.
+		if &ident.ident != "origin" && &ident.ident != "_origin" {
+			let msg = "Invalid name for second parameter: expected `origin: OriginFor<T>`";
 			return Err(syn::Error::new(ident.span(), msg))
 		}
 	}
 
-	// This checks the type is `T::AccountId` with `CheckDispatchableFirstArg`
+	// This checks the type is `OriginFor<T>` with `CheckDispatchableFirstArg`
 	let ty = &arg.ty;
 	syn::parse2::<CheckDispatchableFirstArg>(ty.to_token_stream()).map_err(|e| {
-		let msg = "Invalid type for second parameter: expected `caller: T::AccountId`";
+		let msg = "Invalid type for second parameter: expected `origin: OriginFor<T>`";
 		let mut err = syn::Error::new(ty.span(), msg);
 		err.combine(e);
 		err
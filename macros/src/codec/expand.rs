@@ -0,0 +1,167 @@
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+
+/// See the `fn derive_encode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_encode(input: syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+	let name = &input.ident;
+	let generics = bounded_generics(&input, quote!(crate::codec::Encode));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	let body = match &input.data {
+		syn::Data::Struct(data) => {
+			let (pattern, bindings) = destructure(&data.fields);
+			quote! {
+				let Self #pattern = self;
+				#( crate::codec::Encode::encode_to(#bindings, dest); )*
+			}
+		},
+		syn::Data::Enum(data) => {
+			check_variant_count(data)?;
+			// Each variant is encoded as its index, followed by its fields.
+			let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+				let variant_name = &variant.ident;
+				let index = index as u8;
+				let (pattern, bindings) = destructure(&variant.fields);
+				quote! {
+					Self::#variant_name #pattern => {
+						dest.push(#index);
+						#( crate::codec::Encode::encode_to(#bindings, dest); )*
+					}
+				}
+			});
+			quote! {
+				match self {
+					#( #arms )*
+				}
+			}
+		},
+		syn::Data::Union(_) => {
+			return Err(syn::Error::new(input.span(), "Encode can not be derived for unions"))
+		},
+	};
+
+	Ok(quote! {
+		impl #impl_generics crate::codec::Encode for #name #ty_generics #where_clause {
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				#body
+			}
+		}
+	})
+}
+
+/// See the `fn derive_decode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_decode(input: syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+	let name = &input.ident;
+	let generics = bounded_generics(&input, quote!(crate::codec::Decode));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	let body = match &input.data {
+		syn::Data::Struct(data) => {
+			let fields = construct(&data.fields);
+			quote! { Ok(Self #fields) }
+		},
+		syn::Data::Enum(data) => {
+			check_variant_count(data)?;
+			let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+				let variant_name = &variant.ident;
+				let index = index as u8;
+				let fields = construct(&variant.fields);
+				quote! { #index => Ok(Self::#variant_name #fields), }
+			});
+			quote! {
+				match <u8 as crate::codec::Decode>::decode(input)? {
+					#( #arms )*
+					_ => Err(crate::codec::Error("unknown variant index")),
+				}
+			}
+		},
+		syn::Data::Union(_) => {
+			return Err(syn::Error::new(input.span(), "Decode can not be derived for unions"))
+		},
+	};
+
+	Ok(quote! {
+		impl #impl_generics crate::codec::Decode for #name #ty_generics #where_clause {
+			fn decode(input: &mut &[u8]) -> Result<Self, crate::codec::Error> {
+				#body
+			}
+		}
+	})
+}
+
+/// The index of a variant is encoded in a single byte.
+fn check_variant_count(data: &syn::DataEnum) -> syn::Result<()> {
+	if data.variants.len() > 256 {
+		let msg = "Invalid codec derive, enums can have at most 256 variants";
+		return Err(syn::Error::new(data.variants.span(), msg))
+	}
+	Ok(())
+}
+
+/// The generics of `input`, with every field type bound by `bound`.
+///
+/// Bounding the field types rather than the type parameters lets us derive for types like
+/// `Event<T: Config>`, where only the associated types of `T` are encoded.
+fn bounded_generics(input: &syn::DeriveInput, bound: proc_macro2::TokenStream) -> syn::Generics {
+	let mut generics = input.generics.clone();
+	if generics.type_params().next().is_none() {
+		return generics
+	}
+
+	let field_types = match &input.data {
+		syn::Data::Struct(data) => data.fields.iter().map(|field| &field.ty).collect::<Vec<_>>(),
+		syn::Data::Enum(data) => data
+			.variants
+			.iter()
+			.flat_map(|variant| variant.fields.iter().map(|field| &field.ty))
+			.collect(),
+		syn::Data::Union(_) => Vec::new(),
+	};
+
+	// The same type often shows up in many fields, but we only need to bound it once.
+	let mut seen = std::collections::BTreeSet::new();
+	let where_clause = generics.make_where_clause();
+	for ty in field_types {
+		if seen.insert(quote!(#ty).to_string()) {
+			where_clause.predicates.push(syn::parse_quote!(#ty: #bound));
+		}
+	}
+	generics
+}
+
+/// A pattern which binds every field in `fields` by reference, and the names of the bindings in
+/// order.
+fn destructure(fields: &syn::Fields) -> (proc_macro2::TokenStream, Vec<syn::Ident>) {
+	match fields {
+		syn::Fields::Named(named) => {
+			let names = named
+				.named
+				.iter()
+				.map(|field| field.ident.clone().expect("named fields have a name; qed"))
+				.collect::<Vec<_>>();
+			(quote!({ #( #names ),* }), names)
+		},
+		syn::Fields::Unnamed(unnamed) => {
+			let names = (0..unnamed.unnamed.len())
+				.map(|i| format_ident!("__field_{}", i))
+				.collect::<Vec<_>>();
+			(quote!(( #( #names ),* )), names)
+		},
+		syn::Fields::Unit => (quote!(), Vec::new()),
+	}
+}
+
+/// The fields part of a constructor for `fields`, decoding each field in order from `input`.
+fn construct(fields: &syn::Fields) -> proc_macro2::TokenStream {
+	match fields {
+		syn::Fields::Named(named) => {
+			let names = named.named.iter().map(|field| &field.ident);
+			quote!({ #( #names: crate::codec::Decode::decode(input)? ),* })
+		},
+		syn::Fields::Unnamed(unnamed) => {
+			let values = unnamed.unnamed.iter().map(|_| quote!(crate::codec::Decode::decode(input)?));
+			quote!(( #( #values ),* ))
+		},
+		syn::Fields::Unit => quote!(),
+	}
+}
@@ -0,0 +1,21 @@
+pub mod expand;
+
+/// See the `fn derive_encode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn derive_encode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = syn::parse_macro_input!(item as syn::DeriveInput);
+
+	match expand::expand_encode(input) {
+		Ok(generated) => generated.into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
+
+/// See the `fn derive_decode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn derive_decode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = syn::parse_macro_input!(item as syn::DeriveInput);
+
+	match expand::expand_decode(input) {
+		Ok(generated) => generated.into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
@@ -0,0 +1,81 @@
+use super::parse::ErrorDef;
+use quote::quote;
+
+/// See the `fn error` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_error(def: ErrorDef) -> proc_macro2::TokenStream {
+	let ErrorDef { mut item, variants } = def;
+
+	// The index of each variant, used to identify the error inside of a `DispatchError`.
+	let indices = (0..variants.len()).map(|i| i as u8).collect::<Vec<_>>();
+	// The name of each variant, used as a human readable message.
+	let names = variants.iter().map(|variant| variant.to_string()).collect::<Vec<_>>();
+
+	// The enum must use `T` somewhere, so we add a variant which can never be constructed.
+	item.variants.push(syn::parse_quote! {
+		#[doc(hidden)]
+		__Ignore(core::marker::PhantomData<T>, core::convert::Infallible)
+	});
+
+	// We implement the common traits by hand, since `derive` would require `T` to implement
+	// them too, and the runtime is not `Copy` or `PartialEq`.
+	quote! {
+		#item
+
+		impl<T: Config> crate::support::PalletError for Error<T> {
+			fn index(&self) -> u8 {
+				match self {
+					#( Self::#variants => #indices, )*
+					Self::__Ignore(_, never) => match *never {},
+				}
+			}
+
+			fn as_str(&self) -> &'static str {
+				match self {
+					#( Self::#variants => #names, )*
+					Self::__Ignore(_, never) => match *never {},
+				}
+			}
+
+			fn from_index(index: u8) -> Option<Self> {
+				match index {
+					#( #indices => Some(Self::#variants), )*
+					_ => None,
+				}
+			}
+		}
+
+		impl<T: Config> From<Error<T>> for crate::support::DispatchError {
+			fn from(error: Error<T>) -> Self {
+				// The index of the pallet is only known by the runtime which includes it.
+				let index = <<T as crate::system::Config>::PalletInfo as crate::support::PalletInfo>::index::<Pallet<T>>()
+					.expect("pallet is not part of the runtime");
+				crate::support::DispatchError::Module(crate::support::ModuleError {
+					index,
+					error: crate::support::PalletError::index(&error),
+				})
+			}
+		}
+
+		impl<T: Config> core::fmt::Debug for Error<T> {
+			fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+				f.write_str(crate::support::PalletError::as_str(self))
+			}
+		}
+
+		impl<T: Config> Clone for Error<T> {
+			fn clone(&self) -> Self {
+				*self
+			}
+		}
+
+		impl<T: Config> Copy for Error<T> {}
+
+		impl<T: Config> PartialEq for Error<T> {
+			fn eq(&self, other: &Self) -> bool {
+				crate::support::PalletError::index(self) == crate::support::PalletError::index(other)
+			}
+		}
+
+		impl<T: Config> Eq for Error<T> {}
+	}
+}
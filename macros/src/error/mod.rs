@@ -0,0 +1,17 @@
+pub mod expand;
+pub mod parse;
+
+/// See the `fn error` docs at the `lib.rs` of this crate for a high level definition.
+pub fn error(
+	_attr: proc_macro::TokenStream,
+	item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+	let item_enum = syn::parse_macro_input!(item as syn::Item);
+
+	// Unlike the other macros, we need to change the `enum` itself to add a hidden variant, so the
+	// expanded code replaces `item` instead of extending it.
+	match parse::ErrorDef::try_from(item_enum) {
+		Ok(def) => expand::expand_error(def).into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
@@ -0,0 +1,49 @@
+use syn::spanned::Spanned;
+
+/// This object will collect all the information we need to keep while parsing the `Error` enum.
+#[derive(Debug)]
+pub struct ErrorDef {
+	/// The parsed `enum Error<T>`, which we re-emit with an extra hidden variant.
+	pub item: syn::ItemEnum,
+	/// The names of the variants of the enum, in declaration order. The position of a variant in
+	/// this list is its error index.
+	pub variants: Vec<syn::Ident>,
+}
+
+impl ErrorDef {
+	pub fn try_from(item: syn::Item) -> syn::Result<Self> {
+		// First we check that we are parsing an `enum`.
+		let item_enum = if let syn::Item::Enum(item) = item {
+			item
+		} else {
+			return Err(syn::Error::new(item.span(), "Invalid pallet::error, expected item enum"))
+		};
+
+		// We assume the enum is exactly `Error<T>`, to keep the generated code simple.
+		if item_enum.ident != "Error" {
+			let msg = "Invalid pallet::error, expected enum named `Error`";
+			return Err(syn::Error::new(item_enum.ident.span(), msg))
+		}
+		if item_enum.generics.params.len() != 1 {
+			let msg = "Invalid pallet::error, expected a single generic parameter `T`";
+			return Err(syn::Error::new(item_enum.generics.span(), msg))
+		}
+
+		// Errors are identified by their index, so they can not carry any data.
+		let mut variants = vec![];
+		for variant in item_enum.variants.iter() {
+			if !matches!(variant.fields, syn::Fields::Unit) {
+				let msg = "Invalid pallet::error, variants can not have fields";
+				return Err(syn::Error::new(variant.span(), msg))
+			}
+			variants.push(variant.ident.clone());
+		}
+
+		if variants.len() > u8::MAX as usize {
+			let msg = "Invalid pallet::error, too many variants";
+			return Err(syn::Error::new(item_enum.span(), msg))
+		}
+
+		Ok(Self { item: item_enum, variants })
+	}
+}
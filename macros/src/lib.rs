@@ -1,6 +1,23 @@
 mod call;
+mod codec;
+mod error;
 mod runtime;
 
+/// Expand the callable functions of a pallet.
+///
+/// Every function in the `impl` block becomes a variant of the generated `enum Call`, which derives
+/// `codec::Encode` and `codec::Decode`, and is dispatched through `support::Dispatch`. The first
+/// two parameters of a function must be `&mut self` and `origin: OriginFor<T>`, which the function
+/// checks with the helpers of `system`, such as `ensure_signed` and `ensure_root`.
+///
+/// Only a function marked with `#[unsigned]` can be submitted in an extrinsic without a signature,
+/// in which case its origin is `RawOrigin::None`. Such an extrinsic only goes in the transaction
+/// pool if the pallet accepts it in `support::ValidateUnsigned`.
+///
+/// Every function must declare its weight with `#[weight(..)]`, which takes an expression of type
+/// `support::Weight`. The expression can use the args of the function, by reference. A function
+/// can return either a `support::DispatchResult`, or a `support::DispatchResultWithPostInfo` to
+/// report that it used less weight than it declared.
 #[proc_macro_attribute]
 pub fn call(
 	attr: proc_macro::TokenStream,
@@ -9,22 +26,113 @@ pub fn call(
 	call::call(attr, item)
 }
 
+/// Derive `codec::Encode` for a struct or an enum.
+///
+/// A struct is encoded as the concatenation of its fields. An enum is encoded as the index of the
+/// variant, which is its position in the enum, followed by its fields. Explicit discriminants are
+/// ignored.
+///
+/// For generic types, every field type must implement `Encode`, rather than every type parameter.
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	codec::derive_encode(item)
+}
+
+/// Derive `codec::Decode` for a struct or an enum, reading back what `#[derive(Encode)]` writes.
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	codec::derive_decode(item)
+}
+
+/// Expand the `Error` enum of a pallet.
+///
+/// The enum must be named `Error`, take a single generic parameter `T: Config`, and only contain
+/// unit variants. The index of a variant is its position in the enum.
+///
+/// This generates:
+/// - a hidden, uninhabited variant which uses `T`, so the enum can be generic over the pallet
+///   config without storing anything.
+/// - an implementation of `support::PalletError`, to convert between a variant, its index and its
+///   name.
+/// - `From<Error<T>> for support::DispatchError`, which looks up the index of the pallet in the
+///   runtime through `system::Config::PalletInfo`.
+/// - `Debug`, `Clone`, `Copy`, `PartialEq` and `Eq`, without requiring `T` to implement them.
+#[proc_macro_attribute]
+pub fn error(
+	attr: proc_macro::TokenStream,
+	item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+	error::error(attr, item)
+}
+
 /// Expand the `Runtime` definition.
 ///
 /// This generates function implementations on `Runtime`:
 /// - `fn new()` - which generates a new instance of the runtime, by instantiating all the pallets
-///   included in the runtime.
+///   included in the runtime. Pallets keep their state in storage, so the runtime itself holds no
+///   state, and every other function must run inside of `storage::Storage::execute_with`.
+///   `Default` builds the runtime the same way, so pallets can build it to dispatch calls.
 /// - `fn execute_block()` - which handles basic logic for executing a block of extrinsics. It does
-///   basic actions like incrementing the block number and checking the block to be executed has a
-///   valid block number.
+///   basic actions like incrementing the block number, and checks that the header builds on the
+///   last executed block and matches the extrinsics and the resulting state root. Every extrinsic
+///   is dispatched inside `storage::with_transaction`, so the changes of a failing extrinsic are
+///   rolled back across all pallets. The signature of signed extrinsics is verified before
+///   dispatch, and the origin is `RawOrigin::Signed` with the account of the signer. Blocks
+///   containing an extrinsic with a bad signature, the wrong nonce, or an unsigned call which its
+///   pallet does not allow, are rejected as a whole. Unsigned extrinsics pay no fee, so a block is
+///   also rejected if the call of one of them fails: the pallet of an unsigned call stops it from
+///   being replayed by making it fail the second time.
+/// - Blocks are limited to `system::Config::MAX_BLOCK_WEIGHT`. Every extrinsic weighs
+///   `system::Config::EXTRINSIC_BASE_WEIGHT` on top of the weight of its call, and a block with an
+///   extrinsic which could take it over the maximum is rejected. Once dispatched, the block is only
+///   charged the weight the call actually used. Blocks are also limited to
+///   `system::Config::MAX_BLOCK_LENGTH` bytes of encoded extrinsics.
+/// - Signed extrinsics pay a fee through `system::Config::OnChargeTransaction`, for all the weight
+///   they declare, before dispatch. Blocks with an extrinsic whose caller can not pay are rejected.
+///   The fee for the weight the call did not use is refunded after dispatch.
+/// - `fn validate_transaction()` - checks an extrinsic against the current state without changing
+///   it, for the transaction pool, and returns a `support::ValidTransaction`. Signed extrinsics
+///   provide a tag made of their caller and the nonce after theirs, and require the tag of the
+///   previous nonce when their nonce is ahead of the caller. Their priority comes from their tip,
+///   through `support::OnChargeTransaction::priority`. Unsigned extrinsics are only valid if the
+///   pallet of their call accepts them in `support::ValidateUnsigned`, which no pallet does by
+///   default, and if their call succeeds in a dry run.
+/// - `fn initialize_block()`, `fn apply_extrinsic()` and `fn finalize_block()` - the steps of
+///   `execute_block`, which can also be used to build a new block. `finalize_block` records the
+///   extrinsics root, and returns the state root, which is the root of the storage trie.
+/// - Every pallet but system must implement `support::ValidateUnsigned`, and the runtime
+///   implements it by asking the pallet of the call.
+/// - Every pallet, including system, must implement `support::Hooks`. `initialize_block` calls
+///   `on_initialize` on every pallet, and `finalize_block` calls `on_idle` and then `on_finalize`
+///   on every pallet, always in the order the pallets are declared. Events are recorded with the
+///   `system::Phase` of the block they were emitted in.
 ///
 /// This also generates code needed for dispatching calls to the pallets:
 /// - Note: For simplicity, we assume that the system pallet is not callable.
 /// - `enum RuntimeCall` - an "outer"-enum representing the accumulation of all possible calls to
-///   all pallets. The system pallet is not included.
+///   all pallets. The system pallet is not included. It implements `codec::Encode` and
+///   `codec::Decode`, as the index of the pallet followed by the pallet call. Calls which wrap
+///   other calls are decoded with `codec::decode_nested`, which limits how deep they can be nested.
+/// - `type RuntimeOrigin` - the `system::RawOrigin` of the runtime, which calls are dispatched
+///   with. Signed extrinsics are dispatched with `RawOrigin::Signed`, and unsigned ones with
+///   `RawOrigin::None`.
 /// - implements the trait `support::Dispatch` to dispatch calls to the appropriate pallet. Basic
 ///   logic like incrementing the nonce of the user is included in the generated code. The system
 ///   pallet is not included.
+/// - `struct PalletInfo` - implements `support::PalletInfo`, giving the index of each pallet in the
+///   runtime, with system at index 0. This is used to tag errors with the pallet which raised them.
+/// - `enum RuntimeError` - an "outer"-enum of the `Error` enums of all pallets, which can be
+///   recovered from a `support::DispatchError`. The system pallet is not included.
+/// - `enum RuntimeEvent` - an "outer"-enum representing the accumulation of all the `Event` enums
+///   of all pallets, including system. Every pallet event converts into it with `From`, and it
+///   implements `codec::Encode` and `codec::Decode`, so events can be kept in storage. Every
+///   pallet but system must implement `support::PalletEvents`, which the events of each
+///   extrinsic are collected from.
+/// - `struct RuntimeGenesisConfig` - the accumulation of the `GenesisConfig` of all pallets,
+///   including system, in a field named after each pallet. It can be read from JSON with `serde`,
+///   and implements `support::BuildGenesisConfig`, building the genesis state of each pallet in
+///   turn. Every pallet must define a `GenesisConfig<T>` implementing `Default` and
+///   `support::BuildGenesisConfig`.
 #[proc_macro_attribute]
 pub fn runtime(
 	attr: proc_macro::TokenStream,
@@ -9,9 +9,18 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 	let pallet_names = pallets.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
 	// This is a vector of all the pallet types, not including system.
 	let pallet_types = pallets.iter().map(|(_, type_)| type_.clone()).collect::<Vec<_>>();
+	// This is a vector of the index of each pallet in the runtime. System is at index 0.
+	let pallet_indices = (1..=pallets.len()).map(|i| i as u8).collect::<Vec<_>>();
 
 	// This quote block implements functions on the `Runtime` struct.
 	let runtime_impl = quote! {
+		// Pallets which dispatch calls, like sudo, build the runtime with this.
+		impl Default for #runtime_struct {
+			fn default() -> Self {
+				Self::new()
+			}
+		}
+
 		impl #runtime_struct {
 			// Create a new instance of the main Runtime, by creating a new instance of each pallet.
 			fn new() -> Self {
@@ -24,23 +33,299 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 				}
 			}
 
-			// Execute a block of extrinsics. Increments the block number.
-			fn execute_block(&mut self, block: types::Block) -> crate::support::DispatchResult {
+			// Start executing a block on top of the current state. Checks that the header follows
+			// the last executed block, and moves to its block number.
+			fn initialize_block(&mut self, header: &types::Header) -> Result<(), crate::support::BlockError> {
+				// This must come first, since the hash of the last block depends on the state root.
+				let parent_hash = self.system.block_hash();
 				self.system.inc_block_number();
-				if block.header.block_number != self.system.block_number() {
-					return Err(&"block number does not match what is expected")
-				}
-				for (i, support::Extrinsic { caller, call }) in block.extrinsics.into_iter().enumerate() {
-					self.system.inc_nonce(&caller);
-					let _res = self.dispatch(caller, call).map_err(|e| {
-						eprintln!(
-							"Extrinsic Error\n\tBlock Number: {}\n\tExtrinsic Number: {}\n\tError: {}",
-							block.header.block_number, i, e
-						)
-					});
+				if header.block_number != self.system.block_number() {
+					return Err(crate::support::BlockError::UnexpectedBlockNumber)
+				}
+				if header.parent_hash != parent_hash {
+					return Err(crate::support::BlockError::ParentHashMismatch)
+				}
+				self.system.initialize(header.parent_hash, &header.digest);
+				self.system.reset_events();
+
+				// Run the `on_initialize` hook of every pallet, in the order they are declared.
+				let block_number = self.system.block_number();
+				let weight = crate::support::Hooks::on_initialize(&mut self.system, block_number);
+				self.system.register_weight(weight);
+				#(
+					let weight = crate::support::Hooks::on_initialize(
+						&mut self.#pallet_names,
+						block_number,
+					);
+					self.system.register_weight(weight);
+				)*
+				self.collect_events(system::Phase::Initialization);
+				Ok(())
+			}
+
+			// Move the events the pallets queued up into the events of the block, as emitted
+			// during `phase`.
+			fn collect_events(&mut self, phase: system::Phase) {
+				#(
+					let events = crate::support::PalletEvents::take_events(&mut self.#pallet_names);
+					for event in events {
+						self.system.deposit_event(phase, event);
+					}
+				)*
+			}
+
+			// Apply the extrinsic at `extrinsic_index` in the current block, and record the events
+			// it emitted in the system pallet.
+			//
+			// An error means the extrinsic can not be included in a block at all, for example
+			// because its signature does not match, its nonce was already used, or the caller can
+			// not pay its fee. In that case the state is left untouched. A call which fails to
+			// dispatch is still included: it is rolled back, and its failure is recorded as an
+			// event. Its fee is still paid.
+			fn apply_extrinsic(
+				&mut self,
+				extrinsic_index: u32,
+				extrinsic: types::Extrinsic,
+			) -> Result<(), crate::support::InvalidTransaction> {
+				let phase = system::Phase::ApplyExtrinsic(extrinsic_index);
+				let len = crate::codec::Encode::encode(&extrinsic).len() as u32;
+				let support::Extrinsic { signature, call } = extrinsic;
+				// The extrinsic must fit in the block with all the weight its call may use.
+				let call_weight = <Self as crate::support::Dispatch>::weight(&call);
+				let base_weight = <Self as system::Config>::EXTRINSIC_BASE_WEIGHT;
+				let weight = base_weight.saturating_add(call_weight);
+				if self.system.block_weight().saturating_add(weight)
+					> <Self as system::Config>::MAX_BLOCK_WEIGHT
+				{
+					return Err(crate::support::InvalidTransaction::ExhaustsResources)
+				}
+				// And it must fit in the block with its length.
+				if self.system.block_length().saturating_add(len)
+					> <Self as system::Config>::MAX_BLOCK_LENGTH
+				{
+					return Err(crate::support::InvalidTransaction::ExhaustsResources)
+				}
+				let caller = match signature {
+					Some(support::ExtrinsicSignature { signer, signature, nonce, tip }) => {
+						// The signature covers the call, the nonce, the tip and the genesis hash.
+						let payload = crate::support::signing_payload(
+							&call,
+							&nonce,
+							&tip,
+							&self.system.genesis_hash(),
+						);
+						if !crate::support::Verify::verify(&signature, &payload, &signer) {
+							return Err(crate::support::InvalidTransaction::BadProof)
+						}
+						let caller = crate::support::IdentifyAccount::into_account(signer);
+						// Only the next nonce of the caller is accepted, which stops an extrinsic
+						// from being replayed.
+						self.system.check_nonce(&caller, nonce)?;
+						// The fee covers all the weight the call may use. What it does not use is
+						// refunded once it was dispatched. The tip is never refunded.
+						<<Self as system::Config>::OnChargeTransaction as crate::support::OnChargeTransaction<_>>::withdraw_fee(
+							&caller,
+							weight,
+							len,
+							&tip,
+						)?;
+						self.system.inc_nonce(&caller);
+						Some(caller)
+					}
+					None => {
+						if !<Self as crate::support::Dispatch>::allows_unsigned(&call) {
+							return Err(crate::support::InvalidTransaction::UnsignedNotAllowed)
+						}
+						None
+					}
+				};
+				// Each extrinsic runs in its own storage transaction, so a failing call leaves no
+				// trace in any pallet. The nonce and the fee are taken outside of it, and always
+				// stick.
+				let payer = caller.clone();
+				let origin = match caller {
+					Some(caller) => system::RawOrigin::Signed(caller),
+					None => system::RawOrigin::None,
+				};
+				let result = crate::storage::with_transaction(|| self.dispatch(origin, call));
+				// No one pays for an unsigned extrinsic, so one whose call fails is not included at
+				// all. Its pallet must make the call fail once it was dispatched, if it is not meant
+				// to be dispatched again.
+				if payer.is_none() && result.is_err() {
+					return Err(crate::support::InvalidTransaction::Call)
+				}
+				// The block is charged for the weight the call actually used, which is never more
+				// than it declared, so the block stays within its maximum. The caller gets back the
+				// fee for the rest.
+				let (result, post_info) = match result {
+					Ok(post_info) => (Ok(()), post_info),
+					Err(error) => (Err(error.error), error.post_info),
+				};
+				let actual_weight =
+					base_weight.saturating_add(post_info.calc_actual_weight(call_weight));
+				self.system.register_weight(actual_weight);
+				self.system.register_length(len);
+				if let Some(payer) = &payer {
+					<<Self as system::Config>::OnChargeTransaction as crate::support::OnChargeTransaction<_>>::refund_fee(
+						payer,
+						weight,
+						actual_weight,
+					);
+				}
+				// Collect the events the pallets queued up for this extrinsic. Events of a failed
+				// call were rolled back along with the rest of its changes.
+				self.collect_events(phase);
+				match result {
+					Ok(()) => self.system.deposit_event(
+						phase,
+						system::Event::ExtrinsicSuccess { weight: actual_weight },
+					),
+					Err(error) => self.system.deposit_event(
+						phase,
+						system::Event::ExtrinsicFailed { error, weight: actual_weight },
+					),
 				}
 				Ok(())
 			}
+
+			// Check whether `extrinsic` could be included in the next block, on top of the current
+			// state, for the transaction pool. Nothing is dispatched, and the state is left
+			// untouched.
+			//
+			// Unlike `apply_extrinsic`, a nonce ahead of the next nonce of the caller is valid: the
+			// extrinsic requires the one with the previous nonce to be included first. The caller
+			// must be able to pay its fee from its current balance.
+			fn validate_transaction(
+				&mut self,
+				extrinsic: &types::Extrinsic,
+			) -> Result<crate::support::ValidTransaction, crate::support::InvalidTransaction> {
+				let len = crate::codec::Encode::encode(extrinsic).len() as u32;
+				let support::Extrinsic { signature, call } = extrinsic;
+				let weight = <Self as system::Config>::EXTRINSIC_BASE_WEIGHT
+					.saturating_add(<Self as crate::support::Dispatch>::weight(call));
+				if weight > <Self as system::Config>::MAX_BLOCK_WEIGHT
+					|| len > <Self as system::Config>::MAX_BLOCK_LENGTH
+				{
+					return Err(crate::support::InvalidTransaction::ExhaustsResources)
+				}
+				let Some(support::ExtrinsicSignature { signer, signature, nonce, tip }) = signature
+				else {
+					if !<Self as crate::support::Dispatch>::allows_unsigned(call) {
+						return Err(crate::support::InvalidTransaction::UnsignedNotAllowed)
+					}
+					// No one pays for an unsigned extrinsic, so the pool only takes those which the
+					// pallet of their call vouches for.
+					let valid = crate::support::ValidateUnsigned::validate_unsigned(self, call)?;
+					let origin = system::RawOrigin::None;
+					if crate::storage::dry_run(|| self.dispatch(origin, call.clone())).is_err() {
+						return Err(crate::support::InvalidTransaction::Call)
+					}
+					return Ok(valid)
+				};
+				let payload = crate::support::signing_payload(
+					call,
+					nonce,
+					tip,
+					&self.system.genesis_hash(),
+				);
+				if !crate::support::Verify::verify(signature, &payload, signer) {
+					return Err(crate::support::InvalidTransaction::BadProof)
+				}
+				let caller = crate::support::IdentifyAccount::into_account(signer.clone());
+				let expected = self.system.nonce(&caller);
+				if *nonce < expected {
+					return Err(crate::support::InvalidTransaction::Stale)
+				}
+				crate::storage::dry_run(|| {
+					<<Self as system::Config>::OnChargeTransaction as crate::support::OnChargeTransaction<_>>::withdraw_fee(
+						&caller,
+						weight,
+						len,
+						tip,
+					)
+				})?;
+				// Each extrinsic of the caller provides the nonce after its own, which its next
+				// extrinsic requires.
+				let tag = |nonce| crate::codec::Encode::encode(&(&caller, nonce));
+				let requires = if *nonce > expected { vec![tag(*nonce)] } else { Vec::new() };
+				let next = num::CheckedAdd::checked_add(
+					nonce,
+					&<<Self as system::Config>::Nonce as num::One>::one(),
+				)
+				.ok_or(crate::support::InvalidTransaction::NonceOverflow)?;
+				Ok(crate::support::ValidTransaction {
+					priority: <<Self as system::Config>::OnChargeTransaction as crate::support::OnChargeTransaction<_>>::priority(
+						weight,
+						tip,
+					),
+					requires,
+					provides: vec![tag(next)],
+				})
+			}
+
+			// Finish executing the current block, whose extrinsics have `extrinsics_root`, and return
+			// the resulting state root: the root of the trie holding the storage of every pallet.
+			//
+			// The `on_idle` hook of every pallet runs first, and then the `on_finalize` hook of
+			// every pallet, both in the order the pallets are declared. `on_idle` is given whatever
+			// weight is left in the block. The extrinsics root is only recorded at the end, so a
+			// block can be built without knowing up front which extrinsics it will hold.
+			fn finalize_block(&mut self, extrinsics_root: crate::crypto::Hash) -> crate::crypto::Hash {
+				let block_number = self.system.block_number();
+				let remaining_weight = <Self as system::Config>::MAX_BLOCK_WEIGHT
+					.saturating_sub(self.system.block_weight());
+				let weight = crate::support::Hooks::on_idle(
+					&mut self.system,
+					block_number,
+					remaining_weight,
+				);
+				self.system.register_weight(weight);
+				#(
+					let remaining_weight = <Self as system::Config>::MAX_BLOCK_WEIGHT
+						.saturating_sub(self.system.block_weight());
+					let weight = crate::support::Hooks::on_idle(
+						&mut self.#pallet_names,
+						block_number,
+						remaining_weight,
+					);
+					self.system.register_weight(weight);
+				)*
+				crate::support::Hooks::on_finalize(&mut self.system, block_number);
+				#(
+					crate::support::Hooks::on_finalize(&mut self.#pallet_names, block_number);
+				)*
+				self.collect_events(system::Phase::Finalization);
+				self.system.finalize(extrinsics_root);
+				crate::storage::root()
+			}
+
+			// Execute a block of extrinsics, and check that its header matches the result.
+			//
+			// The block is rejected if it does not build on the last executed block, if any of its
+			// extrinsics is invalid, or if its extrinsics or state root do not match. The whole
+			// block runs in a storage transaction, so a rejected block leaves no trace, even if
+			// some of its extrinsics were already applied.
+			fn execute_block(&mut self, block: types::Block) -> Result<(), crate::support::BlockError> {
+				crate::storage::with_transaction(|| {
+					let support::Block { header, extrinsics } = block;
+					self.initialize_block(&header)?;
+					if crate::support::extrinsics_root(&extrinsics) != header.extrinsics_root {
+						return Err(crate::support::BlockError::ExtrinsicsRootMismatch)
+					}
+					for (i, extrinsic) in extrinsics.into_iter().enumerate() {
+						let index = i as u32;
+						self.apply_extrinsic(index, extrinsic).map_err(|error| {
+							crate::support::BlockError::InvalidTransaction { index, error }
+						})?;
+					}
+					// Nothing may be written after this, so the state root stays the one the
+					// header commits to.
+					if self.finalize_block(header.extrinsics_root) != header.state_root {
+						return Err(crate::support::BlockError::StateRootMismatch)
+					}
+					Ok(())
+				})
+			}
 		}
 	};
 
@@ -51,33 +336,208 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 		//
 		// The parsed function names will be `snake_case`, and that will show up in the enum.
 		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone)]
 		pub enum RuntimeCall {
 			#( #pallet_names(#pallet_names::Call<#runtime_struct>) ),*
 		}
 
+		// The origin of the calls of every pallet in the runtime.
+		pub type RuntimeOrigin = system::OriginFor<#runtime_struct>;
+
 		impl crate::support::Dispatch for #runtime_struct {
-			type Caller = <Runtime as system::Config>::AccountId;
+			type RuntimeOrigin = RuntimeOrigin;
 			type Call = RuntimeCall;
-			// Dispatch a call on behalf of a caller. Increments the caller's nonce.
+			// Dispatch a call on behalf of an origin.
 			//
 			// Dispatch allows us to identify which underlying pallet call we want to execute.
-			// Note that we extract the `caller` from the extrinsic, and use that information
-			// to determine who we are executing the call on behalf of.
+			// Note that `apply_extrinsic` derives the `origin` from the signature of the
+			// extrinsic, and the pallet checks it is allowed to make the call.
 			fn dispatch(
 				&mut self,
-				caller: Self::Caller,
+				origin: Self::RuntimeOrigin,
 				runtime_call: Self::Call,
-			) -> crate::support::DispatchResult {
+			) -> crate::support::DispatchResultWithPostInfo {
 				// This match statement will allow us to correctly route `RuntimeCall`s
 				// to the appropriate pallet level call.
 				match runtime_call {
 					#(
 						RuntimeCall::#pallet_names(call) => {
-							self.#pallet_names.dispatch(caller, call)?;
+							self.#pallet_names.dispatch(origin, call)
 						}
 					),*
 				}
-				Ok(())
+			}
+
+			fn allows_unsigned(runtime_call: &Self::Call) -> bool {
+				match runtime_call {
+					#(
+						RuntimeCall::#pallet_names(call) => {
+							<#pallet_types as crate::support::Dispatch>::allows_unsigned(call)
+						}
+					),*
+				}
+			}
+
+			fn weight(runtime_call: &Self::Call) -> crate::support::Weight {
+				match runtime_call {
+					#(
+						RuntimeCall::#pallet_names(call) => {
+							<#pallet_types as crate::support::Dispatch>::weight(call)
+						}
+					),*
+				}
+			}
+		}
+
+		// Each unsigned call is validated by its own pallet.
+		impl crate::support::ValidateUnsigned for #runtime_struct {
+			fn validate_unsigned(
+				&self,
+				runtime_call: &Self::Call,
+			) -> Result<crate::support::ValidTransaction, crate::support::InvalidTransaction> {
+				use crate::support::ValidateUnsigned;
+				match runtime_call {
+					#(
+						RuntimeCall::#pallet_names(call) => {
+							self.#pallet_names.validate_unsigned(call)
+						}
+					),*
+				}
+			}
+		}
+
+		// A runtime call is encoded as the index of its pallet, followed by the pallet call.
+		impl crate::codec::Encode for RuntimeCall {
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				match self {
+					#(
+						RuntimeCall::#pallet_names(call) => {
+							dest.push(#pallet_indices);
+							crate::codec::Encode::encode_to(call, dest);
+						}
+					),*
+				}
+			}
+		}
+
+		// Calls can wrap other calls, so their depth is limited.
+		impl crate::codec::Decode for RuntimeCall {
+			fn decode(input: &mut &[u8]) -> Result<Self, crate::codec::Error> {
+				crate::codec::decode_nested(input, |input| {
+					match <u8 as crate::codec::Decode>::decode(input)? {
+						#(
+							#pallet_indices => Ok(RuntimeCall::#pallet_names(
+								crate::codec::Decode::decode(input)?,
+							)),
+						)*
+						_ => Err(crate::codec::Error("unknown pallet index")),
+					}
+				})
+			}
+		}
+	};
+
+	// This quote block implements the `RuntimeEvent` enum, aggregating the events of all pallets.
+	let event_impl = quote! {
+		// These are all the events which can be emitted by the runtime.
+		// Note that it is just an accumulation of the events of each pallet, including system.
+		//
+		// Events are kept in storage, so they are encoded: the index of the variant is the index
+		// of the pallet in the runtime.
+		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, crate::codec::Encode, crate::codec::Decode)]
+		pub enum RuntimeEvent {
+			system(system::Event),
+			#( #pallet_names(#pallet_names::Event<#runtime_struct>) ),*
+		}
+
+		impl From<system::Event> for RuntimeEvent {
+			fn from(event: system::Event) -> Self {
+				RuntimeEvent::system(event)
+			}
+		}
+
+		#(
+			impl From<#pallet_names::Event<#runtime_struct>> for RuntimeEvent {
+				fn from(event: #pallet_names::Event<#runtime_struct>) -> Self {
+					RuntimeEvent::#pallet_names(event)
+				}
+			}
+		)*
+	};
+
+	// This quote block implements `PalletInfo` and the `RuntimeError` enum.
+	let error_impl = quote! {
+		// Gives the index of each pallet in the runtime, following the order of the fields of the
+		// runtime struct.
+		pub struct PalletInfo;
+
+		impl crate::support::PalletInfo for PalletInfo {
+			fn index<P: 'static>() -> Option<u8> {
+				let pallet = core::any::TypeId::of::<P>();
+				if pallet == core::any::TypeId::of::<system::Pallet<#runtime_struct>>() {
+					return Some(0)
+				}
+				#(
+					if pallet == core::any::TypeId::of::<#pallet_names::Pallet<#runtime_struct>>() {
+						return Some(#pallet_indices)
+					}
+				)*
+				None
+			}
+		}
+
+		// These are all the errors which can be raised by the pallets of the runtime.
+		// Note that it is just an accumulation of the `Error` enum of each pallet.
+		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+		pub enum RuntimeError {
+			#( #pallet_names(#pallet_names::Error<#runtime_struct>) ),*
+		}
+
+		// Recover the typed error of a pallet from a `DispatchError`. Errors which were not raised
+		// by a pallet of this runtime are given back as they are.
+		impl TryFrom<crate::support::DispatchError> for RuntimeError {
+			type Error = crate::support::DispatchError;
+
+			fn try_from(error: crate::support::DispatchError) -> Result<Self, Self::Error> {
+				use crate::support::PalletError;
+				let crate::support::DispatchError::Module(module_error) = error else {
+					return Err(error)
+				};
+				match module_error.index {
+					#(
+						#pallet_indices => #pallet_names::Error::from_index(module_error.error)
+							.map(RuntimeError::#pallet_names)
+							.ok_or(error),
+					)*
+					_ => Err(error),
+				}
+			}
+		}
+	};
+
+	// This quote block implements the `RuntimeGenesisConfig` struct, aggregating the genesis config
+	// of all pallets.
+	let genesis_impl = quote! {
+		// The initial state of every pallet of the runtime, as read from a chain spec.
+		//
+		// Each field is named after its pallet. A pallet left out of the chain spec starts from its
+		// default genesis config, while a field which is not a pallet of the runtime is an error.
+		#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+		#[serde(default, deny_unknown_fields)]
+		pub struct RuntimeGenesisConfig {
+			pub system: system::GenesisConfig<#runtime_struct>,
+			#( pub #pallet_names: #pallet_names::GenesisConfig<#runtime_struct> ),*
+		}
+
+		impl crate::support::BuildGenesisConfig for RuntimeGenesisConfig {
+			// Build the genesis state of each pallet, in the order of the runtime.
+			fn build(&self) {
+				crate::support::BuildGenesisConfig::build(&self.system);
+				#(
+					crate::support::BuildGenesisConfig::build(&self.#pallet_names);
+				)*
 			}
 		}
 	};
@@ -85,6 +545,9 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 	// We combine and return all the generated code.
 	quote! {
 		#dispatch_impl
+		#event_impl
+		#error_impl
+		#genesis_impl
 		#runtime_impl
 	}
 	.into()
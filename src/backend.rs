@@ -0,0 +1,566 @@
+//! The chain as a node keeps it: the blocks it executed, and the state after the best of them.
+//!
+//! Everything is kept in a `Database`, so a node backed by a `FileDb` picks up where it left off
+//! when it is restarted.
+//!
+//! Blocks form a tree: several blocks can build on the same parent, when authors race or the
+//! network splits. `import_block` executes every block on top of the state of its parent, and
+//! keeps the longest chain as the best chain, breaking ties with the lowest hash. When a fork
+//! becomes longer than the best chain, the best chain is re-orged onto it. The states of the blocks
+//! imported since the backend was opened are kept in memory, so forks can be built on.
+//!
+//! Once the authorities agree that a block is final, `finalize_block` keeps its justification along
+//! with it, and prunes every fork which does not build on it, so it can never be reverted.
+
+use crate::codec::{decode_all, Decode, Encode};
+use crate::crypto::Hash;
+use crate::database::{Column, Database, Transaction};
+use crate::finality::Justification;
+use crate::storage::Storage;
+use crate::types::{Block, BlockNumber, Extrinsic, Header};
+use core::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io;
+
+/// The key of the hash of the best block, in `Column::Meta`.
+const BEST_BLOCK: &[u8] = b"best_block";
+/// The key of the hash of the last finalized block, in `Column::Meta`.
+const FINALIZED_BLOCK: &[u8] = b"finalized_block";
+
+/// The blocks and state of the chain, kept in the database `D`.
+#[derive(Debug)]
+pub struct Backend<D> {
+    db: D,
+    /// The state after the best block, along with the changes not yet committed to `db`.
+    state: Storage,
+    /// The states after the blocks committed or imported since the backend was opened, and after
+    /// the best block at that time. New blocks can only build on these.
+    states: BTreeMap<Hash, Storage>,
+}
+
+/// Why a block was not imported.
+#[derive(Debug)]
+pub enum ImportError<E> {
+    /// The block was already imported.
+    AlreadyImported,
+    /// The parent of the block is not known, or its state is not kept.
+    UnknownParent,
+    /// Executing the block on top of its parent failed.
+    Execution(E),
+    Database(io::Error),
+}
+
+/// Why a block was not finalized.
+#[derive(Debug)]
+pub enum FinalizeError {
+    /// The block is not known, or does not have the number which was justified. Also when the best
+    /// chain would have to be re-orged onto the block, but its state is not kept.
+    UnknownBlock,
+    /// The block is not after the last finalized block.
+    AlreadyFinalized,
+    /// The block does not build on the last finalized block.
+    ConflictsWithFinalized,
+    Database(io::Error),
+}
+
+/// What importing a block did to the best chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOutcome {
+    /// Whether the block is the new best block.
+    pub is_best: bool,
+    /// The blocks which left the best chain, from the old best block down.
+    pub retracted: Vec<Hash>,
+    /// The blocks which joined the best chain, up to the new best block.
+    pub enacted: Vec<Hash>,
+}
+
+impl<D: Database> Backend<D> {
+    /// Open the chain kept in `db`, which is empty for a new chain.
+    pub fn open(db: D) -> Self {
+        let state = Storage::from_entries(db.entries(Column::State));
+        let mut backend = Self {
+            db,
+            state,
+            states: BTreeMap::new(),
+        };
+        if let Some(best) = backend.best_header() {
+            backend.states.insert(best.hash(), backend.state.clone());
+        }
+        backend
+    }
+
+    /// The state after the best block, to execute the next block against. Changes made to it are
+    /// persisted by `commit_block`.
+    pub fn state(&mut self) -> &mut Storage {
+        &mut self.state
+    }
+
+    /// The state after the block `hash`, if it is kept, to build a block on top of it.
+    pub fn state_at(&self, hash: &Hash) -> Option<&Storage> {
+        self.states.get(hash)
+    }
+
+    /// The header of the best block, or `None` for a new chain.
+    pub fn best_header(&self) -> Option<Header> {
+        let hash = self.get(Column::Meta, BEST_BLOCK)?;
+        self.header(&hash)
+    }
+
+    /// The header of the last finalized block, or `None` if no block was finalized yet.
+    pub fn finalized_header(&self) -> Option<Header> {
+        let hash = self.get(Column::Meta, FINALIZED_BLOCK)?;
+        self.header(&hash)
+    }
+
+    /// The justification of the block `hash`, if it was finalized.
+    pub fn justification(&self, hash: &Hash) -> Option<Justification> {
+        self.get(Column::Justification, hash)
+    }
+
+    /// The hash of the block at `number` in the best chain.
+    pub fn block_hash(&self, number: BlockNumber) -> Option<Hash> {
+        self.get(Column::BlockHash, &number.encode())
+    }
+
+    pub fn header(&self, hash: &Hash) -> Option<Header> {
+        self.get(Column::Header, hash)
+    }
+
+    pub fn block(&self, hash: &Hash) -> Option<Block> {
+        let header = self.header(hash)?;
+        let extrinsics: Vec<Extrinsic> = self.get(Column::Body, hash)?;
+        Some(Block { header, extrinsics })
+    }
+
+    /// Persist `block` as the new best block, along with the changes made to the state since the
+    /// last commit, which must be the result of executing it. Everything is committed at once, so
+    /// the database never holds a block without its state, or the other way around.
+    pub fn commit_block(&mut self, block: &Block) -> io::Result<()> {
+        let hash = block.header.hash();
+        let mut transaction = Transaction::default();
+        for (key, value) in self.state.changes() {
+            match value {
+                Some(value) => transaction.set(Column::State, key.clone(), value.clone()),
+                None => transaction.remove(Column::State, key.clone()),
+            }
+        }
+        transaction.set(Column::Header, hash.to_vec(), block.header.encode());
+        transaction.set(Column::Body, hash.to_vec(), block.extrinsics.encode());
+        transaction.set(
+            Column::BlockHash,
+            block.header.block_number.encode(),
+            hash.to_vec(),
+        );
+        transaction.set(Column::Meta, BEST_BLOCK.to_vec(), hash.encode());
+        self.db.commit(transaction)?;
+        self.state.clear_changes();
+        self.states.insert(hash, self.state.clone());
+        Ok(())
+    }
+
+    /// Import `block`, which can build on any block whose state is kept: `execute` runs against a
+    /// copy of the state of its parent, like `Runtime::execute_block`. If the block makes the
+    /// longest chain, it becomes the best block, and its state is persisted along with it. See
+    /// the module docs.
+    pub fn import_block<E>(
+        &mut self,
+        block: &Block,
+        execute: impl FnOnce(Block) -> Result<(), E>,
+    ) -> Result<ImportOutcome, ImportError<E>> {
+        let hash = block.header.hash();
+        if self.header(&hash).is_some() {
+            return Err(ImportError::AlreadyImported);
+        }
+        let mut state = self
+            .state_at(&block.header.parent_hash)
+            .cloned()
+            .ok_or(ImportError::UnknownParent)?;
+        state
+            .execute_with(|| execute(block.clone()))
+            .map_err(ImportError::Execution)?;
+        state.clear_changes();
+
+        let mut transaction = Transaction::default();
+        transaction.set(Column::Header, hash.to_vec(), block.header.encode());
+        transaction.set(Column::Body, hash.to_vec(), block.extrinsics.encode());
+        let best = self
+            .best_header()
+            .expect("the parent of the block was imported; qed");
+        let best_hash = best.hash();
+        let is_best =
+            (block.header.block_number, Reverse(hash)) > (best.block_number, Reverse(best_hash));
+        let mut outcome = ImportOutcome {
+            is_best,
+            retracted: Vec::new(),
+            enacted: Vec::new(),
+        };
+        if is_best {
+            (outcome.retracted, outcome.enacted) =
+                self.set_best(&mut transaction, best_hash, &block.header, &state);
+        }
+        self.db.commit(transaction).map_err(ImportError::Database)?;
+        if is_best {
+            self.state = state.clone();
+        }
+        self.states.insert(hash, state);
+        Ok(outcome)
+    }
+
+    /// Finalize the block of `justification`, which must have been verified, along with its
+    /// ancestors. The justification is kept with the block, and every fork which does not build on
+    /// it is pruned: its blocks are removed, and so are the states of the ancestors of the block,
+    /// so no block which would revert it can be imported. If the best block was pruned, the best
+    /// chain is re-orged onto the longest chain which builds on the finalized block. Returns the
+    /// pruned blocks.
+    pub fn finalize_block(
+        &mut self,
+        justification: &Justification,
+    ) -> Result<Vec<Hash>, FinalizeError> {
+        let hash = justification.target_hash;
+        let header = self
+            .header(&hash)
+            .filter(|header| header.block_number == justification.target_number)
+            .ok_or(FinalizeError::UnknownBlock)?;
+        if let Some(finalized) = self.finalized_header() {
+            if finalized.block_number >= header.block_number {
+                return Err(FinalizeError::AlreadyFinalized);
+            }
+            if !self.is_ancestor(&finalized.hash(), &header) {
+                return Err(FinalizeError::ConflictsWithFinalized);
+            }
+        }
+
+        let mut transaction = Transaction::default();
+        let mut kept = Vec::new();
+        let mut pruned = Vec::new();
+        for kept_hash in self.states.keys() {
+            let kept_header = self
+                .header(kept_hash)
+                .expect("kept blocks are imported; qed");
+            if self.is_ancestor(&hash, &kept_header) {
+                kept.push((kept_header.block_number, *kept_hash));
+            } else if !self.is_ancestor(kept_hash, &header) {
+                transaction.remove(Column::Header, kept_hash.to_vec());
+                transaction.remove(Column::Body, kept_hash.to_vec());
+                pruned.push(*kept_hash);
+            }
+        }
+        let best = self.best_header().expect("the block was imported; qed");
+        let best_hash = best.hash();
+        let new_best = if self.is_ancestor(&hash, &best) {
+            None
+        } else {
+            let new_best = kept
+                .iter()
+                .max_by_key(|(number, hash)| (*number, Reverse(*hash)))
+                .and_then(|(_, hash)| self.header(hash))
+                .ok_or(FinalizeError::UnknownBlock)?;
+            let state = self.states[&new_best.hash()].clone();
+            self.set_best(&mut transaction, best_hash, &new_best, &state);
+            Some(state)
+        };
+        transaction.set(Column::Justification, hash.to_vec(), justification.encode());
+        transaction.set(Column::Meta, FINALIZED_BLOCK.to_vec(), hash.encode());
+        self.db
+            .commit(transaction)
+            .map_err(FinalizeError::Database)?;
+
+        if let Some(state) = new_best {
+            self.state = state;
+        }
+        self.states
+            .retain(|kept_hash, _| kept.iter().any(|(_, hash)| hash == kept_hash));
+        Ok(pruned)
+    }
+
+    /// Record in `transaction` that `header` replaces the block `best_hash` as the best block, with
+    /// `state` as the state after it: the best chain is re-orged onto it, and the state is
+    /// persisted. Returns the retracted and the enacted blocks.
+    fn set_best(
+        &self,
+        transaction: &mut Transaction,
+        best_hash: Hash,
+        header: &Header,
+        state: &Storage,
+    ) -> (Vec<Hash>, Vec<Hash>) {
+        let (retracted, enacted) = self.tree_route(best_hash, header);
+        for hash in &retracted {
+            let number = self
+                .header(hash)
+                .expect("retracted blocks are kept; qed")
+                .block_number;
+            if number > header.block_number {
+                transaction.remove(Column::BlockHash, number.encode());
+            }
+        }
+        for (i, hash) in enacted.iter().rev().enumerate() {
+            let number = header.block_number - i as BlockNumber;
+            transaction.set(Column::BlockHash, number.encode(), hash.to_vec());
+        }
+        for (key, value) in state.diff(&self.state) {
+            match value {
+                Some(value) => transaction.set(Column::State, key, value),
+                None => transaction.remove(Column::State, key),
+            }
+        }
+        transaction.set(Column::Meta, BEST_BLOCK.to_vec(), header.hash().encode());
+        (retracted, enacted)
+    }
+
+    /// Whether `header` is the block `ancestor`, or builds on it.
+    fn is_ancestor(&self, ancestor: &Hash, header: &Header) -> bool {
+        let Some(ancestor_number) = self.header(ancestor).map(|header| header.block_number) else {
+            return false;
+        };
+        let (mut hash, mut number) = (header.hash(), header.block_number);
+        while number > ancestor_number {
+            hash = self
+                .header(&hash)
+                .expect("ancestors are kept; qed")
+                .parent_hash;
+            number -= 1;
+        }
+        hash == *ancestor
+    }
+
+    /// The blocks to retract from the chain ending at `from`, and the blocks to enact to get to
+    /// `to`, down to their last common ancestor.
+    fn tree_route(&self, from: Hash, to: &Header) -> (Vec<Hash>, Vec<Hash>) {
+        let header = |hash: &Hash| self.header(hash).expect("ancestors are kept; qed");
+        let mut retracted = Vec::new();
+        let mut enacted = vec![to.hash()];
+        let (mut from, mut from_number) = (from, header(&from).block_number);
+        let (mut to, mut to_number) = (to.parent_hash, to.block_number - 1);
+        while from != to {
+            if from_number >= to_number {
+                retracted.push(from);
+                from = header(&from).parent_hash;
+                from_number -= 1;
+            }
+            if to_number > from_number {
+                enacted.push(to);
+                to = header(&to).parent_hash;
+                to_number -= 1;
+            }
+        }
+        enacted.reverse();
+        (retracted, enacted)
+    }
+
+    /// Read and decode a value written by this backend.
+    fn get<V: Decode>(&self, column: Column, key: &[u8]) -> Option<V> {
+        let value = self.db.get(column, key)?;
+        Some(decode_all(&value).expect("the backend only writes values it can read; qed"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backend, FinalizeError, ImportError};
+    use crate::block_builder::build_block;
+    use crate::chain_spec::ChainSpec;
+    use crate::crypto::{dev_seed, Hash, Pair};
+    use crate::database::MemoryDb;
+    use crate::finality::Finality;
+    use crate::support::{Digest, Extrinsic};
+    use crate::types::{Block, Nonce};
+    use crate::{balances, Runtime, RuntimeCall};
+
+    /// A backend with the development genesis block committed, and that block.
+    fn new_backend(runtime: &Runtime) -> (Backend<MemoryDb>, Block) {
+        let mut backend = Backend::open(MemoryDb::default());
+        let genesis = backend
+            .state()
+            .execute_with(|| ChainSpec::development().build_genesis(runtime));
+        backend.commit_block(&genesis).unwrap();
+        (backend, genesis)
+    }
+
+    /// A block on top of `parent`, with a transfer of 10 from `caller` to charlie.
+    fn build_on(
+        backend: &Backend<MemoryDb>,
+        runtime: &mut Runtime,
+        parent: &Hash,
+        caller: &Pair,
+        nonce: Nonce,
+    ) -> Block {
+        let mut state = backend.state_at(parent).expect("Known parent.").clone();
+        state.execute_with(|| {
+            let call = RuntimeCall::balances(balances::Call::transfer {
+                receiver: Pair::ed25519(&dev_seed("charlie")).account(),
+                amount: 10,
+            });
+            let extrinsic =
+                Extrinsic::new_signed(call, caller, nonce, &runtime.system.genesis_hash());
+            build_block(runtime, Digest::default(), vec![extrinsic])
+        })
+    }
+
+    #[test]
+    fn the_longest_fork_becomes_the_best_chain() {
+        let mut runtime = Runtime::new();
+        let (mut backend, genesis) = new_backend(&runtime);
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+
+        let import = |backend: &mut Backend<MemoryDb>, runtime: &mut Runtime, block: &Block| {
+            backend
+                .import_block(block, |block| runtime.execute_block(block))
+                .expect("Valid block.")
+        };
+
+        // Alice and bob build competing blocks on genesis. The lowest hash wins the tie.
+        let a1 = build_on(&backend, &mut runtime, &genesis.header.hash(), &alice, 0);
+        let b1 = build_on(&backend, &mut runtime, &genesis.header.hash(), &bob, 0);
+        let (a1_hash, b1_hash) = (a1.header.hash(), b1.header.hash());
+        assert!(import(&mut backend, &mut runtime, &a1).is_best);
+        assert_eq!(
+            import(&mut backend, &mut runtime, &b1).is_best,
+            b1_hash < a1_hash
+        );
+        assert!(matches!(
+            backend.import_block(&b1, |block| runtime.execute_block(block)),
+            Err(ImportError::AlreadyImported)
+        ));
+
+        // Bob's fork gets longer.
+        let b2 = build_on(&backend, &mut runtime, &b1_hash, &bob, 1);
+        let outcome = import(&mut backend, &mut runtime, &b2);
+        assert!(outcome.is_best);
+        if b1_hash < a1_hash {
+            assert_eq!(outcome.enacted, vec![b2.header.hash()]);
+        } else {
+            assert_eq!(outcome.retracted, vec![a1_hash]);
+            assert_eq!(outcome.enacted, vec![b1_hash, b2.header.hash()]);
+        }
+        assert_eq!(backend.block_hash(1), Some(b1_hash));
+        assert_eq!(backend.state().root(), b2.header.state_root);
+
+        // Then alice's fork overtakes it, and the chain re-orgs back onto it.
+        let a2 = build_on(&backend, &mut runtime, &a1_hash, &alice, 1);
+        import(&mut backend, &mut runtime, &a2);
+        let a3 = build_on(&backend, &mut runtime, &a2.header.hash(), &alice, 2);
+        let mut orphan = a3.clone();
+        orphan.header.parent_hash = [7; 32];
+        assert!(matches!(
+            backend.import_block(&orphan, |block| runtime.execute_block(block)),
+            Err(ImportError::UnknownParent)
+        ));
+        let best = backend.best_header().unwrap().hash();
+        let outcome = import(&mut backend, &mut runtime, &a3);
+        assert!(outcome.is_best);
+        if best == b2.header.hash() {
+            assert_eq!(outcome.retracted, vec![best, b1_hash]);
+            assert_eq!(
+                outcome.enacted,
+                vec![a1_hash, a2.header.hash(), a3.header.hash()]
+            );
+        } else {
+            assert_eq!(outcome.enacted, vec![a3.header.hash()]);
+        }
+
+        // The best chain and its state are persisted, and the losing fork is still kept.
+        let mut backend = Backend::open(backend.db);
+        assert_eq!(backend.best_header(), Some(a3.header.clone()));
+        assert_eq!(backend.block_hash(1), Some(a1_hash));
+        assert_eq!(backend.block_hash(3), Some(a3.header.hash()));
+        assert_eq!(backend.state().root(), a3.header.state_root);
+        assert_eq!(backend.header(&b2.header.hash()), Some(b2.header.clone()));
+        backend.state().execute_with(|| {
+            let charlie = Pair::ed25519(&dev_seed("charlie"));
+            assert_eq!(runtime.balances.balance(&charlie.account()), 30);
+            assert_eq!(runtime.system.nonce(&bob.account()), 0);
+        });
+    }
+
+    #[test]
+    fn finalized_blocks_prune_the_other_forks() {
+        let mut runtime = Runtime::new();
+        let (mut backend, genesis) = new_backend(&runtime);
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        let genesis_hash = genesis.header.hash();
+        let mut finality = Finality::new(genesis_hash, 0, vec![alice.public(), bob.public()]);
+
+        let mut justify = |block: &Block| {
+            let (hash, number) = (block.header.hash(), block.header.block_number);
+            finality.precommit(&alice, hash, number).unwrap();
+            finality.precommit(&bob, hash, number).unwrap().unwrap()
+        };
+
+        // Bob's fork is the longest, but alice's block is finalized.
+        let a1 = build_on(&backend, &mut runtime, &genesis_hash, &alice, 0);
+        let b1 = build_on(&backend, &mut runtime, &genesis_hash, &bob, 0);
+        for block in [&a1, &b1] {
+            backend
+                .import_block(block, |block| runtime.execute_block(block))
+                .unwrap();
+        }
+        let b2 = build_on(&backend, &mut runtime, &b1.header.hash(), &bob, 1);
+        backend
+            .import_block(&b2, |block| runtime.execute_block(block))
+            .unwrap();
+        let b3 = build_on(&backend, &mut runtime, &b2.header.hash(), &bob, 2);
+        let c1 = build_on(&backend, &mut runtime, &genesis_hash, &charlie, 0);
+        assert_eq!(backend.best_header(), Some(b2.header.clone()));
+
+        let a1_justification = justify(&a1);
+        let mut pruned = backend.finalize_block(&a1_justification).unwrap();
+        pruned.sort();
+        let mut expected = vec![b1.header.hash(), b2.header.hash()];
+        expected.sort();
+        assert_eq!(pruned, expected);
+        assert_eq!(backend.finalized_header(), Some(a1.header.clone()));
+
+        // The best chain is re-orged onto the finalized block, and the fork is gone.
+        assert_eq!(backend.best_header(), Some(a1.header.clone()));
+        assert_eq!(backend.block_hash(1), Some(a1.header.hash()));
+        assert_eq!(backend.block_hash(2), None);
+        assert_eq!(backend.state().root(), a1.header.state_root);
+        assert_eq!(backend.header(&b1.header.hash()), None);
+        assert!(backend.block(&b2.header.hash()).is_none());
+
+        // Nothing which would revert the finalized block can be imported or finalized.
+        for block in [&b3, &c1] {
+            assert!(matches!(
+                backend.import_block(block, |block| runtime.execute_block(block)),
+                Err(ImportError::UnknownParent)
+            ));
+        }
+        assert!(matches!(
+            backend.finalize_block(&a1_justification),
+            Err(FinalizeError::AlreadyFinalized)
+        ));
+        let mut unknown = a1_justification.clone();
+        unknown.target_hash = b1.header.hash();
+        assert!(matches!(
+            backend.finalize_block(&unknown),
+            Err(FinalizeError::UnknownBlock)
+        ));
+
+        let a2 = build_on(&backend, &mut runtime, &a1.header.hash(), &alice, 1);
+        backend
+            .import_block(&a2, |block| runtime.execute_block(block))
+            .unwrap();
+        let a2_justification = justify(&a2);
+        assert!(backend
+            .finalize_block(&a2_justification)
+            .unwrap()
+            .is_empty());
+
+        // Justifications are kept with their blocks.
+        let backend = Backend::open(backend.db);
+        assert_eq!(backend.finalized_header(), Some(a2.header.clone()));
+        assert_eq!(
+            backend.justification(&a2.header.hash()),
+            Some(a2_justification.clone())
+        );
+        assert_eq!(
+            backend.justification(&a1.header.hash()),
+            Some(a1_justification)
+        );
+        assert_eq!(backend.justification(&genesis_hash), None);
+        assert_eq!(finality.verify(&a2_justification), Ok(()));
+    }
+}
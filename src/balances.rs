@@ -1,96 +1,421 @@
-use crate::support::DispatchResult;
+use crate::codec::{Decode, Encode};
+use crate::storage::StorageMap;
+use crate::support::{BuildGenesisConfig, DispatchResult, Hooks, PalletEvents, ValidateUnsigned};
+use crate::system::{ensure_root, ensure_signed, OriginFor};
 use num::{zero, CheckedAdd, CheckedSub, Zero};
-use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 pub trait Config: crate::system::Config {
-    type Balance: Zero + CheckedSub + CheckedAdd + Copy;
+    type Balance: Zero
+        + CheckedSub
+        + CheckedAdd
+        + Copy
+        + Encode
+        + Decode
+        + Serialize
+        + DeserializeOwned;
 }
 
-#[derive(Debug)]
+/// Events emitted by the balances pallet.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    /// `amount` was transferred from `from` to `to`.
+    Transfer {
+        from: T::AccountId,
+        to: T::AccountId,
+        amount: T::Balance,
+    },
+    /// `amount` was taken out of the balance of `who`, like a fee.
+    Withdraw {
+        who: T::AccountId,
+        amount: T::Balance,
+    },
+    /// `amount` was added to the balance of `who`, like a refund.
+    Deposit {
+        who: T::AccountId,
+        amount: T::Balance,
+    },
+    /// `amount` of the balance of `who` was set aside, like a deposit.
+    Reserved {
+        who: T::AccountId,
+        amount: T::Balance,
+    },
+    /// `amount` of the reserved balance of `who` was returned to its balance.
+    Unreserved {
+        who: T::AccountId,
+        amount: T::Balance,
+    },
+    /// Root set the balance of `who` to `amount`.
+    BalanceSet {
+        who: T::AccountId,
+        amount: T::Balance,
+    },
+}
+
+/// Errors which can be raised by the balances pallet.
+#[macros::error]
+pub enum Error<T> {
+    /// The sender does not have enough funds for the transfer.
+    InsufficientBalance,
+    /// The transfer would overflow the balance of the receiver.
+    BalanceOverflow,
+    /// The account does not have that much reserved balance.
+    InsufficientReserved,
+}
+
+/// The initial state of the balances pallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    /// The accounts which hold funds from the start, and their balance.
+    pub balances: Vec<(T::AccountId, T::Balance)>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self {
+            balances: Vec::new(),
+        }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {
+        let mut pallet = Pallet::<T>::new();
+        for (who, amount) in &self.balances {
+            pallet.set_balance(who, *amount);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pallet<T: Config> {
-    balances: BTreeMap<T::AccountId, T::Balance>,
+    balances: StorageMap<T::AccountId, T::Balance>,
+    /// The balance set aside for each account, which it can not spend. See `reserve`.
+    reserved: StorageMap<T::AccountId, T::Balance>,
 }
 
 impl<T: Config> Pallet<T> {
     pub fn new() -> Self {
         Self {
-            balances: BTreeMap::new(),
+            balances: StorageMap::new("Balances", "Balances"),
+            reserved: StorageMap::new("Balances", "Reserved"),
         }
     }
 
     pub fn set_balance(&mut self, who: &T::AccountId, amount: T::Balance) {
-        self.balances.insert(who.clone(), amount);
+        self.balances.insert(who, &amount);
     }
 
     pub fn balance(&mut self, who: &T::AccountId) -> T::Balance {
-        *self.balances.get(who).unwrap_or(&zero())
+        self.balances.get(who).unwrap_or_else(zero)
+    }
+
+    /// Take `amount` out of the balance of `who`, outside of any call, for example to pay a fee.
+    pub fn withdraw(&mut self, who: &T::AccountId, amount: T::Balance) -> DispatchResult {
+        let balance = self
+            .balance(who)
+            .checked_sub(&amount)
+            .ok_or(Error::<T>::InsufficientBalance)?;
+        self.set_balance(who, balance);
+        self.deposit_event(Event::Withdraw {
+            who: who.clone(),
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Add `amount` to the balance of `who`, outside of any call, for example to refund a fee.
+    pub fn deposit(&mut self, who: &T::AccountId, amount: T::Balance) -> DispatchResult {
+        let balance = self
+            .balance(who)
+            .checked_add(&amount)
+            .ok_or(Error::<T>::BalanceOverflow)?;
+        self.set_balance(who, balance);
+        self.deposit_event(Event::Deposit {
+            who: who.clone(),
+            amount,
+        });
+        Ok(())
     }
+
+    /// The balance of `who` which is set aside, on top of its balance.
+    pub fn reserved_balance(&mut self, who: &T::AccountId) -> T::Balance {
+        self.reserved.get(who).unwrap_or_else(zero)
+    }
+
+    /// Set `amount` of the balance of `who` aside, for example to hold a deposit. It can not be
+    /// spent until it is unreserved.
+    pub fn reserve(&mut self, who: &T::AccountId, amount: T::Balance) -> DispatchResult {
+        let balance = self
+            .balance(who)
+            .checked_sub(&amount)
+            .ok_or(Error::<T>::InsufficientBalance)?;
+        let reserved = self
+            .reserved_balance(who)
+            .checked_add(&amount)
+            .ok_or(Error::<T>::BalanceOverflow)?;
+        self.set_balance(who, balance);
+        self.reserved.insert(who, &reserved);
+        self.deposit_event(Event::Reserved {
+            who: who.clone(),
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Return `amount` of the reserved balance of `who` to its balance.
+    pub fn unreserve(&mut self, who: &T::AccountId, amount: T::Balance) -> DispatchResult {
+        let reserved = self
+            .reserved_balance(who)
+            .checked_sub(&amount)
+            .ok_or(Error::<T>::InsufficientReserved)?;
+        let balance = self
+            .balance(who)
+            .checked_add(&amount)
+            .ok_or(Error::<T>::BalanceOverflow)?;
+        if reserved.is_zero() {
+            self.reserved.remove(who);
+        } else {
+            self.reserved.insert(who, &reserved);
+        }
+        self.set_balance(who, balance);
+        self.deposit_event(Event::Unreserved {
+            who: who.clone(),
+            amount,
+        });
+        Ok(())
+    }
+
+    /// The storage key of the balance of `who`, to prove it to a light client.
+    pub fn balance_key(&self, who: &T::AccountId) -> Vec<u8> {
+        self.balances.hashed_key_for(who)
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "Balances";
+    type Event = Event<T>;
 }
 
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
 
 #[macros::call]
 impl<T: Config> Pallet<T> {
+    #[weight(10_000)]
     pub fn transfer(
         &mut self,
-        caller: T::AccountId,
+        origin: OriginFor<T>,
         receiver: T::AccountId,
         amount: T::Balance,
     ) -> DispatchResult {
+        let caller = ensure_signed(origin)?;
         let mut caller_balance = self.balance(&caller);
-        let mut receiver_balance = self.balance(&receiver);
-
         caller_balance = caller_balance
             .checked_sub(&amount)
-            .ok_or("Not Enough Funds.")?;
+            .ok_or(Error::<T>::InsufficientBalance)?;
+        // Reading the receiver's balance before writing the caller's would credit it twice.
+        if caller == receiver {
+            return Ok(());
+        }
+
+        let mut receiver_balance = self.balance(&receiver);
         receiver_balance = receiver_balance
             .checked_add(&amount)
-            .ok_or("Overflow Balance.")?;
+            .ok_or(Error::<T>::BalanceOverflow)?;
 
         self.set_balance(&caller, caller_balance);
         self.set_balance(&receiver, receiver_balance);
 
+        self.deposit_event(Event::Transfer {
+            from: caller,
+            to: receiver,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Set the balance of `who` to `amount`, out of thin air. Only root can do this.
+    #[weight(5_000)]
+    pub fn force_set_balance(
+        &mut self,
+        origin: OriginFor<T>,
+        who: T::AccountId,
+        amount: T::Balance,
+    ) -> DispatchResult {
+        ensure_root(origin)?;
+        self.set_balance(&who, amount);
+        self.deposit_event(Event::BalanceSet { who, amount });
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::balances::{Config, Pallet};
+    use crate::balances::{Config, Error, Event, Pallet};
+    use crate::storage::Storage;
+    use crate::support::{DispatchError, PalletEvents};
+    use crate::system::RawOrigin;
 
+    #[derive(Debug, Clone, PartialEq)]
     struct TestConfig {}
     impl crate::system::Config for TestConfig {
         type AccountId = String;
         type BlockNumber = u32;
         type Nonce = u32;
+        type RuntimeEvent = crate::system::Event;
+        type PalletInfo = ();
+        const MAX_BLOCK_WEIGHT: crate::support::Weight = crate::support::Weight::MAX;
+        const EXTRINSIC_BASE_WEIGHT: crate::support::Weight = 0;
+        const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+        type OnChargeTransaction = ();
     }
     impl Config for TestConfig {
         type Balance = u128;
     }
     #[test]
     fn init_balance() {
-        let mut balances: Pallet<TestConfig> = Pallet::new();
+        Storage::default().execute_with(|| {
+            let mut balances: Pallet<TestConfig> = Pallet::new();
 
-        assert_eq!(balances.balance(&"alice".to_string()), 0);
+            assert_eq!(balances.balance(&"alice".to_string()), 0);
 
-        balances.set_balance(&"alice".to_string(), 100);
+            balances.set_balance(&"alice".to_string(), 100);
 
-        assert_eq!(balances.balance(&"alice".to_string()), 100);
+            assert_eq!(balances.balance(&"alice".to_string()), 100);
 
-        assert_eq!(balances.balance(&"bob".to_string()), 0);
+            assert_eq!(balances.balance(&"bob".to_string()), 0);
+        });
     }
 
     #[test]
     fn transfer_balance() {
-        let mut balances: Pallet<TestConfig> = Pallet::new();
+        Storage::default().execute_with(|| {
+            let mut balances: Pallet<TestConfig> = Pallet::new();
+
+            let alice = RawOrigin::Signed("alice".to_string());
+            let transfer_result = balances.transfer(alice.clone(), "bob".to_string(), 100);
+            assert_eq!(
+                transfer_result,
+                Err(Error::<TestConfig>::InsufficientBalance.into())
+            );
+
+            balances.set_balance(&"alice".to_string(), 100);
+            let transfer_result = balances.transfer(alice, "bob".to_string(), 55);
+            assert_eq!(transfer_result, Ok(()));
+
+            assert_eq!(balances.balance(&"alice".to_string()), 45);
+            assert_eq!(balances.balance(&"bob".to_string()), 55);
+
+            assert_eq!(
+                balances.take_events(),
+                vec![Event::Transfer {
+                    from: "alice".to_string(),
+                    to: "bob".to_string(),
+                    amount: 55
+                }]
+            );
+            assert!(balances.take_events().is_empty());
+
+            // Only signed accounts can transfer.
+            assert_eq!(
+                balances.transfer(RawOrigin::Root, "bob".to_string(), 1),
+                Err(DispatchError::BadOrigin)
+            );
+        });
+    }
+
+    #[test]
+    fn transfer_to_self_changes_nothing() {
+        Storage::default().execute_with(|| {
+            let mut balances: Pallet<TestConfig> = Pallet::new();
+            let alice = RawOrigin::Signed("alice".to_string());
+            balances.set_balance(&"alice".to_string(), 100);
+
+            assert_eq!(
+                balances.transfer(alice.clone(), "alice".to_string(), 101),
+                Err(Error::<TestConfig>::InsufficientBalance.into())
+            );
+            assert_eq!(balances.transfer(alice, "alice".to_string(), 60), Ok(()));
+            assert_eq!(balances.balance(&"alice".to_string()), 100);
+            assert!(balances.take_events().is_empty());
+        });
+    }
+
+    #[test]
+    fn reserved_balance_can_not_be_spent() {
+        Storage::default().execute_with(|| {
+            let mut balances: Pallet<TestConfig> = Pallet::new();
+            let alice = "alice".to_string();
+            balances.set_balance(&alice, 100);
 
-        let transfer_result = balances.transfer("alice".to_string(), "bob".to_string(), 100);
-        assert_eq!(transfer_result, Err("Not Enough Funds."));
+            assert_eq!(
+                balances.reserve(&alice, 101),
+                Err(Error::<TestConfig>::InsufficientBalance.into())
+            );
+            assert_eq!(balances.reserve(&alice, 60), Ok(()));
+            assert_eq!(balances.balance(&alice), 40);
+            assert_eq!(balances.reserved_balance(&alice), 60);
+            assert_eq!(
+                balances.transfer(RawOrigin::Signed(alice.clone()), "bob".to_string(), 50),
+                Err(Error::<TestConfig>::InsufficientBalance.into())
+            );
+
+            assert_eq!(
+                balances.unreserve(&alice, 61),
+                Err(Error::<TestConfig>::InsufficientReserved.into())
+            );
+            assert_eq!(balances.unreserve(&alice, 60), Ok(()));
+            assert_eq!(balances.balance(&alice), 100);
+            assert_eq!(balances.reserved_balance(&alice), 0);
+            assert_eq!(
+                balances.take_events(),
+                vec![
+                    Event::Reserved {
+                        who: alice.clone(),
+                        amount: 60
+                    },
+                    Event::Unreserved {
+                        who: alice,
+                        amount: 60
+                    },
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn only_root_can_force_set_balance() {
+        Storage::default().execute_with(|| {
+            let mut balances: Pallet<TestConfig> = Pallet::new();
+            let alice = "alice".to_string();
 
-        balances.set_balance(&"alice".to_string(), 100);
-        let transfer_result = balances.transfer("alice".to_string(), "bob".to_string(), 55);
-        assert_eq!(transfer_result, Ok(()));
+            assert_eq!(
+                balances.force_set_balance(RawOrigin::Signed(alice.clone()), alice.clone(), 100),
+                Err(DispatchError::BadOrigin)
+            );
+            assert_eq!(
+                balances.force_set_balance(RawOrigin::None, alice.clone(), 100),
+                Err(DispatchError::BadOrigin)
+            );
+            assert_eq!(balances.balance(&alice), 0);
 
-        assert_eq!(balances.balance(&"alice".to_string()), 45);
-        assert_eq!(balances.balance(&"bob".to_string()), 55);
+            assert_eq!(
+                balances.force_set_balance(RawOrigin::Root, alice.clone(), 100),
+                Ok(())
+            );
+            assert_eq!(balances.balance(&alice), 100);
+            assert_eq!(
+                balances.take_events(),
+                vec![Event::BalanceSet {
+                    who: alice,
+                    amount: 100
+                }]
+            );
+        });
     }
 }
@@ -0,0 +1,139 @@
+//! Block authoring: building the next block out of candidate extrinsics, like the ready
+//! extrinsics of the transaction pool.
+
+use crate::crypto::Hash;
+use crate::storage;
+use crate::support::{extrinsics_root, Digest, Header, InvalidTransaction};
+use crate::types::{Block, Extrinsic};
+use crate::Runtime;
+
+/// Build the next block on top of the current state, out of `extrinsics`, in order, with `digest`
+/// in its header, like the slot a consensus engine authors the block in.
+///
+/// The extrinsics are applied one by one, and those which are invalid are skipped. The block is
+/// closed at the first extrinsic which does not fit in its weight or length. The header then
+/// commits to the extrinsics which made it in, and to the resulting state, so `execute_block`
+/// accepts the block. Everything runs in a dry run, so the state itself is left untouched until
+/// the block is executed. The block can be sealed afterwards. Must run inside
+/// `Storage::execute_with`.
+pub fn build_block(
+    runtime: &mut Runtime,
+    digest: Digest,
+    extrinsics: impl IntoIterator<Item = Extrinsic>,
+) -> Block {
+    storage::dry_run(|| {
+        let mut header = Header {
+            parent_hash: runtime.system.block_hash(),
+            block_number: runtime.system.block_number() + 1,
+            state_root: Hash::default(),
+            extrinsics_root: Hash::default(),
+            digest,
+        };
+        runtime
+            .initialize_block(&header)
+            .expect("The header builds on the last executed block.");
+        let mut included = Vec::new();
+        for extrinsic in extrinsics {
+            match runtime.apply_extrinsic(included.len() as u32, extrinsic.clone()) {
+                Ok(()) => included.push(extrinsic),
+                Err(InvalidTransaction::ExhaustsResources) => break,
+                // An invalid extrinsic leaves the state untouched, so the next one can follow.
+                Err(_) => {}
+            }
+        }
+        header.extrinsics_root = extrinsics_root(&included);
+        header.state_root = runtime.finalize_block(header.extrinsics_root);
+        Block {
+            header,
+            extrinsics: included,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::build_block;
+    use crate::chain_spec::ChainSpec;
+    use crate::codec::Encode;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::storage::{self, Storage};
+    use crate::support::{Digest, Extrinsic};
+    use crate::{balances, proof_of_existence, system, types, Runtime, RuntimeCall};
+
+    #[test]
+    fn invalid_extrinsics_are_skipped() {
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+            let genesis_hash = runtime.system.genesis_hash();
+            let transfer = |caller: &Pair, nonce| {
+                Extrinsic::new_signed(
+                    RuntimeCall::balances(balances::Call::transfer {
+                        receiver: charlie.account(),
+                        amount: 10,
+                    }),
+                    caller,
+                    nonce,
+                    &genesis_hash,
+                )
+            };
+            let valid = [transfer(&alice, 0), transfer(&alice, 1)];
+            let candidates = vec![
+                valid[0].clone(),
+                // Used nonce, future nonce, and no funds for the fee.
+                transfer(&alice, 0),
+                transfer(&alice, 5),
+                transfer(&charlie, 0),
+                valid[1].clone(),
+            ];
+
+            // Building a block leaves the state untouched.
+            let root = storage::root();
+            let block = build_block(&mut runtime, Digest::default(), candidates);
+            assert_eq!(storage::root(), root);
+            assert_eq!(block.header.block_number, 1);
+            assert_eq!(block.extrinsics.encode(), valid.to_vec().encode());
+            assert_eq!(runtime.execute_block(block), Ok(()));
+            assert_eq!(runtime.balances.balance(&charlie.account()), 20);
+        });
+    }
+
+    #[test]
+    fn blocks_are_closed_at_the_length_limit() {
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+            let genesis_hash = runtime.system.genesis_hash();
+            // Sixteen claims fit in the weight of a block, but not in its length.
+            let claims = (0..16)
+                .map(|nonce| {
+                    Extrinsic::new_signed(
+                        RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                            claim: format!("{nonce:05000}"),
+                        }),
+                        &alice,
+                        nonce,
+                        &genesis_hash,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let block = build_block(&mut runtime, Digest::default(), claims.clone());
+            let included = block.extrinsics.len();
+            let length = |extrinsics: &[types::Extrinsic]| {
+                extrinsics.iter().map(|e| e.encode().len()).sum::<usize>() as u32
+            };
+            let max = <Runtime as system::Config>::MAX_BLOCK_LENGTH;
+            assert!(included < claims.len());
+            assert!(length(&claims[..included]) <= max);
+            assert!(length(&claims[..=included]) > max);
+            assert_eq!(runtime.execute_block(block), Ok(()));
+            assert_eq!(runtime.system.block_length(), length(&claims[..included]));
+        });
+    }
+}
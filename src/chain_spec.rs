@@ -0,0 +1,158 @@
+//! Chain specs: what a node needs to start a new chain, read from a JSON file.
+//!
+//! A chain spec names the chain, and holds the genesis config of every pallet of the runtime.
+//! Nodes started from the same chain spec build the same genesis block, so a network is pinned
+//! down by its chain spec, which can be kept under version control along with the code.
+
+use crate::support::BuildGenesisConfig;
+use crate::types::Block;
+use crate::{Runtime, RuntimeGenesisConfig};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// The chain spec of the development chain, built into the node.
+const DEVELOPMENT: &str = include_str!("../res/dev.json");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainSpec {
+    /// The human readable name of the chain.
+    pub name: String,
+    /// A short name for the chain, like `dev`.
+    pub id: String,
+    /// The initial state of each pallet.
+    pub genesis: RuntimeGenesisConfig,
+}
+
+impl ChainSpec {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Read the chain spec in the JSON file at `path`.
+    pub fn from_json_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(Self::from_json(&json)?)
+    }
+
+    /// The development chain, where the well known dev accounts hold funds.
+    pub fn development() -> Self {
+        Self::from_json(DEVELOPMENT).expect("the development chain spec is valid; qed")
+    }
+
+    /// Build the genesis state in the current storage, and return the genesis block, which commits
+    /// to it. Must run inside `Storage::execute_with`, on an empty storage.
+    pub fn build_genesis(&self, runtime: &Runtime) -> Block {
+        self.genesis.build();
+        Block {
+            header: runtime.system.header(),
+            extrinsics: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChainSpec;
+    use crate::block_builder::build_block;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::storage::Storage;
+    use crate::support::{Digest, Extrinsic};
+    use crate::{balances, Runtime, RuntimeCall};
+
+    #[test]
+    fn development_chain_spec() {
+        let spec = ChainSpec::development();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        assert_eq!(spec.id, "dev");
+        assert_eq!(
+            spec.genesis.balances.balances,
+            vec![(alice.account(), 1_000_000), (bob.account(), 1_000_000)]
+        );
+    }
+
+    #[test]
+    fn genesis_is_built_from_chain_spec() {
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        let json = format!(
+            r#"{{
+                "name": "Test",
+                "id": "test",
+                "genesis": {{
+                    "system": {{ "block_number": 5 }},
+                    "balances": {{ "balances": [["{:?}", 100], ["{:?}", 50]] }},
+                    "proof_of_existence": {{ "claims": [["Hello, world!", "{:?}"]] }}
+                }}
+            }}"#,
+            alice.account(),
+            bob.account(),
+            bob.account()
+        );
+        let spec = ChainSpec::from_json(&json).expect("Valid chain spec.");
+
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let genesis = state.execute_with(|| spec.build_genesis(&runtime));
+        assert_eq!(genesis.header.block_number, 5);
+        assert_eq!(genesis.header.state_root, state.root());
+
+        // The same chain spec always gives the same genesis block.
+        let mut other_state = Storage::default();
+        let other_genesis = other_state.execute_with(|| spec.build_genesis(&Runtime::new()));
+        assert_eq!(other_genesis.header.hash(), genesis.header.hash());
+
+        state.execute_with(|| {
+            assert_eq!(runtime.balances.balance(&alice.account()), 100);
+            assert_eq!(runtime.balances.balance(&bob.account()), 50);
+            assert_eq!(
+                runtime
+                    .proof_of_existence
+                    .get_claim(&"Hello, world!".to_string()),
+                Some(bob.account())
+            );
+
+            // The chain carries on from the genesis block.
+            let genesis_hash = genesis.header.hash();
+            assert_eq!(runtime.system.genesis_hash(), genesis_hash);
+            let extrinsic = Extrinsic::new_signed(
+                RuntimeCall::balances(balances::Call::transfer {
+                    receiver: bob.account(),
+                    amount: 10,
+                }),
+                &alice,
+                0,
+                &genesis_hash,
+            );
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(block.header.block_number, 6);
+            assert_eq!(block.header.parent_hash, genesis_hash);
+            assert_eq!(runtime.execute_block(block), Ok(()));
+            assert_eq!(runtime.system.genesis_hash(), genesis_hash);
+            assert_eq!(runtime.balances.balance(&bob.account()), 60);
+        });
+    }
+
+    #[test]
+    fn invalid_chain_specs_are_rejected() {
+        let spec = |genesis: &str| {
+            ChainSpec::from_json(&format!(
+                r#"{{ "name": "Test", "id": "test", "genesis": {genesis} }}"#
+            ))
+        };
+
+        // Pallets can be left out, and start from their default genesis config.
+        let empty = spec("{}").expect("Valid chain spec.");
+        assert!(empty.genesis.balances.balances.is_empty());
+        assert_eq!(empty.genesis.system.block_number, 0);
+
+        // Anything the runtime does not know about is a mistake.
+        assert!(spec(r#"{ "assets": {} }"#).is_err());
+        assert!(spec(r#"{ "balances": { "endowed": [] } }"#).is_err());
+        // Accounts are 32 bytes of hex.
+        assert!(spec(r#"{ "balances": { "balances": [["alice", 100]] } }"#).is_err());
+        assert!(spec(r#"{ "balances": { "balances": [["0x0102", 100]] } }"#).is_err());
+    }
+}
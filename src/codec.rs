@@ -0,0 +1,502 @@
+//! A compact, deterministic binary encoding, modelled after SCALE.
+//!
+//! - Fixed width integers are encoded in little endian.
+//! - Lengths of strings, vectors and maps are prefixed with their `Compact` encoding.
+//! - Enums are encoded as the index of the variant, followed by its fields.
+//! - Structs and tuples are encoded as the concatenation of their fields.
+//!
+//! The same value always encodes to the same bytes, which is what makes the encoding safe to hash
+//! and sign. Going the other way, `Decode` only accepts the one encoding of each value, so decoding
+//! and re-encoding always gives back the same bytes.
+//!
+//! `Encode` and `Decode` can be derived for structs and enums, with `#[derive(Encode, Decode)]`.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use std::collections::BTreeMap;
+
+pub use macros::{Decode, Encode};
+
+/// A type which can be encoded to bytes.
+pub trait Encode {
+    /// Append the encoding of `self` to `dest`.
+    fn encode_to(&self, dest: &mut Vec<u8>);
+
+    /// The encoding of `self`.
+    fn encode(&self) -> Vec<u8> {
+        let mut dest = Vec::new();
+        self.encode_to(&mut dest);
+        dest
+    }
+}
+
+/// The reason some bytes could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub &'static str);
+
+/// A type which can be decoded from bytes.
+pub trait Decode: Sized {
+    /// Decode a value from the start of `input`, and move `input` past it.
+    fn decode(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+/// Decode a value which takes up the whole of `input`.
+pub fn decode_all<T: Decode>(mut input: &[u8]) -> Result<T, Error> {
+    let value = T::decode(&mut input)?;
+    if !input.is_empty() {
+        return Err(Error("input is longer than the value"));
+    }
+    Ok(value)
+}
+
+/// How deep `decode_nested` can go.
+pub const MAX_DEPTH: u32 = 64;
+
+thread_local! {
+    /// How many calls to `decode_nested` are in progress on this thread, one inside the other.
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Decode a value of a type which can contain itself, like a call which wraps other calls, with
+/// `decode`. Nesting such values more than `MAX_DEPTH` deep is an error, rather than a stack
+/// overflow on untrusted input.
+pub fn decode_nested<T>(
+    input: &mut &[u8],
+    decode: impl FnOnce(&mut &[u8]) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let depth = DEPTH.get();
+    if depth >= MAX_DEPTH {
+        return Err(Error("value is nested too deeply"));
+    }
+    DEPTH.set(depth + 1);
+    let result = decode(input);
+    DEPTH.set(depth);
+    result
+}
+
+/// Take the first `len` bytes of `input`.
+fn read<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        return Err(Error("input is too short"));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+/// Decode a compact length prefix.
+fn decode_len(input: &mut &[u8]) -> Result<usize, Error> {
+    usize::try_from(Compact::decode(input)?.0).map_err(|_| Error("length is too large"))
+}
+
+/// An integer in compact encoding: the smaller the value, the fewer bytes it takes.
+///
+/// The two lowest bits of the first byte give the mode:
+/// - `0b00`: the value fits in the upper 6 bits of a single byte.
+/// - `0b01`: the value fits in the upper 14 bits of two bytes.
+/// - `0b10`: the value fits in the upper 30 bits of four bytes.
+/// - `0b11`: the upper 6 bits of the first byte are the number of bytes which follow, minus 4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compact(pub u128);
+
+impl Encode for Compact {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        let value = self.0;
+        match value {
+            0..=0x3f => dest.push((value as u8) << 2),
+            0x40..=0x3fff => dest.extend_from_slice(&(((value as u16) << 2) | 0b01).to_le_bytes()),
+            0x4000..=0x3fff_ffff => {
+                dest.extend_from_slice(&(((value as u32) << 2) | 0b10).to_le_bytes())
+            }
+            _ => {
+                let bytes = value.to_le_bytes();
+                let len = bytes.len() - bytes.iter().rev().take_while(|b| **b == 0).count();
+                dest.push((((len - 4) as u8) << 2) | 0b11);
+                dest.extend_from_slice(&bytes[..len]);
+            }
+        }
+    }
+}
+
+impl Decode for Compact {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let first = read(input, 1)?[0];
+        // Values must use the smallest mode they fit in, so that each value has a single encoding.
+        let (value, min) = match first & 0b11 {
+            0b00 => return Ok(Compact((first >> 2) as u128)),
+            0b01 => {
+                let value = u16::from_le_bytes([first, read(input, 1)?[0]]) >> 2;
+                (value as u128, 0x40)
+            }
+            0b10 => {
+                let mut bytes = [first, 0, 0, 0];
+                bytes[1..].copy_from_slice(read(input, 3)?);
+                ((u32::from_le_bytes(bytes) >> 2) as u128, 0x4000)
+            }
+            _ => {
+                let len = (first >> 2) as usize + 4;
+                if len > 16 {
+                    return Err(Error("compact integer is too large"));
+                }
+                let mut bytes = [0; 16];
+                bytes[..len].copy_from_slice(read(input, len)?);
+                if bytes[len - 1] == 0 {
+                    return Err(Error("compact integer is not in its shortest form"));
+                }
+                (u128::from_le_bytes(bytes), 0x4000_0000)
+            }
+        };
+        if value < min {
+            return Err(Error("compact integer is not in its shortest form"));
+        }
+        Ok(Compact(value))
+    }
+}
+
+macro_rules! impl_encode_for_int {
+    ( $( $int:ty ),* ) => {
+        $(
+            impl Encode for $int {
+                fn encode_to(&self, dest: &mut Vec<u8>) {
+                    dest.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $int {
+                fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+                    let bytes = read(input, std::mem::size_of::<$int>())?;
+                    Ok(<$int>::from_le_bytes(bytes.try_into().expect("read the size of the int; qed")))
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Encode for bool {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error("invalid bool")),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode_to(&self, _dest: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_input: &mut &[u8]) -> Result<Self, Error> {
+        Ok(())
+    }
+}
+
+/// Lets generic types which do not hold a `T` be encoded, like the events of some pallets.
+impl<T> Encode for PhantomData<T> {
+    fn encode_to(&self, _dest: &mut Vec<u8>) {}
+}
+
+impl<T> Decode for PhantomData<T> {
+    fn decode(_input: &mut &[u8]) -> Result<Self, Error> {
+        Ok(PhantomData)
+    }
+}
+
+/// A value which can not exist, paired with a `PhantomData` in variants which can never be
+/// constructed.
+impl Encode for Infallible {
+    fn encode_to(&self, _dest: &mut Vec<u8>) {
+        match *self {}
+    }
+}
+
+impl Decode for Infallible {
+    fn decode(_input: &mut &[u8]) -> Result<Self, Error> {
+        Err(Error("invalid infallible"))
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        (**self).encode_to(dest);
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        (**self).encode_to(dest);
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        T::decode(input).map(Box::new)
+    }
+}
+
+impl Encode for str {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_bytes().encode_to(dest);
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_str().encode_to(dest);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        String::from_utf8(Vec::decode(input)?).map_err(|_| Error("invalid utf-8"))
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u128).encode_to(dest);
+        for item in self {
+            item.encode_to(dest);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_slice().encode_to(dest);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let len = decode_len(input)?;
+        // Every item takes at least one byte, bar zero sized ones, so a length larger than the
+        // input can not be trusted to size the allocation.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Arrays have a fixed length, so it is not part of the encoding.
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        for item in self {
+            item.encode_to(dest);
+        }
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let items = (0..N)
+            .map(|_| T::decode(input))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("decoded N items; qed")))
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            None => dest.push(0),
+            Some(value) => {
+                dest.push(1);
+                value.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => T::decode(input).map(Some),
+            _ => Err(Error("invalid option")),
+        }
+    }
+}
+
+impl<T: Encode, E: Encode> Encode for Result<T, E> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            Ok(value) => {
+                dest.push(0);
+                value.encode_to(dest);
+            }
+            Err(error) => {
+                dest.push(1);
+                error.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<T: Decode, E: Decode> Decode for Result<T, E> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => T::decode(input).map(Ok),
+            1 => E::decode(input).map(Err),
+            _ => Err(Error("invalid result")),
+        }
+    }
+}
+
+/// Maps are encoded as a vector of `(key, value)` pairs, sorted by key.
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u128).encode_to(dest);
+        for (key, value) in self {
+            key.encode_to(dest);
+            value.encode_to(dest);
+        }
+    }
+}
+
+/// The keys must be sorted and unique, as they are when encoded.
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let len = decode_len(input)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::decode(input)?;
+            if map.last_key_value().is_some_and(|(last, _)| *last >= key) {
+                return Err(Error("map keys are not sorted"));
+            }
+            let value = V::decode(input)?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! impl_encode_for_tuple {
+    ( $( ( $( $name:ident ),+ ) ),* ) => {
+        $(
+            impl<$( $name: Encode ),+> Encode for ( $( $name, )+ ) {
+                #[allow(non_snake_case)]
+                fn encode_to(&self, dest: &mut Vec<u8>) {
+                    let ( $( $name, )+ ) = self;
+                    $( $name.encode_to(dest); )+
+                }
+            }
+
+            impl<$( $name: Decode ),+> Decode for ( $( $name, )+ ) {
+                fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+                    Ok(( $( $name::decode(input)?, )+ ))
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_for_tuple!((A), (A, B), (A, B, C), (A, B, C, D));
+
+#[cfg(test)]
+mod test {
+    use super::{decode_all, Compact, Decode, Encode, Error};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn encode_compact() {
+        assert_eq!(Compact(0).encode(), vec![0x00]);
+        assert_eq!(Compact(1).encode(), vec![0x04]);
+        assert_eq!(Compact(63).encode(), vec![0xfc]);
+        assert_eq!(Compact(64).encode(), vec![0x01, 0x01]);
+        assert_eq!(Compact(16383).encode(), vec![0xfd, 0xff]);
+        assert_eq!(Compact(16384).encode(), vec![0x02, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            Compact(1 << 30).encode(),
+            vec![0x03, 0x00, 0x00, 0x00, 0x40]
+        );
+        assert_eq!(
+            Compact(u64::MAX as u128).encode(),
+            vec![0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn encode_values() {
+        assert_eq!(1u32.encode(), vec![1, 0, 0, 0]);
+        assert_eq!(true.encode(), vec![1]);
+        assert_eq!("ab".encode(), vec![0x08, b'a', b'b']);
+        assert_eq!(vec![1u16, 2].encode(), vec![0x08, 1, 0, 2, 0]);
+        assert_eq!([1u8, 2].encode(), vec![1, 2]);
+        assert_eq!(Some(1u8).encode(), vec![1, 1]);
+        assert_eq!(None::<u8>.encode(), vec![0]);
+        assert_eq!(Ok::<u8, u16>(1).encode(), vec![0, 1]);
+        assert_eq!(Err::<u8, u16>(1).encode(), vec![1, 1, 0]);
+        assert_eq!((1u8, 2u16).encode(), vec![1, 2, 0]);
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    enum Shape {
+        Point,
+        Circle { radius: u32 },
+        Polygon(Vec<(i16, i16)>),
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Drawing<T> {
+        name: String,
+        shapes: Vec<Shape>,
+        layers: BTreeMap<u8, T>,
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        for value in [
+            0,
+            1,
+            63,
+            64,
+            16383,
+            16384,
+            1 << 30,
+            u64::MAX as u128,
+            u128::MAX,
+        ] {
+            assert_eq!(decode_all(&Compact(value).encode()), Ok(Compact(value)));
+        }
+
+        let drawing = Drawing {
+            name: "shapes".to_string(),
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle { radius: 5 },
+                Shape::Polygon(vec![(0, 0), (-1, 2)]),
+            ],
+            layers: BTreeMap::from([(0, Some(true)), (3, None)]),
+        };
+        assert_eq!(Shape::Circle { radius: 5 }.encode(), vec![1, 5, 0, 0, 0]);
+        assert_eq!(decode_all(&drawing.encode()), Ok(drawing));
+    }
+
+    #[test]
+    fn decode_rejects_non_canonical_input() {
+        // 1 in two bytes, where one would do.
+        assert!(Compact::decode(&mut &[0x05, 0x00][..]).is_err());
+        assert_eq!(bool::decode(&mut &[2][..]), Err(Error("invalid bool")));
+        assert!(Shape::decode(&mut &[3][..]).is_err());
+        assert!(Result::<u8, u8>::decode(&mut &[2, 0][..]).is_err());
+        // The keys 1 and 0, out of order.
+        assert!(BTreeMap::<u8, u8>::decode(&mut &[0x08, 1, 1, 0, 0][..]).is_err());
+        assert!(u32::decode(&mut &[1, 0][..]).is_err());
+        assert!(decode_all::<u8>(&[1, 0]).is_err());
+    }
+}
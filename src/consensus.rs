@@ -0,0 +1,338 @@
+//! Proof-of-Authority consensus: which node may author each block.
+//!
+//! Time is split into slots of `slot_duration` milliseconds, counted from the unix epoch. A fixed
+//! set of authorities take turns round robin, so the author of slot `n` is the authority at index
+//! `n % len`. The author puts the slot in a pre-runtime digest item of the header, builds the block,
+//! and seals it with its signature over the hash of the header. Nodes only import a block whose
+//! slot comes after the slot of its parent, and which is sealed by the author of that slot. A block
+//! whose slot has not started yet by the clock of the node is rejected, allowing for `MAX_DRIFT`
+//! slots of difference between the clocks of the nodes. So is a block which sets its time, with
+//! `timestamp::set`, more than `MAX_DRIFT` slots away from its slot.
+
+use crate::block_builder::build_block;
+use crate::codec::{decode_all, Encode};
+use crate::crypto::{MultiSignature, MultiSigner, Pair};
+use crate::support::{BlockError, ConsensusEngineId, Digest, DigestItem, Verify};
+use crate::timestamp;
+use crate::types::{Block, Extrinsic, Header};
+use crate::{Runtime, RuntimeCall};
+
+/// Tags the digest items of this engine.
+pub const ENGINE_ID: ConsensusEngineId = *b"poa_";
+
+pub type Slot = u64;
+
+/// How many slots ahead of the local clock the slot of an imported block may be.
+pub const MAX_DRIFT: Slot = 1;
+
+/// Why a block was not imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusError {
+    /// The header does not say which slot the block was authored in.
+    MissingSlot,
+    /// The slot of the block is not after the slot of its parent.
+    SlotNotIncreasing,
+    /// The slot of the block is more than `MAX_DRIFT` slots ahead of the local clock.
+    SlotInFuture,
+    /// The time the block sets is more than `MAX_DRIFT` slots away from the slot of the block.
+    TimestampOutOfSlot,
+    /// The header is not sealed.
+    MissingSeal,
+    /// The seal is not a signature of the header by the author of the slot.
+    BadSeal,
+    /// The runtime rejected the block.
+    Block(BlockError),
+}
+
+impl From<BlockError> for ConsensusError {
+    fn from(error: BlockError) -> Self {
+        Self::Block(error)
+    }
+}
+
+/// The authority set and the slot duration of a chain. See the module docs.
+#[derive(Debug, Clone)]
+pub struct Poa {
+    authorities: Vec<MultiSigner>,
+    /// In milliseconds.
+    slot_duration: u64,
+}
+
+impl Poa {
+    pub fn new(authorities: Vec<MultiSigner>, slot_duration: u64) -> Self {
+        assert!(!authorities.is_empty(), "There must be an authority.");
+        assert!(slot_duration > 0, "Slots can not be empty.");
+        Self {
+            authorities,
+            slot_duration,
+        }
+    }
+
+    /// The slot at `timestamp`, in milliseconds since the unix epoch.
+    pub fn slot_at(&self, timestamp: u64) -> Slot {
+        timestamp / self.slot_duration
+    }
+
+    /// The authority which may author the block of `slot`.
+    pub fn author(&self, slot: Slot) -> &MultiSigner {
+        &self.authorities[(slot % self.authorities.len() as u64) as usize]
+    }
+
+    /// The slot `header` was authored in.
+    pub fn slot(header: &Header) -> Result<Slot, ConsensusError> {
+        let slot = header
+            .digest
+            .pre_runtime(ENGINE_ID)
+            .ok_or(ConsensusError::MissingSlot)?;
+        decode_all(slot).map_err(|_| ConsensusError::MissingSlot)
+    }
+
+    /// The slot of the last executed block, or `None` if it is the genesis block. Must run inside
+    /// `Storage::execute_with`.
+    pub fn best_slot(runtime: &Runtime) -> Option<Slot> {
+        Self::slot(&runtime.system.header()).ok()
+    }
+
+    /// Author the block of `slot` out of `extrinsics` with the key of `pair`, on top of the current
+    /// state, and seal it. `None` if `pair` is not the author of `slot`, or if `slot` is not after
+    /// the slot of the last block. Must run inside `Storage::execute_with`.
+    pub fn propose(
+        &self,
+        runtime: &mut Runtime,
+        pair: &Pair,
+        slot: Slot,
+        extrinsics: impl IntoIterator<Item = Extrinsic>,
+    ) -> Option<Block> {
+        if pair.public() != *self.author(slot)
+            || Self::best_slot(runtime).is_some_and(|best| slot <= best)
+        {
+            return None;
+        }
+        let digest = Digest {
+            logs: vec![DigestItem::PreRuntime(ENGINE_ID, slot.encode())],
+        };
+        let mut block = build_block(runtime, digest, extrinsics);
+        let seal = pair.sign(&block.header.hash());
+        block
+            .header
+            .digest
+            .logs
+            .push(DigestItem::Seal(ENGINE_ID, seal.encode()));
+        Some(block)
+    }
+
+    /// Check that `block` comes in a later slot than its parent, that its slot has started by
+    /// `now`, in milliseconds since the unix epoch, that the time it sets is in its slot, and that
+    /// it is sealed by the author of its slot, and execute it. Must run inside
+    /// `Storage::execute_with`.
+    pub fn import_block(
+        &self,
+        runtime: &mut Runtime,
+        block: Block,
+        now: u64,
+    ) -> Result<(), ConsensusError> {
+        let slot = Self::slot(&block.header)?;
+        if Self::best_slot(runtime).is_some_and(|best| slot <= best) {
+            return Err(ConsensusError::SlotNotIncreasing);
+        }
+        if slot > self.slot_at(now).saturating_add(MAX_DRIFT) {
+            return Err(ConsensusError::SlotInFuture);
+        }
+        // Otherwise the author could move the time of the chain anywhere after the time of the
+        // last block, even to its very end.
+        for extrinsic in &block.extrinsics {
+            if let RuntimeCall::timestamp(timestamp::Call::set { now: time }) = &extrinsic.call {
+                if self.slot_at(*time).abs_diff(slot) > MAX_DRIFT {
+                    return Err(ConsensusError::TimestampOutOfSlot);
+                }
+            }
+        }
+        let seal = block
+            .header
+            .digest
+            .seal(ENGINE_ID)
+            .ok_or(ConsensusError::MissingSeal)?;
+        let seal: MultiSignature = decode_all(seal).map_err(|_| ConsensusError::BadSeal)?;
+        if !seal.verify(&block.header.hash(), self.author(slot)) {
+            return Err(ConsensusError::BadSeal);
+        }
+        Ok(runtime.execute_block(block)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConsensusError, Poa, ENGINE_ID, MAX_DRIFT};
+    use crate::block_builder::build_block;
+    use crate::chain_spec::ChainSpec;
+    use crate::codec::Encode;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::storage::Storage;
+    use crate::support::{BlockError, Digest, DigestItem, Extrinsic};
+    use crate::timestamp;
+    use crate::types::Block;
+    use crate::{Runtime, RuntimeCall};
+
+    /// The start of slot 10, so blocks of later slots can be imported.
+    const NOW: u64 = 60_000;
+
+    fn authorities() -> (Pair, Pair, Poa) {
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        let poa = Poa::new(vec![alice.public(), bob.public()], 6_000);
+        (alice, bob, poa)
+    }
+
+    /// Replace the seal of `block` with one by `pair`.
+    fn reseal(block: &mut Block, pair: &Pair) {
+        let digest = &mut block.header.digest;
+        *digest = digest.without_seals();
+        let seal = pair.sign(&block.header.hash());
+        block
+            .header
+            .digest
+            .logs
+            .push(DigestItem::Seal(ENGINE_ID, seal.encode()));
+    }
+
+    #[test]
+    fn authorities_take_turns() {
+        let (alice, bob, poa) = authorities();
+        assert_eq!(poa.slot_at(0), 0);
+        assert_eq!(poa.slot_at(5_999), 0);
+        assert_eq!(poa.slot_at(6_000), 1);
+        assert_eq!(*poa.author(0), alice.public());
+        assert_eq!(*poa.author(1), bob.public());
+        assert_eq!(*poa.author(2), alice.public());
+    }
+
+    #[test]
+    fn sealed_blocks_are_imported() {
+        let (alice, bob, poa) = authorities();
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+            assert_eq!(Poa::best_slot(&runtime), None);
+
+            // Only the author of a slot can propose its block.
+            assert!(poa.propose(&mut runtime, &alice, 3, vec![]).is_none());
+            let block = poa.propose(&mut runtime, &bob, 3, vec![]).unwrap();
+            assert_eq!(Poa::slot(&block.header), Ok(3));
+            let hash = block.header.hash();
+            assert_eq!(poa.import_block(&mut runtime, block, NOW), Ok(()));
+            assert_eq!(runtime.system.block_hash(), hash);
+            assert_eq!(Poa::best_slot(&runtime), Some(3));
+
+            // Slots only go forward.
+            assert!(poa.propose(&mut runtime, &bob, 3, vec![]).is_none());
+            let block = poa.propose(&mut runtime, &alice, 4, vec![]).unwrap();
+            assert_eq!(poa.import_block(&mut runtime, block, NOW), Ok(()));
+            assert_eq!(runtime.system.block_number(), 2);
+        });
+    }
+
+    #[test]
+    fn blocks_must_be_sealed_by_the_author_of_their_slot() {
+        let (alice, bob, poa) = authorities();
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+            let block = poa.propose(&mut runtime, &alice, 2, vec![]).unwrap();
+
+            let unsealed = build_block(&mut runtime, Digest::default(), vec![]);
+            assert_eq!(
+                poa.import_block(&mut runtime, unsealed, NOW),
+                Err(ConsensusError::MissingSlot)
+            );
+
+            let mut unsealed = block.clone();
+            unsealed.header.digest = unsealed.header.digest.without_seals();
+            assert_eq!(
+                poa.import_block(&mut runtime, unsealed, NOW),
+                Err(ConsensusError::MissingSeal)
+            );
+
+            // Bob seals the block of a slot which belongs to alice.
+            let mut forged = block.clone();
+            reseal(&mut forged, &bob);
+            assert_eq!(
+                poa.import_block(&mut runtime, forged, NOW),
+                Err(ConsensusError::BadSeal)
+            );
+
+            // The seal covers the whole header, slot included.
+            let mut moved = block.clone();
+            moved.header.digest.logs[0] = DigestItem::PreRuntime(ENGINE_ID, 4u64.encode());
+            assert_eq!(
+                poa.import_block(&mut runtime, moved, NOW),
+                Err(ConsensusError::BadSeal)
+            );
+
+            // A valid seal does not save a block the runtime rejects.
+            let mut wrong_state = block.clone();
+            wrong_state.header.state_root = Default::default();
+            reseal(&mut wrong_state, &alice);
+            assert_eq!(
+                poa.import_block(&mut runtime, wrong_state, NOW),
+                Err(ConsensusError::Block(BlockError::StateRootMismatch))
+            );
+
+            assert_eq!(poa.import_block(&mut runtime, block, NOW), Ok(()));
+        });
+    }
+
+    #[test]
+    fn blocks_from_the_future_are_rejected() {
+        let (alice, bob, poa) = authorities();
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+
+            // Within the drift, the block is imported.
+            let slot = poa.slot_at(NOW) + MAX_DRIFT;
+            let block = poa.propose(&mut runtime, &bob, slot, vec![]).unwrap();
+            assert_eq!(poa.import_block(&mut runtime, block, NOW), Ok(()));
+
+            let block = poa.propose(&mut runtime, &alice, slot + 1, vec![]).unwrap();
+            assert_eq!(
+                poa.import_block(&mut runtime, block.clone(), NOW),
+                Err(ConsensusError::SlotInFuture)
+            );
+            // Once the clock catches up, it is imported too.
+            assert_eq!(poa.import_block(&mut runtime, block, NOW + 6_000), Ok(()));
+        });
+    }
+    #[test]
+    fn blocks_must_set_a_time_in_their_slot() {
+        let (alice, _, poa) = authorities();
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let set_time =
+            |now| Extrinsic::new_unsigned(RuntimeCall::timestamp(timestamp::Call::set { now }));
+        state.execute_with(|| {
+            ChainSpec::development().build_genesis(&runtime);
+            let slot = poa.slot_at(NOW);
+
+            // The end of time would freeze the chain, as its time could never go forward again.
+            for now in [u64::MAX, NOW + 6_000 * (MAX_DRIFT + 1)] {
+                let block = poa
+                    .propose(&mut runtime, &alice, slot, vec![set_time(now)])
+                    .unwrap();
+                assert_eq!(block.extrinsics.len(), 1);
+                assert_eq!(
+                    poa.import_block(&mut runtime, block, NOW),
+                    Err(ConsensusError::TimestampOutOfSlot)
+                );
+            }
+
+            let block = poa
+                .propose(&mut runtime, &alice, slot, vec![set_time(NOW + 1)])
+                .unwrap();
+            assert_eq!(poa.import_block(&mut runtime, block, NOW), Ok(()));
+            assert_eq!(runtime.timestamp.now(), NOW + 1);
+        });
+    }
+}
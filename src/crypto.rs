@@ -0,0 +1,242 @@
+//! Hashing, keys and signatures used by the runtime.
+//!
+//! Accounts are identified by a 32 byte public key, which can be either an ed25519 or an sr25519
+//! key. The signature of an extrinsic says which scheme it uses.
+
+use crate::codec::{Decode, Encode, Error};
+use crate::support::{IdentifyAccount, Verify};
+use blake2::digest::consts::{U16, U32};
+use blake2::{Blake2b, Digest};
+use core::fmt;
+
+/// The output of `blake2_256`.
+pub type Hash = [u8; 32];
+
+/// The context sr25519 signatures are made in.
+const SR25519_SIGNING_CONTEXT: &[u8] = b"substrate";
+
+/// Hash `data` with BLAKE2b, to a 256 bit output.
+pub fn blake2_256(data: &[u8]) -> Hash {
+    Blake2b::<U32>::digest(data).into()
+}
+
+/// Hash `data` with BLAKE2b, to a 128 bit output.
+pub fn blake2_128(data: &[u8]) -> [u8; 16] {
+    Blake2b::<U16>::digest(data).into()
+}
+
+/// Write `bytes` as `0x`-prefixed lowercase hex.
+pub fn fmt_hex(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "0x")?;
+    for byte in bytes {
+        write!(f, "{byte:02x}")?;
+    }
+    Ok(())
+}
+
+/// Read `0x`-prefixed hex, as written by `fmt_hex`.
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.strip_prefix("0x")?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// An account, identified by the public key which controls it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId32(pub [u8; 32]);
+
+impl fmt::Debug for AccountId32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(&self.0, f)
+    }
+}
+
+/// Accounts which no key controls, like multisig accounts, are derived from a hash.
+impl From<Hash> for AccountId32 {
+    fn from(hash: Hash) -> Self {
+        Self(hash)
+    }
+}
+
+/// Accounts are written as hex in chain specs, the same way `Debug` shows them.
+impl serde::Serialize for AccountId32 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{self:?}"))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AccountId32 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        parse_hex(&hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(AccountId32)
+            .ok_or_else(|| {
+                serde::de::Error::custom("expected a 32 byte account, as 0x-prefixed hex")
+            })
+    }
+}
+
+impl Encode for AccountId32 {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.0.encode_to(dest);
+    }
+}
+
+impl Decode for AccountId32 {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        Decode::decode(input).map(AccountId32)
+    }
+}
+
+/// The public key of the signer of an extrinsic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum MultiSigner {
+    Ed25519([u8; 32]),
+    Sr25519([u8; 32]),
+}
+
+/// The account of a signer is its public key, whatever the scheme.
+impl IdentifyAccount for MultiSigner {
+    type AccountId = AccountId32;
+
+    fn into_account(self) -> AccountId32 {
+        match self {
+            MultiSigner::Ed25519(public) | MultiSigner::Sr25519(public) => AccountId32(public),
+        }
+    }
+}
+
+/// A signature made with one of the supported schemes.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum MultiSignature {
+    Ed25519([u8; 64]),
+    Sr25519([u8; 64]),
+}
+
+impl fmt::Debug for MultiSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (scheme, signature) = match self {
+            MultiSignature::Ed25519(signature) => ("Ed25519", signature),
+            MultiSignature::Sr25519(signature) => ("Sr25519", signature),
+        };
+        write!(f, "{scheme}(")?;
+        fmt_hex(signature, f)?;
+        write!(f, ")")
+    }
+}
+
+impl Verify for MultiSignature {
+    type Signer = MultiSigner;
+
+    fn verify(&self, message: &[u8], signer: &MultiSigner) -> bool {
+        match (self, signer) {
+            (MultiSignature::Ed25519(signature), MultiSigner::Ed25519(public)) => {
+                let Ok(public) = ed25519_dalek::VerifyingKey::from_bytes(public) else {
+                    return false;
+                };
+                let signature = ed25519_dalek::Signature::from_bytes(signature);
+                public.verify_strict(message, &signature).is_ok()
+            }
+            (MultiSignature::Sr25519(signature), MultiSigner::Sr25519(public)) => {
+                let Ok(public) = schnorrkel::PublicKey::from_bytes(public) else {
+                    return false;
+                };
+                let Ok(signature) = schnorrkel::Signature::from_bytes(signature) else {
+                    return false;
+                };
+                public
+                    .verify_simple(SR25519_SIGNING_CONTEXT, message, &signature)
+                    .is_ok()
+            }
+            // The signature was made with a different scheme than the key.
+            _ => false,
+        }
+    }
+}
+
+/// A key pair which can sign extrinsics.
+#[derive(Clone)]
+pub enum Pair {
+    Ed25519(ed25519_dalek::SigningKey),
+    Sr25519(schnorrkel::Keypair),
+}
+
+impl Pair {
+    /// The ed25519 key pair for `seed`.
+    pub fn ed25519(seed: &[u8; 32]) -> Self {
+        Pair::Ed25519(ed25519_dalek::SigningKey::from_bytes(seed))
+    }
+
+    /// The sr25519 key pair for `seed`.
+    pub fn sr25519(seed: &[u8; 32]) -> Self {
+        let secret = schnorrkel::MiniSecretKey::from_bytes(seed).expect("seed is 32 bytes; qed");
+        Pair::Sr25519(secret.expand_to_keypair(schnorrkel::ExpansionMode::Ed25519))
+    }
+
+    /// The public key of this pair.
+    pub fn public(&self) -> MultiSigner {
+        match self {
+            Pair::Ed25519(pair) => MultiSigner::Ed25519(pair.verifying_key().to_bytes()),
+            Pair::Sr25519(pair) => MultiSigner::Sr25519(pair.public.to_bytes()),
+        }
+    }
+
+    /// The account controlled by this pair.
+    pub fn account(&self) -> AccountId32 {
+        self.public().into_account()
+    }
+
+    /// Sign `message` with this pair.
+    pub fn sign(&self, message: &[u8]) -> MultiSignature {
+        match self {
+            Pair::Ed25519(pair) => {
+                use ed25519_dalek::Signer;
+                MultiSignature::Ed25519(pair.sign(message).to_bytes())
+            }
+            Pair::Sr25519(pair) => MultiSignature::Sr25519(
+                pair.sign_simple(SR25519_SIGNING_CONTEXT, message)
+                    .to_bytes(),
+            ),
+        }
+    }
+}
+
+/// A well known seed derived from `name`, for development and tests. Keys made from it are public
+/// knowledge, and must never hold real funds.
+pub fn dev_seed(name: &str) -> [u8; 32] {
+    blake2_256(format!("//{name}").as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dev_seed, Pair};
+    use crate::support::Verify;
+
+    #[test]
+    fn sign_and_verify() {
+        for pair in [
+            Pair::ed25519(&dev_seed("alice")),
+            Pair::sr25519(&dev_seed("alice")),
+        ] {
+            let signature = pair.sign(b"hello");
+            assert!(signature.verify(b"hello", &pair.public()));
+            assert!(!signature.verify(b"goodbye", &pair.public()));
+        }
+    }
+
+    #[test]
+    fn signature_must_match_key_scheme() {
+        let ed25519 = Pair::ed25519(&dev_seed("alice"));
+        let sr25519 = Pair::sr25519(&dev_seed("alice"));
+
+        assert_ne!(ed25519.account(), sr25519.account());
+        assert!(!ed25519.sign(b"hello").verify(b"hello", &sr25519.public()));
+        assert!(!sr25519.sign(b"hello").verify(b"hello", &ed25519.public()));
+    }
+}
@@ -0,0 +1,257 @@
+//! Key-value databases a node keeps the chain in.
+//!
+//! The node only talks to the `Database` trait, so the backend can be swapped: `MemoryDb` keeps
+//! everything in memory, and `FileDb` persists it to a single file, so a node can be restarted.
+
+use crate::codec::{decode_all, Decode, Encode};
+use crate::crypto::blake2_256;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The columns of a database, each of which is a separate key-value space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
+pub enum Column {
+    /// The entries of the state of the last block.
+    State,
+    /// Block headers, by block hash.
+    Header,
+    /// Block bodies, by block hash.
+    Body,
+    /// Block hashes, by block number.
+    BlockHash,
+    /// Everything else, like the hash of the best block.
+    Meta,
+    /// The justifications of finalized blocks, by block hash.
+    Justification,
+}
+
+/// A set of changes to apply to a database at once: `None` removes a key.
+#[derive(Debug, Clone, Default, Encode, Decode)]
+pub struct Transaction(pub Vec<(Column, Vec<u8>, Option<Vec<u8>>)>);
+
+impl Transaction {
+    pub fn set(&mut self, column: Column, key: Vec<u8>, value: Vec<u8>) {
+        self.0.push((column, key, Some(value)));
+    }
+
+    pub fn remove(&mut self, column: Column, key: Vec<u8>) {
+        self.0.push((column, key, None));
+    }
+}
+
+/// A key-value database.
+pub trait Database {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Every entry of `column`, sorted by key.
+    fn entries(&self, column: Column) -> Vec<(Vec<u8>, Vec<u8>)>;
+
+    /// Apply every change of `transaction`, or none of them if it fails.
+    fn commit(&mut self, transaction: Transaction) -> io::Result<()>;
+}
+
+/// A database which lives in memory, and is gone when dropped.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb {
+    entries: BTreeMap<(Column, Vec<u8>), Vec<u8>>,
+}
+
+impl MemoryDb {
+    fn apply(&mut self, transaction: Transaction) {
+        for (column, key, value) in transaction.0 {
+            match value {
+                Some(value) => self.entries.insert((column, key), value),
+                None => self.entries.remove(&(column, key)),
+            };
+        }
+    }
+}
+
+impl Database for MemoryDb {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.get(&(column, key.to_vec())).cloned()
+    }
+
+    fn entries(&self, column: Column) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.entries
+            .range((column, Vec::new())..)
+            .take_while(|((entry_column, _), _)| *entry_column == column)
+            .map(|((_, key), value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    fn commit(&mut self, transaction: Transaction) -> io::Result<()> {
+        self.apply(transaction);
+        Ok(())
+    }
+}
+
+/// A database persisted to a single file.
+///
+/// The file is a log of the committed transactions. Each record is the length of the encoded
+/// transaction, a checksum, and the encoded transaction itself, and is flushed to disk before
+/// `commit` returns. The whole database is also kept in memory, to serve reads.
+///
+/// On `open`, the log is replayed up to the first record which is incomplete or does not match
+/// its checksum, which is what a crash in the middle of a commit leaves behind. The log is then
+/// compacted, by writing the live entries to a new file and moving it over the old one.
+#[derive(Debug)]
+pub struct FileDb {
+    file: File,
+    /// The length of the log up to the last committed record.
+    len: u64,
+    memory: MemoryDb,
+}
+
+/// The size of the length and checksum before every record.
+const RECORD_HEADER_LEN: usize = 8;
+
+impl FileDb {
+    /// Open the database at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut log = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut log)?;
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        let mut memory = MemoryDb::default();
+        let mut records = &log[..];
+        while let Some((transaction, rest)) = read_record(records) {
+            memory.apply(transaction);
+            records = rest;
+        }
+
+        // Start over with a log holding a single transaction which sets every live entry.
+        let compacted = Transaction(
+            memory
+                .entries
+                .iter()
+                .map(|((column, key), value)| (*column, key.clone(), Some(value.clone())))
+                .collect(),
+        );
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let compacted = record(&compacted);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&compacted)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            file,
+            len: compacted.len() as u64,
+            memory,
+        })
+    }
+}
+
+/// `transaction` as a record of the log.
+fn record(transaction: &Transaction) -> Vec<u8> {
+    let payload = transaction.encode();
+    let mut record = (payload.len() as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(&blake2_256(&payload)[..4]);
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// The transaction in the record at the start of `log`, and the rest of the log, if the record is
+/// complete and intact.
+fn read_record(log: &[u8]) -> Option<(Transaction, &[u8])> {
+    if log.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let (header, rest) = log.split_at(RECORD_HEADER_LEN);
+    let len = u32::from_le_bytes(header[..4].try_into().expect("4 bytes; qed")) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (payload, rest) = rest.split_at(len);
+    if blake2_256(payload)[..4] != header[4..] {
+        return None;
+    }
+    decode_all(payload)
+        .ok()
+        .map(|transaction| (transaction, rest))
+}
+
+impl Database for FileDb {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>> {
+        self.memory.get(column, key)
+    }
+
+    fn entries(&self, column: Column) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.memory.entries(column)
+    }
+
+    fn commit(&mut self, transaction: Transaction) -> io::Result<()> {
+        let record = record(&transaction);
+        let written = self
+            .file
+            .write_all(&record)
+            .and_then(|()| self.file.sync_data());
+        if let Err(error) = written {
+            // Cut off what made it to the file, so later records are not stuck behind it.
+            self.file.set_len(self.len)?;
+            return Err(error);
+        }
+        self.len += record.len() as u64;
+        self.memory.apply(transaction);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Column, Database, FileDb, Transaction};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn file_db_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("file-db-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut db = FileDb::open(&path).unwrap();
+        let mut transaction = Transaction::default();
+        transaction.set(Column::State, b"alice".to_vec(), b"100".to_vec());
+        transaction.set(Column::State, b"bob".to_vec(), b"50".to_vec());
+        transaction.set(Column::Meta, b"best".to_vec(), b"1".to_vec());
+        db.commit(transaction).unwrap();
+        let mut transaction = Transaction::default();
+        transaction.remove(Column::State, b"bob".to_vec());
+        db.commit(transaction).unwrap();
+        drop(db);
+
+        let mut db = FileDb::open(&path).unwrap();
+        assert_eq!(
+            db.entries(Column::State),
+            vec![(b"alice".to_vec(), b"100".to_vec())]
+        );
+        assert_eq!(db.get(Column::Meta, b"best"), Some(b"1".to_vec()));
+
+        // A commit cut short by a crash is ignored, along with everything after it.
+        let mut transaction = Transaction::default();
+        transaction.set(Column::State, b"charlie".to_vec(), b"10".to_vec());
+        db.commit(transaction).unwrap();
+        drop(db);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[20, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let db = FileDb::open(&path).unwrap();
+        assert_eq!(db.entries(Column::State).len(), 2);
+        assert_eq!(db.get(Column::State, b"charlie"), Some(b"10".to_vec()));
+        assert_eq!(db.get(Column::Header, b"alice"), None);
+
+        drop(db);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+//! Finality: blocks the authorities agreed on, which can never be reverted.
+//!
+//! Fork choice alone never settles a block, since a longer fork can always turn up. Instead, every
+//! authority signs a precommit for the block it considers best, and once more than two thirds of
+//! the authorities precommitted to the same block, that block is final. The precommits make up its
+//! justification, which anyone who knows the authorities can check. As long as fewer than a third
+//! of the authorities are faulty, two conflicting blocks can never both be final: an authority
+//! which precommits to two different blocks of the same number equivocates, and its votes are
+//! rejected.
+//!
+//! Precommits are signed along with the genesis hash of the chain and the id of the authority set,
+//! so they can not be replayed on another chain, or once the authorities changed. Once a block is
+//! final, precommits for it or for any block before it are rejected.
+//!
+//! The backend keeps the justification along with the block, and prunes every fork which does not
+//! build on it.
+
+use crate::codec::{Decode, Encode};
+use crate::crypto::{Hash, MultiSignature, MultiSigner, Pair};
+use crate::support::Verify;
+use crate::types::BlockNumber;
+use std::collections::BTreeMap;
+
+/// Tells authority sets apart: it changes whenever the authorities do.
+pub type SetId = u64;
+
+/// A vote to finalize a block, and its ancestors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Precommit {
+    pub target_hash: Hash,
+    pub target_number: BlockNumber,
+}
+
+impl Precommit {
+    /// What authorities sign, tagged so the signature can not be passed off as anything else, for
+    /// the chain with `genesis_hash` and the authority set `set_id`.
+    fn signing_payload(&self, genesis_hash: &Hash, set_id: SetId) -> Vec<u8> {
+        (*b"precommit", genesis_hash, set_id, self).encode()
+    }
+}
+
+/// A precommit, signed by the authority `id`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SignedPrecommit {
+    pub precommit: Precommit,
+    pub signature: MultiSignature,
+    pub id: MultiSigner,
+}
+
+impl SignedPrecommit {
+    pub fn new(precommit: Precommit, pair: &Pair, genesis_hash: &Hash, set_id: SetId) -> Self {
+        Self {
+            signature: pair.sign(&precommit.signing_payload(genesis_hash, set_id)),
+            id: pair.public(),
+            precommit,
+        }
+    }
+}
+
+/// Proof that a block is final: precommits for it by more than two thirds of the authorities.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Justification {
+    pub target_hash: Hash,
+    pub target_number: BlockNumber,
+    pub precommits: Vec<SignedPrecommit>,
+}
+
+/// Why a precommit or a justification was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalityError {
+    /// The signer is not an authority.
+    NotAnAuthority,
+    /// The signature does not match the precommit.
+    BadSignature,
+    /// The authority already precommitted to this block.
+    DuplicatePrecommit,
+    /// The authority already precommitted to another block of the same number.
+    Equivocation,
+    /// A precommit of the justification is for another block.
+    WrongTarget,
+    /// Not enough authorities precommitted to the block.
+    NotEnoughPrecommits,
+    /// The block is not after the last finalized block, so it is final or can never be.
+    AlreadyFinalized,
+}
+
+/// The authorities voting on finality, and the precommits they cast so far. See the module docs.
+#[derive(Debug, Clone)]
+pub struct Finality {
+    genesis_hash: Hash,
+    set_id: SetId,
+    authorities: Vec<MultiSigner>,
+    /// The number of the last finalized block.
+    finalized: BlockNumber,
+    /// The precommits received for each block which is not final yet.
+    precommits: BTreeMap<Hash, Vec<SignedPrecommit>>,
+}
+
+impl Finality {
+    /// The authority set `set_id` of the chain with `genesis_hash`, from the genesis block.
+    pub fn new(genesis_hash: Hash, set_id: SetId, authorities: Vec<MultiSigner>) -> Self {
+        assert!(!authorities.is_empty(), "There must be an authority.");
+        Self {
+            genesis_hash,
+            set_id,
+            authorities,
+            finalized: 0,
+            precommits: BTreeMap::new(),
+        }
+    }
+
+    /// Carry on from the block `number`, which was finalized before, like by an earlier run of the
+    /// node.
+    pub fn set_finalized(&mut self, number: BlockNumber) {
+        self.finalized = self.finalized.max(number);
+        self.precommits.retain(|_, precommits| {
+            precommits
+                .iter()
+                .any(|signed| signed.precommit.target_number > number)
+        });
+    }
+
+    /// The number of precommits which make a block final: more than two thirds of the
+    /// authorities.
+    pub fn threshold(&self) -> usize {
+        self.authorities.len() * 2 / 3 + 1
+    }
+
+    /// Sign a precommit for the block `target_hash` with the key of `pair`, and count it. Returns
+    /// the justification of the block if this makes it final.
+    pub fn precommit(
+        &mut self,
+        pair: &Pair,
+        target_hash: Hash,
+        target_number: BlockNumber,
+    ) -> Result<Option<Justification>, FinalityError> {
+        let precommit = Precommit {
+            target_hash,
+            target_number,
+        };
+        let signed = SignedPrecommit::new(precommit, pair, &self.genesis_hash, self.set_id);
+        self.import_precommit(signed)
+    }
+
+    /// Count a precommit received from an authority. Returns the justification of its block if
+    /// this makes it final, after which the precommits for that block and the blocks before it are
+    /// forgotten, and no longer accepted.
+    pub fn import_precommit(
+        &mut self,
+        signed: SignedPrecommit,
+    ) -> Result<Option<Justification>, FinalityError> {
+        self.check(&signed)?;
+        let Precommit {
+            target_hash,
+            target_number,
+        } = signed.precommit;
+        if target_number <= self.finalized {
+            return Err(FinalityError::AlreadyFinalized);
+        }
+        for (hash, precommits) in &self.precommits {
+            for other in precommits.iter().filter(|other| other.id == signed.id) {
+                if *hash == target_hash {
+                    return Err(FinalityError::DuplicatePrecommit);
+                }
+                if other.precommit.target_number == target_number {
+                    return Err(FinalityError::Equivocation);
+                }
+            }
+        }
+        let precommits = self.precommits.entry(target_hash).or_default();
+        precommits.push(signed);
+        if precommits.len() < self.threshold() {
+            return Ok(None);
+        }
+        let precommits = self.precommits.remove(&target_hash).unwrap_or_default();
+        self.set_finalized(target_number);
+        Ok(Some(Justification {
+            target_hash,
+            target_number,
+            precommits,
+        }))
+    }
+
+    /// Check that `justification` holds precommits for its block by more than two thirds of the
+    /// authorities.
+    pub fn verify(&self, justification: &Justification) -> Result<(), FinalityError> {
+        let mut signers = Vec::new();
+        for signed in &justification.precommits {
+            if signed.precommit.target_hash != justification.target_hash
+                || signed.precommit.target_number != justification.target_number
+            {
+                return Err(FinalityError::WrongTarget);
+            }
+            self.check(signed)?;
+            if signers.contains(&signed.id) {
+                return Err(FinalityError::DuplicatePrecommit);
+            }
+            signers.push(signed.id);
+        }
+        if signers.len() < self.threshold() {
+            return Err(FinalityError::NotEnoughPrecommits);
+        }
+        Ok(())
+    }
+
+    /// Check that `signed` is a precommit by an authority.
+    fn check(&self, signed: &SignedPrecommit) -> Result<(), FinalityError> {
+        if !self.authorities.contains(&signed.id) {
+            return Err(FinalityError::NotAnAuthority);
+        }
+        let payload = signed
+            .precommit
+            .signing_payload(&self.genesis_hash, self.set_id);
+        if !signed.signature.verify(&payload, &signed.id) {
+            return Err(FinalityError::BadSignature);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Finality, FinalityError, Precommit, SignedPrecommit};
+    use crate::crypto::{dev_seed, Pair};
+
+    const GENESIS_HASH: [u8; 32] = [7; 32];
+
+    fn authorities() -> (Vec<Pair>, Finality) {
+        let pairs = ["alice", "bob", "charlie", "dave"]
+            .map(|name| Pair::sr25519(&dev_seed(name)))
+            .to_vec();
+        let finality = Finality::new(GENESIS_HASH, 0, pairs.iter().map(Pair::public).collect());
+        (pairs, finality)
+    }
+
+    #[test]
+    fn blocks_are_final_with_more_than_two_thirds_of_the_precommits() {
+        let (pairs, mut finality) = authorities();
+        assert_eq!(finality.threshold(), 3);
+        assert_eq!(
+            Finality::new(GENESIS_HASH, 0, vec![pairs[0].public()]).threshold(),
+            1
+        );
+        assert_eq!(
+            Finality::new(
+                GENESIS_HASH,
+                0,
+                pairs[..3].iter().map(Pair::public).collect()
+            )
+            .threshold(),
+            3
+        );
+
+        assert_eq!(finality.precommit(&pairs[0], [1; 32], 1), Ok(None));
+        assert_eq!(finality.precommit(&pairs[1], [1; 32], 1), Ok(None));
+        // A precommit for another block does not count towards the first one.
+        assert_eq!(finality.precommit(&pairs[2], [2; 32], 2), Ok(None));
+        let justification = finality
+            .precommit(&pairs[3], [1; 32], 1)
+            .unwrap()
+            .expect("Three of four authorities precommitted.");
+        assert_eq!(justification.target_hash, [1; 32]);
+        assert_eq!(justification.target_number, 1);
+        assert_eq!(justification.precommits.len(), 3);
+        assert_eq!(finality.verify(&justification), Ok(()));
+
+        // The precommit for the later block is still counted.
+        assert_eq!(finality.precommit(&pairs[0], [2; 32], 2), Ok(None));
+        assert!(finality.precommit(&pairs[1], [2; 32], 2).unwrap().is_some());
+    }
+
+    #[test]
+    fn precommits_must_come_from_honest_authorities() {
+        let (pairs, mut finality) = authorities();
+        let eve = Pair::ed25519(&dev_seed("eve"));
+        assert_eq!(
+            finality.precommit(&eve, [1; 32], 1),
+            Err(FinalityError::NotAnAuthority)
+        );
+
+        let mut forged = SignedPrecommit::new(
+            Precommit {
+                target_hash: [1; 32],
+                target_number: 1,
+            },
+            &eve,
+            &GENESIS_HASH,
+            0,
+        );
+        forged.id = pairs[0].public();
+        assert_eq!(
+            finality.import_precommit(forged),
+            Err(FinalityError::BadSignature)
+        );
+
+        assert_eq!(finality.precommit(&pairs[0], [1; 32], 1), Ok(None));
+        assert_eq!(
+            finality.precommit(&pairs[0], [1; 32], 1),
+            Err(FinalityError::DuplicatePrecommit)
+        );
+        assert_eq!(
+            finality.precommit(&pairs[0], [2; 32], 1),
+            Err(FinalityError::Equivocation)
+        );
+    }
+
+    #[test]
+    fn justifications_are_verified() {
+        let (pairs, mut finality) = authorities();
+        for pair in &pairs[..2] {
+            finality.precommit(pair, [1; 32], 1).unwrap();
+        }
+        let justification = finality.precommit(&pairs[2], [1; 32], 1).unwrap().unwrap();
+
+        let mut short = justification.clone();
+        short.precommits.pop();
+        assert_eq!(
+            finality.verify(&short),
+            Err(FinalityError::NotEnoughPrecommits)
+        );
+
+        let mut repeated = short.clone();
+        repeated.precommits.push(repeated.precommits[0].clone());
+        assert_eq!(
+            finality.verify(&repeated),
+            Err(FinalityError::DuplicatePrecommit)
+        );
+
+        // The precommits do not carry over to another block.
+        let mut moved = justification.clone();
+        moved.target_hash = [2; 32];
+        assert_eq!(finality.verify(&moved), Err(FinalityError::WrongTarget));
+        for signed in &mut moved.precommits {
+            signed.precommit.target_hash = [2; 32];
+        }
+        assert_eq!(finality.verify(&moved), Err(FinalityError::BadSignature));
+    }
+
+    #[test]
+    fn precommits_are_bound_to_the_chain_and_the_authority_set() {
+        let (pairs, mut finality) = authorities();
+        let precommit = Precommit {
+            target_hash: [1; 32],
+            target_number: 1,
+        };
+        let other_chain = SignedPrecommit::new(precommit, &pairs[0], &[8; 32], 0);
+        assert_eq!(
+            finality.import_precommit(other_chain),
+            Err(FinalityError::BadSignature)
+        );
+        let other_set = SignedPrecommit::new(precommit, &pairs[0], &GENESIS_HASH, 1);
+        assert_eq!(
+            finality.import_precommit(other_set),
+            Err(FinalityError::BadSignature)
+        );
+        let signed = SignedPrecommit::new(precommit, &pairs[0], &GENESIS_HASH, 0);
+        assert_eq!(finality.import_precommit(signed), Ok(None));
+    }
+
+    #[test]
+    fn finalized_blocks_take_no_more_precommits() {
+        let (pairs, mut finality) = authorities();
+        for pair in &pairs[..2] {
+            finality.precommit(pair, [2; 32], 2).unwrap();
+        }
+        assert!(finality.precommit(&pairs[2], [2; 32], 2).unwrap().is_some());
+
+        // Neither the final block, nor a block before it or beside it, can be finalized again.
+        assert_eq!(
+            finality.precommit(&pairs[3], [2; 32], 2),
+            Err(FinalityError::AlreadyFinalized)
+        );
+        for pair in &pairs[..3] {
+            assert_eq!(
+                finality.precommit(pair, [1; 32], 1),
+                Err(FinalityError::AlreadyFinalized)
+            );
+            assert_eq!(
+                finality.precommit(pair, [3; 32], 2),
+                Err(FinalityError::AlreadyFinalized)
+            );
+        }
+        assert_eq!(finality.precommit(&pairs[0], [4; 32], 3), Ok(None));
+
+        // A node which restarts carries on from the last finalized block.
+        let (_, mut restarted) = authorities();
+        restarted.set_finalized(3);
+        assert_eq!(
+            restarted.precommit(&pairs[0], [4; 32], 3),
+            Err(FinalityError::AlreadyFinalized)
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Checks for light clients, which follow the chain through its headers alone.
+//!
+//! A light client holds no state. It asks a full node for the storage entries it cares about,
+//! along with a proof made by `Storage::read_proof`, and checks the answer against the state root
+//! of a header it trusts. Nothing here depends on the runtime or its storage.
+
+use crate::codec::{decode_all, Decode};
+use crate::support::Header;
+use crate::trie::{self, Proof, ProofError};
+
+/// The reason an answer from a full node was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The proof does not hold against the state root of the header.
+    InvalidProof(ProofError),
+    /// The proven value is not of the expected type.
+    UndecodableValue(crate::codec::Error),
+}
+
+/// Check that `proof` shows the value at the storage `key` in the state of `header`, and decode
+/// it. `None` means the key is not in storage.
+pub fn verify_storage<BlockNumber, V: Decode>(
+    header: &Header<BlockNumber>,
+    key: &[u8],
+    proof: &Proof,
+) -> Result<Option<V>, Error> {
+    let value = trie::verify_proof(&header.state_root, key, proof).map_err(Error::InvalidProof)?;
+    value
+        .map(|value| decode_all(&value).map_err(Error::UndecodableValue))
+        .transpose()
+}
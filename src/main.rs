@@ -1,16 +1,57 @@
-use crate::support::{Dispatch, Extrinsic, Header};
-use crate::types::{AccountId, Balance, Block, BlockNumber, Content, Nonce};
+use crate::backend::{Backend, FinalizeError, ImportError};
+use crate::chain_spec::ChainSpec;
+use crate::consensus::Poa;
+use crate::crypto::{dev_seed, Pair};
+use crate::database::FileDb;
+use crate::finality::Finality;
+use crate::rpc::Chain;
+use crate::support::{Dispatch, Extrinsic, Weight};
+use crate::transaction_pool::TransactionPool;
+use crate::types::{AccountId, Balance, Block, BlockNumber, Content, Moment, Nonce};
+use std::iter;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+mod backend;
 mod balances;
+mod block_builder;
+mod chain_spec;
+mod codec;
+mod consensus;
+mod crypto;
+mod database;
+mod finality;
+mod light_client;
+#[cfg(test)]
+mod mock;
+mod multisig;
 mod proof_of_existence;
+mod rpc;
+#[cfg(test)]
+mod simulator;
+mod storage;
+mod sudo;
 mod support;
 mod system;
+mod timestamp;
+mod transaction_payment;
+mod transaction_pool;
+mod trie;
 mod types;
+mod utility;
 
 impl system::Config for Runtime {
     type AccountId = AccountId;
     type BlockNumber = BlockNumber;
     type Nonce = Nonce;
+    type RuntimeEvent = RuntimeEvent;
+    type PalletInfo = PalletInfo;
+    const MAX_BLOCK_WEIGHT: Weight = 100_000;
+    const EXTRINSIC_BASE_WEIGHT: Weight = 1_000;
+    const MAX_BLOCK_LENGTH: u32 = 64 * 1024;
+    type OnChargeTransaction = transaction_payment::ChargeTransactionPayment<Self>;
 }
 impl balances::Config for Runtime {
     type Balance = Balance;
@@ -18,91 +59,1106 @@ impl balances::Config for Runtime {
 impl proof_of_existence::Config for Runtime {
     type Content = Content;
 }
+impl multisig::Config for Runtime {
+    const DEPOSIT_BASE: Balance = 1_000;
+    const DEPOSIT_PER_BYTE: Balance = 10;
+    const MAX_SIGNATORIES: u16 = 16;
+}
+impl sudo::Config for Runtime {}
+impl timestamp::Config for Runtime {
+    type Moment = Moment;
+}
+impl utility::Config for Runtime {}
+impl transaction_payment::Config for Runtime {
+    fn weight_to_fee(weight: Weight) -> Balance {
+        Balance::from(weight / 1_000)
+    }
+
+    fn length_to_fee(len: u32) -> Balance {
+        Balance::from(len / 10)
+    }
+}
+
+/// How long each authority has to author its block, in milliseconds.
+const SLOT_DURATION: u64 = 6_000;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[macros::runtime]
 pub struct Runtime {
     system: system::Pallet<Self>,
     balances: balances::Pallet<Self>,
     proof_of_existence: proof_of_existence::Pallet<Self>,
+    sudo: sudo::Pallet<Self>,
+    utility: utility::Pallet<Self>,
+    multisig: multisig::Pallet<Self>,
+    timestamp: timestamp::Pallet<Self>,
+}
+
+/// Milliseconds since the unix epoch.
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock is after the unix epoch.")
+        .as_millis() as u64
+}
+
+/// Author the next block out of the ready extrinsics of `pool`, in the slot at `now`, or in the
+/// slot after the best block if that is later, with the key of its author among `authorities`.
+/// A later slot is waited for, as the block could not be imported before it starts. The block
+/// starts with an unsigned extrinsic setting its time. It is imported, and finalized with the
+/// precommits of every authority.
+fn author_block(
+    backend: &mut Backend<FileDb>,
+    runtime: &mut Runtime,
+    pool: &mut TransactionPool,
+    poa: &Poa,
+    finality: &mut Finality,
+    authorities: &[&Pair],
+    now: u64,
+) -> Block {
+    let slot = backend.state().execute_with(|| {
+        Poa::best_slot(runtime)
+            .map_or(0, |best| best + 1)
+            .max(poa.slot_at(now))
+    });
+    let start = slot * SLOT_DURATION;
+    if start > now {
+        thread::sleep(Duration::from_millis(start - now));
+    }
+    let now = now.max(start);
+    let block = backend.state().execute_with(|| {
+        let author = authorities
+            .iter()
+            .find(|pair| pair.public() == *poa.author(slot))
+            .expect("This node holds the keys of every authority.");
+        let set_time =
+            Extrinsic::new_unsigned(RuntimeCall::timestamp(timestamp::Call::set { now }));
+        poa.propose(
+            runtime,
+            author,
+            slot,
+            iter::once(set_time).chain(pool.ready()),
+        )
+        .expect("The author of the slot proposes its block.")
+    });
+    let block_number = block.header.block_number;
+    println!("Block {block_number}: authored in slot {slot}");
+    match backend.import_block(&block, |block| poa.import_block(runtime, block, now)) {
+        Err(ImportError::Database(error)) => panic!("Can not write to the database: {error}"),
+        result => result.expect("Invalid block."),
+    };
+    backend.state().execute_with(|| pool.maintain(runtime));
+
+    // Every authority precommits to the block it just imported, which makes it final.
+    let hash = block.header.hash();
+    let justification = authorities
+        .iter()
+        .map(|pair| finality.precommit(pair, hash, block_number))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Valid precommit.")
+        .pop()
+        .flatten()
+        .expect("Every authority precommitted.");
+    match backend.finalize_block(&justification) {
+        Err(FinalizeError::Database(error)) => panic!("Can not write to the database: {error}"),
+        result => result.expect("The block builds on the last finalized block."),
+    };
+    println!("Block {block_number}: finalized");
+    block
 }
 
 fn main() {
+    // The chain is kept in the file given as the first argument, so every run continues it. A new
+    // chain starts from the chain spec given as the second argument, or the development chain.
+    // With `--rpc <address>`, the node then serves JSON-RPC, and keeps authoring blocks.
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let rpc_address = args.iter().position(|arg| arg == "--rpc").map(|i| {
+        let address = args
+            .get(i + 1)
+            .cloned()
+            .expect("--rpc takes an address, like 127.0.0.1:9944.");
+        args.drain(i..=i + 1);
+        address
+    });
+    let mut args = args.into_iter();
+    let path = args.next().unwrap_or_else(|| "chain.db".to_string());
+    let spec = match args.next() {
+        Some(spec) => ChainSpec::from_json_file(spec).expect("Can not read the chain spec."),
+        None => ChainSpec::development(),
+    };
+    let mut backend = Backend::open(FileDb::open(&path).expect("Can not open the database."));
     let mut runtime = Runtime::new();
-    let alice = String::from("alice");
-    let bob = String::from("bob");
-    let charlie = String::from("charlie");
+    let alice = Pair::ed25519(&dev_seed("alice"));
+    let bob = Pair::sr25519(&dev_seed("bob"));
+    let charlie = Pair::ed25519(&dev_seed("charlie"));
+    match backend.best_header() {
+        None => {
+            println!(
+                "Starting a new chain in {path} from the {} chain spec",
+                spec.name
+            );
+            let genesis = backend
+                .state()
+                .execute_with(|| spec.build_genesis(&runtime));
+            backend
+                .commit_block(&genesis)
+                .expect("Can not write to the database.");
+        }
+        Some(header) => {
+            println!(
+                "Continuing the chain in {path} from block {}",
+                header.block_number
+            );
+        }
+    }
 
-    runtime.balances.set_balance(&alice, 100);
+    // Alice and bob are the authorities of the chain: they take turns authoring blocks, and vote
+    // on which blocks are final.
+    let genesis_hash = backend
+        .block_hash(0)
+        .expect("The chain has a genesis block.");
+    let mut finality = Finality::new(genesis_hash, 0, vec![alice.public(), bob.public()]);
+    // Anyone can check that the last finalized block was agreed on by the authorities.
+    if let Some(finalized) = backend.finalized_header() {
+        let justification = backend
+            .justification(&finalized.hash())
+            .expect("Finalized blocks keep their justification.");
+        finality
+            .verify(&justification)
+            .expect("Valid justification.");
+        println!(
+            "Block {} is final, justified by {} precommits",
+            finalized.block_number,
+            justification.precommits.len()
+        );
+        finality.set_finalized(finalized.block_number);
+    }
 
-    let block_1 = Block {
-        header: Header { block_number: 1 },
-        extrinsics: vec![
-            Extrinsic {
-                caller: alice.clone(),
-                call: RuntimeCall::balances(balances::Call::transfer {
-                    receiver: bob.clone(),
+    // Nonces carry on from the state of the chain, so the same calls can be submitted every run.
+    let (genesis_hash, alice_nonce, bob_nonce) = backend.state().execute_with(|| {
+        (
+            runtime.system.genesis_hash(),
+            runtime.system.nonce(&alice.account()),
+            runtime.system.nonce(&bob.account()),
+        )
+    });
+
+    // Alice pays bob and charlie in one go: either both transfers go through, or neither.
+    let block_1 = vec![Extrinsic::new_signed(
+        RuntimeCall::utility(utility::Call::batch_all {
+            calls: vec![
+                RuntimeCall::balances(balances::Call::transfer {
+                    receiver: bob.account(),
                     amount: 30,
                 }),
-            },
-            Extrinsic {
-                caller: alice.clone(),
-                call: RuntimeCall::balances(balances::Call::transfer {
-                    receiver: charlie.clone(),
+                RuntimeCall::balances(balances::Call::transfer {
+                    receiver: charlie.account(),
                     amount: 20,
                 }),
-            },
-        ],
+            ],
+        }),
+        &alice,
+        alice_nonce,
+        &genesis_hash,
+    )];
+    let block_2 = vec![
+        Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                claim: "ggs".to_string(),
+            }),
+            &alice,
+            alice_nonce + 1,
+            &genesis_hash,
+        ),
+        Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+                claim: "ggs".to_string(),
+            }),
+            &bob,
+            bob_nonce,
+            &genesis_hash,
+        ),
+        Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+                claim: "ggs".to_string(),
+            }),
+            &alice,
+            alice_nonce + 2,
+            &genesis_hash,
+        ),
+        Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                claim: "lakaka".to_string(),
+            }),
+            &bob,
+            bob_nonce + 1,
+            &genesis_hash,
+        ),
+    ];
+    let block_3 = vec![
+        support::Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+                claim: "Hello, world!".to_string(),
+            }),
+            &alice,
+            alice_nonce + 3,
+            &genesis_hash,
+        ),
+        support::Extrinsic::new_signed(
+            RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                claim: "Hello, world!".to_string(),
+            }),
+            &bob,
+            bob_nonce + 2,
+            &genesis_hash,
+        ),
+        // Alice holds the sudo key of the development chain, so she can make calls for root only.
+        support::Extrinsic::new_signed(
+            RuntimeCall::sudo(sudo::Call::sudo {
+                call: Box::new(RuntimeCall::balances(balances::Call::force_set_balance {
+                    who: charlie.account(),
+                    amount: 1_000,
+                })),
+            }),
+            &alice,
+            alice_nonce + 4,
+            &genesis_hash,
+        ),
+    ];
+    // This node holds the keys of both authorities, so it authors every block, in the next slot from
+    // now on.
+    let poa = Poa::new(vec![alice.public(), bob.public()], SLOT_DURATION);
+    let now = timestamp();
+
+    // Each block is built out of the ready extrinsics of the pool, and the pool is cleaned up
+    // once the block is imported.
+    let mut pool = TransactionPool::new();
+    for extrinsics in [block_1, block_2, block_3] {
+        backend.state().execute_with(|| {
+            for extrinsic in extrinsics {
+                pool.submit(&mut runtime, extrinsic)
+                    .expect("Valid extrinsic.");
+            }
+        });
+        let block = author_block(
+            &mut backend,
+            &mut runtime,
+            &mut pool,
+            &poa,
+            &mut finality,
+            &[&alice, &bob],
+            now,
+        );
+        let block_number = block.header.block_number;
+        backend.state().execute_with(|| {
+            for record in runtime.system.events() {
+                match &record.event {
+                    RuntimeEvent::system(system::Event::ExtrinsicFailed { error, .. }) => {
+                        let error = RuntimeError::try_from(*error)
+                            .map(|error| format!("{error:?}"))
+                            .unwrap_or_else(|error| format!("{error:?}"));
+                        println!(
+                            "Block {block_number}, {:?}: failed with {error}",
+                            record.phase
+                        )
+                    }
+                    event => println!("Block {block_number}, {:?}: {event:?}", record.phase),
+                }
+            }
+        });
+    }
+
+    // Everything submitted was included, so nothing is left waiting.
+    println!("Left in the transaction pool: {:?}", pool.status());
+
+    backend.state().execute_with(|| {
+        for (name, pair) in [("alice", &alice), ("bob", &bob), ("charlie", &charlie)] {
+            let account = pair.account();
+            println!(
+                "{name} ({account:?}): balance {}, nonce {}",
+                runtime.balances.balance(&account),
+                runtime.system.nonce(&account)
+            );
+        }
+    });
+
+    // Every block is kept in the database, by number and by hash.
+    let header = backend.best_header().expect("Blocks were committed.");
+    // The genesis block may have any number, so blocks are only kept from that number on.
+    let blocks = (0..=header.block_number)
+        .filter_map(|number| backend.block_hash(number))
+        .map(|hash| backend.block(&hash).expect("Blocks are kept."))
+        .collect::<Vec<_>>();
+    let extrinsics = blocks
+        .iter()
+        .map(|block| block.extrinsics.len())
+        .sum::<usize>();
+    println!(
+        "The chain in {path} has {} blocks on top of genesis, with {extrinsics} extrinsics",
+        blocks.len() - 1
+    );
+
+    // A light client which only trusts the last header can be convinced of single storage entries,
+    // without the rest of the state.
+    let key = runtime.balances.balance_key(&alice.account());
+    let balance: Option<Balance> =
+        light_client::verify_storage(&header, &key, &backend.state().read_proof(&key))
+            .expect("Valid proof.");
+    println!("Proved to a light client: alice has balance {balance:?}");
+    for claim in ["Hello, world!", "ggs"] {
+        let key = runtime.proof_of_existence.claim_key(&claim.to_string());
+        let owner: Option<AccountId> =
+            light_client::verify_storage(&header, &key, &backend.state().read_proof(&key))
+                .expect("Valid proof.");
+        println!("Proved to a light client: {claim:?} is claimed by {owner:?}");
+    }
+
+    let Some(address) = rpc_address else {
+        return;
     };
-    let block_2 = Block {
-        header: Header { block_number: 2 },
-        extrinsics: vec![
-            Extrinsic {
-                caller: alice.clone(),
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
-                    claim: "ggs",
-                }),
+    let listener = TcpListener::bind(&address).expect("Can not listen on the RPC address.");
+    println!("Serving JSON-RPC on http://{address} and ws://{address}");
+    let chain = Arc::new(Mutex::new(Chain {
+        backend,
+        runtime,
+        pool,
+    }));
+    // Extrinsics submitted over RPC are included in the block of the next slot.
+    let authoring = chain.clone();
+    thread::spawn(move || loop {
+        let now = timestamp();
+        let next_slot = (poa.slot_at(now) + 1) * SLOT_DURATION;
+        thread::sleep(Duration::from_millis(next_slot - now));
+        let mut chain = authoring.lock().unwrap_or_else(PoisonError::into_inner);
+        let Chain {
+            backend,
+            runtime,
+            pool,
+        } = &mut *chain;
+        author_block(
+            backend,
+            runtime,
+            pool,
+            &poa,
+            &mut finality,
+            &[&alice, &bob],
+            next_slot,
+        );
+    });
+    rpc::serve(listener, chain).expect("The RPC server failed.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_builder::build_block;
+    use crate::codec::Encode;
+    use crate::crypto::Hash;
+    use crate::storage::Storage;
+    use crate::support::{BlockError, Digest, DispatchError, Header, InvalidTransaction};
+    use crate::types::Block;
+
+    fn transfer(
+        runtime: &Runtime,
+        caller: &Pair,
+        nonce: Nonce,
+        receiver: &Pair,
+        amount: Balance,
+    ) -> types::Extrinsic {
+        Extrinsic::new_signed(
+            RuntimeCall::balances(balances::Call::transfer {
+                receiver: receiver.account(),
+                amount,
+            }),
+            caller,
+            nonce,
+            &runtime.system.genesis_hash(),
+        )
+    }
+
+    /// The fee paid for `extrinsic`, if its call uses all the weight it declares.
+    fn fee(extrinsic: &types::Extrinsic) -> Balance {
+        let weight = <Runtime as system::Config>::EXTRINSIC_BASE_WEIGHT
+            + <Runtime as Dispatch>::weight(&extrinsic.call);
+        let len = extrinsic.encode().len() as u32;
+        transaction_payment::ChargeTransactionPayment::<Runtime>::compute_fee(weight, len)
+            .expect("Fees do not overflow.")
+    }
+
+    /// The next block on top of `runtime`, without a valid state root. For blocks which are
+    /// expected to fail before the state root is checked.
+    fn unchecked_block(runtime: &Runtime, extrinsics: Vec<types::Extrinsic>) -> Block {
+        Block {
+            header: Header {
+                parent_hash: runtime.system.block_hash(),
+                block_number: runtime.system.block_number() + 1,
+                state_root: Hash::default(),
+                extrinsics_root: support::extrinsics_root(&extrinsics),
+                digest: Default::default(),
             },
-            Extrinsic {
-                caller: bob.clone(),
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
-                    claim: "ggs",
+            extrinsics,
+        }
+    }
+
+    #[test]
+    fn replayed_extrinsic_is_rejected() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 10);
+            let fee = fee(&extrinsic);
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+
+            // The same transfer again, with a nonce alice already used.
+            let block_2 = unchecked_block(
+                &runtime,
+                vec![
+                    transfer(&runtime, &alice, 1, &bob, 10),
+                    transfer(&runtime, &alice, 1, &bob, 10),
+                ],
+            );
+            assert_eq!(
+                runtime.execute_block(block_2),
+                Err(BlockError::InvalidTransaction {
+                    index: 1,
+                    error: InvalidTransaction::Stale
+                })
+            );
+
+            // The whole block was rolled back, including its first, valid, extrinsic.
+            assert_eq!(runtime.system.block_number(), 1);
+            assert_eq!(runtime.system.nonce(&alice.account()), 1);
+            assert_eq!(runtime.balances.balance(&alice.account()), 90 - fee);
+        });
+    }
+
+    #[test]
+    fn future_nonce_is_rejected() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            let block_1 = unchecked_block(&runtime, vec![transfer(&runtime, &alice, 1, &bob, 10)]);
+            assert_eq!(
+                runtime.execute_block(block_1),
+                Err(BlockError::InvalidTransaction {
+                    index: 0,
+                    error: InvalidTransaction::Future
+                })
+            );
+            assert_eq!(runtime.system.block_number(), 0);
+        });
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            // Bob signs a transfer, and claims it was alice.
+            let mut extrinsic = transfer(&runtime, &bob, 0, &bob, 10);
+            extrinsic.signature.as_mut().unwrap().signer = alice.public();
+
+            let block_1 = unchecked_block(&runtime, vec![extrinsic]);
+            assert_eq!(
+                runtime.execute_block(block_1),
+                Err(BlockError::InvalidTransaction {
+                    index: 0,
+                    error: InvalidTransaction::BadProof
+                })
+            );
+            assert_eq!(runtime.balances.balance(&alice.account()), 100);
+        });
+    }
+
+    #[test]
+    fn signed_calls_can_not_be_unsigned() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let bob = Pair::sr25519(&dev_seed("bob"));
+
+            let extrinsic = Extrinsic {
+                signature: None,
+                call: RuntimeCall::balances(balances::Call::transfer {
+                    receiver: bob.account(),
+                    amount: 10,
                 }),
-            },
-            Extrinsic {
-                caller: alice.clone(),
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
-                    claim: "ggs",
+            };
+            let block_1 = unchecked_block(&runtime, vec![extrinsic]);
+            assert_eq!(
+                runtime.execute_block(block_1),
+                Err(BlockError::InvalidTransaction {
+                    index: 0,
+                    error: InvalidTransaction::UnsignedNotAllowed
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn unsigned_calls_are_included_once() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let set_time =
+                |now| Extrinsic::new_unsigned(RuntimeCall::timestamp(timestamp::Call::set { now }));
+
+            // Only the author of a block puts the time in it, the transaction pool takes none.
+            assert_eq!(
+                runtime.validate_transaction(&set_time(1_000)),
+                Err(InvalidTransaction::NoUnsignedValidator)
+            );
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![set_time(1_000)]);
+            assert_eq!(block_1.extrinsics.len(), 1);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.timestamp.now(), 1_000);
+
+            // Its call would fail once replayed, so it is not valid any more.
+            let replayed = unchecked_block(&runtime, vec![set_time(1_000)]);
+            assert_eq!(
+                runtime.execute_block(replayed),
+                Err(BlockError::InvalidTransaction {
+                    index: 0,
+                    error: InvalidTransaction::Call
+                })
+            );
+
+            // The time is only set once per block.
+            let twice = unchecked_block(&runtime, vec![set_time(2_000), set_time(3_000)]);
+            assert_eq!(
+                runtime.execute_block(twice),
+                Err(BlockError::InvalidTransaction {
+                    index: 1,
+                    error: InvalidTransaction::Call
+                })
+            );
+            let block_2 = build_block(
+                &mut runtime,
+                Digest::default(),
+                vec![set_time(2_000), set_time(3_000)],
+            );
+            assert_eq!(block_2.extrinsics.len(), 1);
+            assert_eq!(runtime.execute_block(block_2), Ok(()));
+            assert_eq!(runtime.timestamp.now(), 2_000);
+        });
+    }
+
+    #[test]
+    fn root_calls_can_not_be_signed() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            // Alice pays for the extrinsic, but the call fails, since only root can make it.
+            let extrinsic = Extrinsic::new_signed(
+                RuntimeCall::balances(balances::Call::force_set_balance {
+                    who: alice.account(),
+                    amount: 1_000_000,
                 }),
-            },
-            Extrinsic {
-                caller: bob.clone(),
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
-                    claim: "lakaka",
+                &alice,
+                0,
+                &runtime.system.genesis_hash(),
+            );
+            let fee = fee(&extrinsic);
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.balances.balance(&alice.account()), 100 - fee);
+            assert!(runtime.system.events().iter().any(|record| matches!(
+                record.event,
+                RuntimeEvent::system(system::Event::ExtrinsicFailed {
+                    error: DispatchError::BadOrigin,
+                    ..
+                })
+            )));
+        });
+    }
+
+    #[test]
+    fn sudo_key_makes_root_calls() {
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        state.execute_with(|| {
+            // Alice holds the sudo key of the development chain.
+            ChainSpec::development().build_genesis(&runtime);
+            let force_set_balance = |amount| {
+                Box::new(RuntimeCall::balances(balances::Call::force_set_balance {
+                    who: charlie.account(),
+                    amount,
+                }))
+            };
+            let sudo = |pair, nonce, amount| {
+                Extrinsic::new_signed(
+                    RuntimeCall::sudo(sudo::Call::sudo {
+                        call: force_set_balance(amount),
+                    }),
+                    pair,
+                    nonce,
+                    &runtime.system.genesis_hash(),
+                )
+            };
+            let extrinsics = vec![sudo(&alice, 0, 500), sudo(&bob, 0, 1_000_000)];
+            let block_1 = build_block(&mut runtime, Digest::default(), extrinsics);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.balances.balance(&charlie.account()), 500);
+
+            let events = runtime
+                .system
+                .events()
+                .into_iter()
+                .map(|record| record.event)
+                .collect::<Vec<_>>();
+            assert!(matches!(
+                &events[1],
+                RuntimeEvent::balances(balances::Event::BalanceSet { amount: 500, .. })
+            ));
+            assert!(matches!(
+                events[2],
+                RuntimeEvent::sudo(sudo::Event::Sudid { result: Ok(()) })
+            ));
+            assert!(events.iter().any(|event| matches!(
+                event,
+                RuntimeEvent::system(system::Event::ExtrinsicFailed { error, .. })
+                    if RuntimeError::try_from(*error)
+                        == Ok(RuntimeError::sudo(sudo::Error::RequireSudo))
+            )));
+        });
+    }
+
+    #[test]
+    fn batches_are_dispatched_in_one_extrinsic() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 1_000);
+            let transfer = |amount| {
+                RuntimeCall::balances(balances::Call::transfer {
+                    receiver: bob.account(),
+                    amount,
+                })
+            };
+            let batch_all = |nonce, calls| {
+                Extrinsic::new_signed(
+                    RuntimeCall::utility(utility::Call::batch_all { calls }),
+                    &alice,
+                    nonce,
+                    &runtime.system.genesis_hash(),
+                )
+            };
+
+            // The second transfer is more than alice has, so neither goes through, but she pays
+            // the fee of the batch.
+            let failing = batch_all(0, vec![transfer(10), transfer(10_000)]);
+            let fee = fee(&failing);
+            let succeeding = batch_all(1, vec![transfer(10), transfer(20)]);
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![failing, succeeding]);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.balances.balance(&bob.account()), 30);
+            assert!(runtime.balances.balance(&alice.account()) < 1_000 - 30 - fee);
+
+            let events = runtime.system.events();
+            assert!(events.iter().any(|record| matches!(
+                record.event,
+                RuntimeEvent::system(system::Event::ExtrinsicFailed { error, .. })
+                    if RuntimeError::try_from(error)
+                        == Ok(RuntimeError::balances(balances::Error::InsufficientBalance))
+            )));
+            let completed = events
+                .iter()
+                .filter(|record| {
+                    matches!(
+                        record.event,
+                        RuntimeEvent::utility(utility::Event::ItemCompleted)
+                    )
+                })
+                .count();
+            assert_eq!(completed, 2);
+        });
+    }
+
+    #[test]
+    fn multisig_accounts_need_enough_approvals() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            let charlie = Pair::ed25519(&dev_seed("charlie"));
+            let accounts = [alice.account(), bob.account(), charlie.account()];
+            let treasury = multisig::Pallet::<Runtime>::multi_account_id(&accounts, 2);
+            for who in accounts.iter().chain([&treasury]) {
+                runtime.balances.set_balance(who, 10_000);
+            }
+
+            // Alice and bob agree to pay charlie out of the treasury.
+            let call = RuntimeCall::balances(balances::Call::transfer {
+                receiver: charlie.account(),
+                amount: 5_000,
+            });
+            let call_hash = crypto::blake2_256(&call.encode());
+            let deposit = 1_000 + 10 * call.encode().len() as Balance;
+            let as_multi = Extrinsic::new_signed(
+                RuntimeCall::multisig(multisig::Call::as_multi {
+                    threshold: 2,
+                    other_signatories: vec![bob.account(), charlie.account()],
+                    call: Box::new(call),
                 }),
-            },
-        ],
-    };
-    let block_3 = types::Block {
-        header: support::Header { block_number: 3 },
-        extrinsics: vec![
-            support::Extrinsic {
-                caller: alice,
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
-                    claim: "Hello, world!",
+                &alice,
+                0,
+                &runtime.system.genesis_hash(),
+            );
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![as_multi]);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.balances.balance(&treasury), 10_000);
+            assert_eq!(runtime.balances.reserved_balance(&alice.account()), deposit);
+
+            let approve = Extrinsic::new_signed(
+                RuntimeCall::multisig(multisig::Call::approve_as_multi {
+                    threshold: 2,
+                    other_signatories: vec![alice.account(), charlie.account()],
+                    call_hash,
+                    max_weight: 10_000,
                 }),
-            },
-            support::Extrinsic {
-                caller: bob,
-                call: RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
-                    claim: "Hello, world!",
+                &bob,
+                0,
+                &runtime.system.genesis_hash(),
+            );
+            let block_2 = build_block(&mut runtime, Digest::default(), vec![approve]);
+            assert_eq!(runtime.execute_block(block_2), Ok(()));
+            assert_eq!(runtime.balances.balance(&treasury), 5_000);
+            assert_eq!(runtime.balances.reserved_balance(&alice.account()), 0);
+            assert!(runtime.system.events().iter().any(|record| matches!(
+                record.event,
+                RuntimeEvent::multisig(multisig::Event::MultisigExecuted { result: Ok(()), .. })
+            )));
+        });
+    }
+
+    #[test]
+    fn blocks_can_not_exceed_the_maximum_weight() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 1_000);
+
+            // A transfer weighs 11_000 with the base weight of an extrinsic, so only nine of them
+            // fit in a block.
+            let extrinsics = (0..10)
+                .map(|nonce| transfer(&runtime, &alice, nonce, &bob, 1))
+                .collect::<Vec<_>>();
+            let block_1 = unchecked_block(&runtime, extrinsics.clone());
+            assert_eq!(
+                runtime.execute_block(block_1),
+                Err(BlockError::InvalidTransaction {
+                    index: 9,
+                    error: InvalidTransaction::ExhaustsResources
+                })
+            );
+
+            let block_1 = build_block(&mut runtime, Digest::default(), extrinsics[..9].to_vec());
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.system.block_weight(), 99_000);
+            let successes = runtime
+                .system
+                .events()
+                .into_iter()
+                .filter(|record| {
+                    matches!(
+                        record.event,
+                        RuntimeEvent::system(system::Event::ExtrinsicSuccess { weight: 11_000 })
+                    )
+                })
+                .count();
+            assert_eq!(successes, 9);
+        });
+    }
+
+    #[test]
+    fn fees_are_paid_before_dispatch() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            // Bob has nothing to pay the fee with.
+            let claim = Extrinsic::new_signed(
+                RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                    claim: "Hello, world!".to_string(),
                 }),
-            },
-        ],
-    };
-    runtime.execute_block(block_1).expect("Invalid block.");
-    runtime.execute_block(block_2).expect("Invalid block.");
-    runtime.execute_block(block_3).expect("Invalid block.");
+                &bob,
+                0,
+                &runtime.system.genesis_hash(),
+            );
+            let block_1 = unchecked_block(&runtime, vec![claim]);
+            assert_eq!(
+                runtime.execute_block(block_1),
+                Err(BlockError::InvalidTransaction {
+                    index: 0,
+                    error: InvalidTransaction::Payment
+                })
+            );
+
+            // Alice can not transfer more than she has, but the failed call still costs her.
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 1_000);
+            let fee = fee(&extrinsic);
+            let block_1 = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block_1), Ok(()));
+            assert_eq!(runtime.balances.balance(&alice.account()), 100 - fee);
+            assert_eq!(runtime.balances.balance(&bob.account()), 0);
+            let events = runtime.system.events();
+            assert!(matches!(
+                events[0].event,
+                RuntimeEvent::balances(balances::Event::Withdraw { amount, .. }) if amount == fee
+            ));
+            assert!(matches!(
+                events[1].event,
+                RuntimeEvent::system(system::Event::ExtrinsicFailed { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn header_must_match_execution() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 10);
+            let fee = fee(&extrinsic);
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+
+            let mut wrong_parent = block.clone();
+            wrong_parent.header.parent_hash = Hash::default();
+            assert_eq!(
+                runtime.execute_block(wrong_parent),
+                Err(BlockError::ParentHashMismatch)
+            );
+
+            let mut wrong_extrinsics = block.clone();
+            wrong_extrinsics.extrinsics.clear();
+            assert_eq!(
+                runtime.execute_block(wrong_extrinsics),
+                Err(BlockError::ExtrinsicsRootMismatch)
+            );
+
+            let mut wrong_state = block.clone();
+            wrong_state.header.state_root = Hash::default();
+            assert_eq!(
+                runtime.execute_block(wrong_state),
+                Err(BlockError::StateRootMismatch)
+            );
+            assert_eq!(runtime.balances.balance(&alice.account()), 100);
+
+            let block_hash = block.header.hash();
+            assert_eq!(runtime.execute_block(block), Ok(()));
+            assert_eq!(runtime.system.block_hash(), block_hash);
+            assert_eq!(runtime.balances.balance(&alice.account()), 90 - fee);
+        });
+    }
+
+    #[test]
+    fn blocks_round_trip_through_the_codec() {
+        Storage::default().execute_with(|| {
+            let mut runtime = Runtime::new();
+            let alice = Pair::ed25519(&dev_seed("alice"));
+            let bob = Pair::sr25519(&dev_seed("bob"));
+            runtime.balances.set_balance(&alice.account(), 100);
+            runtime.balances.set_balance(&bob.account(), 100);
+
+            let claim = Extrinsic::new_signed(
+                RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                    claim: "Hello, world!".to_string(),
+                }),
+                &bob,
+                0,
+                &runtime.system.genesis_hash(),
+            );
+            let claim_fee = fee(&claim);
+            let extrinsics = vec![transfer(&runtime, &alice, 0, &bob, 10), claim];
+            let block = build_block(&mut runtime, Digest::default(), extrinsics);
+
+            let encoded = block.encode();
+            let decoded: Block = codec::decode_all(&encoded).expect("Valid encoding.");
+            assert_eq!(decoded.encode(), encoded);
+            assert_eq!(decoded.header, block.header);
+
+            // Anything short of the whole block is rejected.
+            assert!(codec::decode_all::<Block>(&encoded[..encoded.len() - 1]).is_err());
+
+            // The decoded block is as good as the original, signatures included.
+            assert_eq!(runtime.execute_block(decoded), Ok(()));
+            assert_eq!(runtime.balances.balance(&bob.account()), 110 - claim_fee);
+        });
+    }
+
+    #[test]
+    fn deeply_nested_calls_are_rejected() {
+        let mut call = RuntimeCall::balances(balances::Call::transfer {
+            receiver: Pair::ed25519(&dev_seed("bob")).account(),
+            amount: 10,
+        });
+        for _ in 1..codec::MAX_DEPTH {
+            call = RuntimeCall::sudo(sudo::Call::sudo {
+                call: Box::new(call),
+            });
+        }
+        let mut extrinsic: types::Extrinsic = Extrinsic {
+            signature: None,
+            call,
+        };
+        let encoded = extrinsic.encode();
+        assert!(codec::decode_all::<types::Extrinsic>(&encoded).is_ok());
+
+        extrinsic.call = RuntimeCall::utility(utility::Call::batch {
+            calls: vec![extrinsic.call],
+        });
+        assert_eq!(
+            codec::decode_all::<types::Extrinsic>(&extrinsic.encode()).map(|_| ()),
+            Err(codec::Error("value is nested too deeply"))
+        );
+
+        // An unsigned extrinsic of sudo calls, each wrapping the next, far too deep for the stack.
+        let mut encoded = vec![0x00];
+        for _ in 0..200_000 {
+            encoded.extend_from_slice(&[0x03, 0x00]);
+        }
+        assert_eq!(
+            codec::decode_all::<types::Extrinsic>(&encoded).map(|_| ()),
+            Err(codec::Error("value is nested too deeply"))
+        );
+    }
+
+    #[test]
+    fn node_restarts_from_database() {
+        let path = std::env::temp_dir().join(format!("node-restart-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+
+        let mut backend = Backend::open(FileDb::open(&path).unwrap());
+        assert!(backend.best_header().is_none());
+        let genesis = backend
+            .state()
+            .execute_with(|| ChainSpec::development().build_genesis(&runtime));
+        backend.commit_block(&genesis).unwrap();
+        let block_1 = backend.state().execute_with(|| {
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 10);
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block.clone()), Ok(()));
+            block
+        });
+        backend.commit_block(&block_1).unwrap();
+        drop(backend);
+
+        // The restarted node has the blocks and the state it had, and carries on from there.
+        let mut backend = Backend::open(FileDb::open(&path).unwrap());
+        assert_eq!(backend.best_header(), Some(block_1.header.clone()));
+        assert_eq!(backend.block_hash(0), Some(genesis.header.hash()));
+        let hash = backend.block_hash(1).unwrap();
+        assert_eq!(hash, block_1.header.hash());
+        assert_eq!(backend.block(&hash).unwrap().encode(), block_1.encode());
+        assert_eq!(backend.state().root(), block_1.header.state_root);
+
+        let block_2 = backend.state().execute_with(|| {
+            assert_eq!(runtime.system.genesis_hash(), genesis.header.hash());
+            assert_eq!(runtime.system.block_hash(), hash);
+            assert_eq!(runtime.balances.balance(&bob.account()), 1_000_010);
+            let extrinsic = transfer(&runtime, &alice, 1, &bob, 10);
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block.clone()), Ok(()));
+            block
+        });
+        backend.commit_block(&block_2).unwrap();
+        assert_eq!(backend.best_header(), Some(block_2.header));
+
+        drop(backend);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn state_root_commits_to_storage() {
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+
+        let block = state.execute_with(|| {
+            runtime.balances.set_balance(&alice.account(), 100);
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 10);
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block.clone()), Ok(()));
+            block
+        });
+
+        // The header commits to the storage the block left behind, and the same storage always
+        // gives the same root.
+        assert_eq!(block.header.state_root, state.root());
+        assert_eq!(state.clone().root(), state.root());
+    }
+
+    #[test]
+    fn light_client_verifies_storage_proofs() {
+        let mut state = Storage::default();
+        let mut runtime = Runtime::new();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+
+        let (header, fee) = state.execute_with(|| {
+            runtime.balances.set_balance(&alice.account(), 100);
+            let extrinsic = transfer(&runtime, &alice, 0, &bob, 10);
+            let fee = fee(&extrinsic);
+            let block = build_block(&mut runtime, Digest::default(), vec![extrinsic]);
+            assert_eq!(runtime.execute_block(block.clone()), Ok(()));
+            (block.header, fee)
+        });
+
+        let alice_key = runtime.balances.balance_key(&alice.account());
+        let alice_proof = state.read_proof(&alice_key);
+        assert_eq!(
+            light_client::verify_storage(&header, &alice_key, &alice_proof),
+            Ok(Some(90 - fee))
+        );
+
+        // Charlie has no balance, and that can be proven too.
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        let charlie_key = runtime.balances.balance_key(&charlie.account());
+        assert_eq!(
+            light_client::verify_storage::<_, Balance>(
+                &header,
+                &charlie_key,
+                &state.read_proof(&charlie_key)
+            ),
+            Ok(None)
+        );
 
-    println!("{runtime:#?}");
+        // A proof of alice's balance says nothing about bob's, nor about another state.
+        let bob_key = runtime.balances.balance_key(&bob.account());
+        assert!(
+            light_client::verify_storage::<_, Balance>(&header, &bob_key, &alice_proof).is_err()
+        );
+        let mut other_header = header.clone();
+        other_header.state_root = Hash::default();
+        assert_eq!(
+            light_client::verify_storage::<_, Balance>(&other_header, &alice_key, &alice_proof),
+            Err(light_client::Error::InvalidProof(
+                trie::ProofError::RootMismatch
+            ))
+        );
+    }
 }
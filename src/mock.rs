@@ -0,0 +1,62 @@
+//! A stand-in runtime for the tests of the pallets which dispatch calls: sudo, utility and
+//! multisig. It only dispatches the calls of the balances pallet, so the tests can check the
+//! origin and the rollback of the calls they wrap.
+
+use crate::balances;
+use crate::crypto::AccountId32;
+use crate::support::{Dispatch, DispatchResultWithPostInfo, Weight};
+use crate::system::OriginFor;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestConfig;
+
+impl crate::system::Config for TestConfig {
+    type AccountId = AccountId32;
+    type BlockNumber = u32;
+    type Nonce = u32;
+    type RuntimeEvent = crate::system::Event;
+    type PalletInfo = ();
+    const MAX_BLOCK_WEIGHT: Weight = Weight::MAX;
+    const EXTRINSIC_BASE_WEIGHT: Weight = 0;
+    const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+    type OnChargeTransaction = ();
+}
+
+impl balances::Config for TestConfig {
+    type Balance = u128;
+}
+
+impl crate::multisig::Config for TestConfig {
+    const DEPOSIT_BASE: u128 = 10;
+    const DEPOSIT_PER_BYTE: u128 = 1;
+    const MAX_SIGNATORIES: u16 = 3;
+}
+
+impl crate::sudo::Config for TestConfig {}
+
+impl crate::utility::Config for TestConfig {}
+
+impl Dispatch for TestConfig {
+    type RuntimeOrigin = OriginFor<Self>;
+    type Call = balances::Call<Self>;
+
+    fn dispatch(
+        &mut self,
+        origin: Self::RuntimeOrigin,
+        call: Self::Call,
+    ) -> DispatchResultWithPostInfo {
+        balances::Pallet::<Self>::new().dispatch(origin, call)
+    }
+
+    fn allows_unsigned(call: &Self::Call) -> bool {
+        <balances::Pallet<Self> as Dispatch>::allows_unsigned(call)
+    }
+
+    fn weight(call: &Self::Call) -> Weight {
+        <balances::Pallet<Self> as Dispatch>::weight(call)
+    }
+}
+
+pub const ALICE: AccountId32 = AccountId32([1; 32]);
+pub const BOB: AccountId32 = AccountId32([2; 32]);
+pub const CHARLIE: AccountId32 = AccountId32([3; 32]);
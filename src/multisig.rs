@@ -0,0 +1,621 @@
+//! Multisig: accounts controlled by M of N signatories.
+//!
+//! The account of a set of signatories and a threshold is derived from a hash of them, so no key
+//! controls it, and anyone can work it out. A call is dispatched from it once `threshold` of its
+//! signatories approved the call:
+//! - `as_multi` approves the call, and hands it over to be dispatched once there are enough
+//!   approvals.
+//! - `approve_as_multi` approves the call by its hash, which is all the other signatories need.
+//!
+//! Whichever approval reaches the threshold dispatches the call, as long as one of the approvals
+//! came with it. The first signatory to approve a call pays a deposit, which the balances pallet
+//! holds until the call is dispatched, or until that signatory cancels it with
+//! `cancel_as_multi`. The deposit grows with the length of the call, once the call is stored.
+
+use crate::balances;
+use crate::codec::{Decode, Encode};
+use crate::crypto::{blake2_256, Hash};
+use crate::storage::StorageMap;
+use crate::support::{
+    BuildGenesisConfig, DispatchError, DispatchNested, DispatchResult, DispatchResultWithPostInfo,
+    Hooks, PalletEvents, PostDispatchInfo, ValidateUnsigned, Weight,
+};
+use crate::system::{ensure_signed, OriginFor, RawOrigin};
+use core::marker::PhantomData;
+use num::{zero, CheckedAdd, CheckedMul, CheckedSub, Zero};
+use serde::{Deserialize, Serialize};
+
+/// Implemented by the runtime, which dispatches the approved calls.
+pub trait Config:
+    balances::Config<AccountId: From<Hash>, Balance: CheckedMul + From<u32>>
+    + DispatchNested<RuntimeOrigin = OriginFor<Self>>
+{
+    /// Held from the first signatory to approve a call, until it is dispatched or cancelled.
+    const DEPOSIT_BASE: Self::Balance;
+    /// Held on top of `DEPOSIT_BASE` for each byte of the encoded call, while it is stored.
+    const DEPOSIT_PER_BYTE: Self::Balance;
+    /// The most signatories a multisig account can have.
+    const MAX_SIGNATORIES: u16;
+}
+
+/// The weight of a multisig call, on top of the weight of the call it dispatches.
+const MULTISIG_WEIGHT: Weight = 5_000;
+
+/// Events emitted by the multisig pallet.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    /// `approving` started a multisig operation for the call with `call_hash`.
+    NewMultisig {
+        approving: T::AccountId,
+        multisig: T::AccountId,
+        call_hash: Hash,
+    },
+    /// `approving` approved the call with `call_hash`, which needs more approvals.
+    MultisigApproval {
+        approving: T::AccountId,
+        multisig: T::AccountId,
+        call_hash: Hash,
+    },
+    /// The approval of `approving` reached the threshold, so the call with `call_hash` was
+    /// dispatched from `multisig`, with `result`.
+    MultisigExecuted {
+        approving: T::AccountId,
+        multisig: T::AccountId,
+        call_hash: Hash,
+        result: DispatchResult,
+    },
+    /// `cancelling` cancelled the call with `call_hash`.
+    MultisigCancelled {
+        cancelling: T::AccountId,
+        multisig: T::AccountId,
+        call_hash: Hash,
+    },
+}
+
+/// Errors which can be raised by the multisig pallet.
+#[macros::error]
+pub enum Error<T> {
+    /// The threshold is less than 2, so a single signatory could act alone.
+    MinimumThreshold,
+    /// The threshold is more than the number of signatories.
+    TooFewSignatories,
+    /// There are more than `Config::MAX_SIGNATORIES` signatories.
+    TooManySignatories,
+    /// A signatory is listed twice, or the caller is listed among the other signatories.
+    DuplicateSignatory,
+    /// The caller already approved the call.
+    AlreadyApproved,
+    /// No one approved the call yet.
+    NotFound,
+    /// Only the signatory which paid the deposit can cancel the call.
+    NotOwner,
+    /// The call weighs more than the approval allows.
+    MaxWeightTooLow,
+}
+
+/// A call waiting for approvals.
+#[derive(Debug, Clone, Encode, Decode)]
+struct Multisig<T: Config> {
+    /// The signatory which paid the deposit.
+    depositor: T::AccountId,
+    deposit: T::Balance,
+    /// The signatories which approved the call, sorted.
+    approvals: Vec<T::AccountId>,
+    /// The call, once a signatory handed it over with `as_multi`.
+    call: Option<T::Call>,
+}
+
+/// The multisig pallet has no initial state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    #[serde(skip)]
+    _config: PhantomData<T>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self {
+            _config: PhantomData,
+        }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+    /// The calls waiting for approvals, by multisig account and call hash.
+    multisigs: StorageMap<(T::AccountId, Hash), Multisig<T>>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self {
+            multisigs: StorageMap::new("Multisig", "Multisigs"),
+        }
+    }
+
+    /// The account of `signatories`, of which `threshold` must approve every call. The order of
+    /// the signatories does not matter.
+    pub fn multi_account_id(signatories: &[T::AccountId], threshold: u16) -> T::AccountId {
+        let mut signatories = signatories.to_vec();
+        signatories.sort();
+        blake2_256(&(*b"multisig", signatories, threshold).encode()).into()
+    }
+
+    /// The multisig account of `who` and `other_signatories`, after checking they make up a valid
+    /// multisig with `threshold`.
+    fn multisig_of(
+        who: &T::AccountId,
+        threshold: u16,
+        mut other_signatories: Vec<T::AccountId>,
+    ) -> Result<T::AccountId, DispatchError> {
+        if threshold < 2 {
+            Err(Error::<T>::MinimumThreshold)?;
+        }
+        other_signatories.push(who.clone());
+        let mut signatories = other_signatories;
+        signatories.sort();
+        if signatories.windows(2).any(|pair| pair[0] == pair[1]) {
+            Err(Error::<T>::DuplicateSignatory)?;
+        }
+        if signatories.len() > T::MAX_SIGNATORIES as usize {
+            Err(Error::<T>::TooManySignatories)?;
+        }
+        if signatories.len() < threshold as usize {
+            Err(Error::<T>::TooFewSignatories)?;
+        }
+        Ok(Self::multi_account_id(&signatories, threshold))
+    }
+
+    /// The deposit held for a call waiting for approvals, which grows with the length of `call`
+    /// once it is stored.
+    fn deposit(call: Option<&T::Call>) -> Result<T::Balance, DispatchError> {
+        let len = call.map_or(0, |call| call.encode().len() as u32);
+        let deposit = T::DEPOSIT_PER_BYTE
+            .checked_mul(&len.into())
+            .and_then(|deposit| deposit.checked_add(&T::DEPOSIT_BASE))
+            .ok_or(balances::Error::<T>::BalanceOverflow)?;
+        Ok(deposit)
+    }
+
+    /// Count the approval of `who` for the call with `call_hash`, and keep `call` if it is given.
+    /// Dispatches the call if this reaches the threshold, unless it weighs more than
+    /// `max_weight`. Returns the weight the call used, if it was dispatched.
+    fn approve(
+        &mut self,
+        who: T::AccountId,
+        threshold: u16,
+        other_signatories: Vec<T::AccountId>,
+        call_hash: Hash,
+        call: Option<T::Call>,
+        max_weight: Weight,
+    ) -> Result<Weight, DispatchError> {
+        let multisig = Self::multisig_of(&who, threshold, other_signatories)?;
+        let key = (multisig.clone(), call_hash);
+        let (mut pending, is_new) = match self.multisigs.get(&key) {
+            Some(mut pending) => {
+                match pending.approvals.binary_search(&who) {
+                    // Handing over the call is the only reason to approve twice.
+                    Ok(_) if call.is_none() || pending.call.is_some() => {
+                        Err(Error::<T>::AlreadyApproved)?
+                    }
+                    Ok(_) => {}
+                    Err(index) => pending.approvals.insert(index, who.clone()),
+                }
+                (pending, false)
+            }
+            None => {
+                let pending = Multisig {
+                    depositor: who.clone(),
+                    deposit: zero(),
+                    approvals: vec![who.clone()],
+                    call: None,
+                };
+                (pending, true)
+            }
+        };
+        if pending.call.is_none() {
+            pending.call = call;
+        }
+
+        let call = match pending.call.take() {
+            Some(call) if pending.approvals.len() >= threshold as usize => call,
+            call => {
+                pending.call = call;
+                // The depositor approved this very call, so it pays for storing it too.
+                let deposit = Self::deposit(pending.call.as_ref())?;
+                let more = deposit.checked_sub(&pending.deposit);
+                if let Some(more) = more.filter(|more| !more.is_zero()) {
+                    balances::Pallet::<T>::new().reserve(&pending.depositor, more)?;
+                    pending.deposit = deposit;
+                }
+                self.multisigs.insert(&key, &pending);
+                let event = if is_new {
+                    Event::NewMultisig {
+                        approving: who,
+                        multisig,
+                        call_hash,
+                    }
+                } else {
+                    Event::MultisigApproval {
+                        approving: who,
+                        multisig,
+                        call_hash,
+                    }
+                };
+                self.deposit_event(event);
+                return Ok(0);
+            }
+        };
+
+        if T::weight(&call) > max_weight {
+            Err(Error::<T>::MaxWeightTooLow)?;
+        }
+        self.multisigs.remove(&key);
+        balances::Pallet::<T>::new().unreserve(&pending.depositor, pending.deposit)?;
+        // Like any other call, a failing call is rolled back, but the approval which dispatched it
+        // still succeeds.
+        let (result, weight) = T::dispatch_nested(RawOrigin::Signed(multisig.clone()), call);
+        self.deposit_event(Event::MultisigExecuted {
+            approving: who,
+            multisig,
+            call_hash,
+            result,
+        });
+        Ok(weight)
+    }
+}
+
+/// The post info of a multisig call which dispatched a call using `weight`.
+fn used(weight: Weight) -> PostDispatchInfo {
+    PostDispatchInfo {
+        actual_weight: Some(MULTISIG_WEIGHT.saturating_add(weight)),
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "Multisig";
+    type Event = Event<T>;
+}
+
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
+
+#[macros::call]
+impl<T: Config> Pallet<T> {
+    /// Approve `call` from the multisig account of the caller, `other_signatories` and
+    /// `threshold`. The call is dispatched once `threshold` signatories approved it.
+    #[allow(clippy::boxed_local)]
+    #[weight(MULTISIG_WEIGHT.saturating_add(T::weight(call)))]
+    pub fn as_multi(
+        &mut self,
+        origin: OriginFor<T>,
+        threshold: u16,
+        other_signatories: Vec<T::AccountId>,
+        call: Box<T::Call>,
+    ) -> DispatchResultWithPostInfo {
+        let who = ensure_signed(origin)?;
+        let call_hash = blake2_256(&call.encode());
+        let max_weight = T::weight(&call);
+        let weight = self.approve(
+            who,
+            threshold,
+            other_signatories,
+            call_hash,
+            Some(*call),
+            max_weight,
+        )?;
+        Ok(used(weight))
+    }
+
+    /// Approve the call with `call_hash` from the multisig account of the caller,
+    /// `other_signatories` and `threshold`. If this reaches the threshold, the call is dispatched,
+    /// as long as it weighs at most `max_weight`.
+    #[weight(MULTISIG_WEIGHT.saturating_add(*max_weight))]
+    pub fn approve_as_multi(
+        &mut self,
+        origin: OriginFor<T>,
+        threshold: u16,
+        other_signatories: Vec<T::AccountId>,
+        call_hash: Hash,
+        max_weight: Weight,
+    ) -> DispatchResultWithPostInfo {
+        let who = ensure_signed(origin)?;
+        let weight = self.approve(
+            who,
+            threshold,
+            other_signatories,
+            call_hash,
+            None,
+            max_weight,
+        )?;
+        Ok(used(weight))
+    }
+
+    /// Cancel the call with `call_hash`, and get the deposit back. Only the signatory which paid
+    /// the deposit can do this.
+    #[weight(MULTISIG_WEIGHT)]
+    pub fn cancel_as_multi(
+        &mut self,
+        origin: OriginFor<T>,
+        threshold: u16,
+        other_signatories: Vec<T::AccountId>,
+        call_hash: Hash,
+    ) -> DispatchResult {
+        let who = ensure_signed(origin)?;
+        let multisig = Self::multisig_of(&who, threshold, other_signatories)?;
+        let key = (multisig.clone(), call_hash);
+        let pending = self.multisigs.get(&key).ok_or(Error::<T>::NotFound)?;
+        if pending.depositor != who {
+            Err(Error::<T>::NotOwner)?;
+        }
+        self.multisigs.remove(&key);
+        balances::Pallet::<T>::new().unreserve(&who, pending.deposit)?;
+        self.deposit_event(Event::MultisigCancelled {
+            cancelling: who,
+            multisig,
+            call_hash,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Event, Pallet};
+    use crate::balances;
+    use crate::codec::Encode;
+    use crate::crypto::{blake2_256, AccountId32};
+    use crate::mock::{TestConfig, ALICE, BOB, CHARLIE};
+    use crate::storage::{with_transaction, Storage};
+    use crate::support::PalletEvents;
+    use crate::system::RawOrigin;
+
+    /// Alice, bob and charlie hold 100 each, and control a 2 of 3 multisig account holding 1000.
+    /// Returns the account, and a transfer of 500 from it to charlie.
+    fn setup() -> (
+        Pallet<TestConfig>,
+        balances::Pallet<TestConfig>,
+        AccountId32,
+        balances::Call<TestConfig>,
+    ) {
+        let mut balances = balances::Pallet::new();
+        for who in [ALICE, BOB, CHARLIE] {
+            balances.set_balance(&who, 100);
+        }
+        let multisig = Pallet::<TestConfig>::multi_account_id(&[ALICE, BOB, CHARLIE], 2);
+        balances.set_balance(&multisig, 1_000);
+        let call = balances::Call::transfer {
+            receiver: CHARLIE,
+            amount: 500,
+        };
+        (Pallet::new(), balances, multisig, call)
+    }
+
+    #[test]
+    fn multi_account_id_is_deterministic() {
+        let id = Pallet::<TestConfig>::multi_account_id(&[ALICE, BOB, CHARLIE], 2);
+        assert_eq!(
+            Pallet::<TestConfig>::multi_account_id(&[CHARLIE, ALICE, BOB], 2),
+            id
+        );
+        assert_ne!(
+            Pallet::<TestConfig>::multi_account_id(&[ALICE, BOB, CHARLIE], 3),
+            id
+        );
+        assert_ne!(Pallet::<TestConfig>::multi_account_id(&[ALICE, BOB], 2), id);
+    }
+
+    #[test]
+    fn calls_are_dispatched_once_the_threshold_is_met() {
+        Storage::default().execute_with(|| {
+            let (mut multisig, mut balances, id, call) = setup();
+            let call_hash = blake2_256(&call.encode());
+            let deposit = 10 + call.encode().len() as u128;
+
+            // Alice hands over the call, and pays the deposit for it.
+            assert!(multisig
+                .as_multi(
+                    RawOrigin::Signed(ALICE),
+                    2,
+                    vec![BOB, CHARLIE],
+                    Box::new(call.clone())
+                )
+                .is_ok());
+            assert_eq!(balances.balance(&ALICE), 100 - deposit);
+            assert_eq!(balances.reserved_balance(&ALICE), deposit);
+            assert_eq!(balances.balance(&CHARLIE), 100);
+
+            // Charlie approves it by its hash, which dispatches it.
+            assert!(multisig
+                .approve_as_multi(
+                    RawOrigin::Signed(CHARLIE),
+                    2,
+                    vec![ALICE, BOB],
+                    call_hash,
+                    10_000
+                )
+                .is_ok());
+            assert_eq!(balances.balance(&id), 500);
+            assert_eq!(balances.balance(&CHARLIE), 600);
+            assert_eq!(balances.balance(&ALICE), 100);
+            assert_eq!(balances.reserved_balance(&ALICE), 0);
+            assert_eq!(
+                multisig.take_events(),
+                vec![
+                    Event::NewMultisig {
+                        approving: ALICE,
+                        multisig: id,
+                        call_hash
+                    },
+                    Event::MultisigExecuted {
+                        approving: CHARLIE,
+                        multisig: id,
+                        call_hash,
+                        result: Ok(())
+                    },
+                ]
+            );
+            assert!(multisig.multisigs.get(&(id, call_hash)).is_none());
+        });
+    }
+
+    #[test]
+    fn calls_wait_for_the_call_and_enough_weight() {
+        Storage::default().execute_with(|| {
+            let (mut multisig, mut balances, id, call) = setup();
+            let call_hash = blake2_256(&call.encode());
+
+            // Two approvals by hash are not enough without the call.
+            for (who, others) in [(BOB, vec![ALICE, CHARLIE]), (CHARLIE, vec![ALICE, BOB])] {
+                assert!(multisig
+                    .approve_as_multi(RawOrigin::Signed(who), 2, others, call_hash, 10_000)
+                    .is_ok());
+            }
+            assert_eq!(balances.balance(&id), 1_000);
+            assert_eq!(
+                multisig.approve_as_multi(
+                    RawOrigin::Signed(BOB),
+                    2,
+                    vec![ALICE, CHARLIE],
+                    call_hash,
+                    10_000
+                ),
+                Err(Error::<TestConfig>::AlreadyApproved.into())
+            );
+
+            // Bob already approved, but can still hand over the call, which dispatches it.
+            assert!(multisig
+                .as_multi(
+                    RawOrigin::Signed(BOB),
+                    2,
+                    vec![ALICE, CHARLIE],
+                    Box::new(call.clone())
+                )
+                .is_ok());
+            assert_eq!(balances.balance(&id), 500);
+            assert_eq!(balances.balance(&BOB), 100);
+
+            // An approval must allow for the weight of the call it dispatches.
+            assert!(multisig
+                .as_multi(
+                    RawOrigin::Signed(ALICE),
+                    2,
+                    vec![BOB, CHARLIE],
+                    Box::new(call)
+                )
+                .is_ok());
+            let result = with_transaction(|| {
+                multisig.approve_as_multi(
+                    RawOrigin::Signed(BOB),
+                    2,
+                    vec![ALICE, CHARLIE],
+                    call_hash,
+                    9_999,
+                )
+            });
+            assert_eq!(
+                result.map_err(|error| error.error),
+                Err(Error::<TestConfig>::MaxWeightTooLow.into())
+            );
+            assert_eq!(balances.balance(&id), 500);
+        });
+    }
+
+    #[test]
+    fn signatories_are_checked() {
+        Storage::default().execute_with(|| {
+            let (mut multisig, _, _, call) = setup();
+            let call_hash = blake2_256(&call.encode());
+            let approve = |multisig: &mut Pallet<TestConfig>, threshold, others| {
+                multisig
+                    .approve_as_multi(RawOrigin::Signed(ALICE), threshold, others, call_hash, 0)
+                    .map_err(|error| error.error)
+            };
+            assert_eq!(
+                approve(&mut multisig, 1, vec![BOB]),
+                Err(Error::<TestConfig>::MinimumThreshold.into())
+            );
+            assert_eq!(
+                approve(&mut multisig, 3, vec![BOB]),
+                Err(Error::<TestConfig>::TooFewSignatories.into())
+            );
+            assert_eq!(
+                approve(&mut multisig, 2, vec![BOB, ALICE]),
+                Err(Error::<TestConfig>::DuplicateSignatory.into())
+            );
+            assert_eq!(
+                approve(&mut multisig, 2, vec![BOB, CHARLIE, AccountId32([4; 32])]),
+                Err(Error::<TestConfig>::TooManySignatories.into())
+            );
+        });
+    }
+
+    #[test]
+    fn depositor_can_cancel() {
+        Storage::default().execute_with(|| {
+            let (mut multisig, mut balances, id, call) = setup();
+            let call_hash = blake2_256(&call.encode());
+            assert_eq!(
+                multisig.cancel_as_multi(RawOrigin::Signed(ALICE), 2, vec![BOB], call_hash),
+                Err(Error::<TestConfig>::NotFound.into())
+            );
+
+            assert!(multisig
+                .approve_as_multi(
+                    RawOrigin::Signed(ALICE),
+                    2,
+                    vec![BOB, CHARLIE],
+                    call_hash,
+                    0
+                )
+                .is_ok());
+            assert_eq!(balances.reserved_balance(&ALICE), 10);
+            // Handing over the call later makes the deposit grow with it.
+            assert!(multisig
+                .as_multi(
+                    RawOrigin::Signed(ALICE),
+                    2,
+                    vec![BOB, CHARLIE],
+                    Box::new(call.clone())
+                )
+                .is_ok());
+            let deposit = 10 + call.encode().len() as u128;
+            assert_eq!(balances.balance(&ALICE), 100 - deposit);
+            assert_eq!(balances.reserved_balance(&ALICE), deposit);
+            assert_eq!(
+                multisig.cancel_as_multi(
+                    RawOrigin::Signed(BOB),
+                    2,
+                    vec![ALICE, CHARLIE],
+                    call_hash
+                ),
+                Err(Error::<TestConfig>::NotOwner.into())
+            );
+            assert_eq!(
+                multisig.cancel_as_multi(
+                    RawOrigin::Signed(ALICE),
+                    2,
+                    vec![BOB, CHARLIE],
+                    call_hash
+                ),
+                Ok(())
+            );
+            assert_eq!(balances.balance(&ALICE), 100);
+            assert_eq!(balances.reserved_balance(&ALICE), 0);
+            assert_eq!(
+                multisig.take_events().pop(),
+                Some(Event::MultisigCancelled {
+                    cancelling: ALICE,
+                    multisig: id,
+                    call_hash
+                })
+            );
+        });
+    }
+}
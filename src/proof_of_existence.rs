@@ -1,82 +1,195 @@
-use crate::support::{DispatchResult};
+use crate::codec::{Decode, Encode};
+use crate::storage::StorageMap;
+use crate::support::{BuildGenesisConfig, DispatchResult, Hooks, PalletEvents, ValidateUnsigned};
+use crate::system::{ensure_signed, OriginFor};
 use core::fmt::Debug;
-use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 pub trait Config: crate::system::Config {
-    type Content: Debug + Ord;
+    type Content: Debug + Ord + Clone + Encode + Decode + Serialize + DeserializeOwned;
 }
 
-#[derive(Debug)]
+/// Events emitted by the proof of existence pallet.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    /// `who` claimed `claim`.
+    ClaimCreated {
+        who: T::AccountId,
+        claim: T::Content,
+    },
+    /// `who` revoked their claim on `claim`.
+    ClaimRevoked {
+        who: T::AccountId,
+        claim: T::Content,
+    },
+}
+
+/// Errors which can be raised by the proof of existence pallet.
+#[macros::error]
+pub enum Error<T> {
+    /// The content has already been claimed.
+    AlreadyClaimed,
+    /// The content has not been claimed.
+    ClaimNotFound,
+    /// The content was claimed by someone other than the caller.
+    NotClaimOwner,
+}
+
+/// The initial state of the proof of existence pallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    /// The content which is claimed from the start, and the owner of each claim.
+    pub claims: Vec<(T::Content, T::AccountId)>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self { claims: Vec::new() }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {
+        let pallet = Pallet::<T>::new();
+        for (claim, owner) in &self.claims {
+            pallet.claims.insert(claim, owner);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pallet<T: Config> {
-    claims: BTreeMap<T::Content, T::AccountId>,
+    claims: StorageMap<T::Content, T::AccountId>,
 }
 
 impl<T: Config> Pallet<T> {
     pub fn new() -> Self {
         Self {
-            claims: BTreeMap::new(),
+            claims: StorageMap::new("ProofOfExistence", "Claims"),
         }
     }
 
-    pub fn _get_claim(&self, claim: &T::Content) -> Option<&T::AccountId> {
-        self.claims.get(&claim)
+    pub fn get_claim(&self, claim: &T::Content) -> Option<T::AccountId> {
+        self.claims.get(claim)
     }
+
+    /// The storage key of the owner of `claim`, to prove it to a light client.
+    pub fn claim_key(&self, claim: &T::Content) -> Vec<u8> {
+        self.claims.hashed_key_for(claim)
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "ProofOfExistence";
+    type Event = Event<T>;
 }
 
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
+
 #[macros::call]
 impl<T: Config> Pallet<T> {
-    pub fn create_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
+    #[weight(5_000)]
+    pub fn create_claim(&mut self, origin: OriginFor<T>, claim: T::Content) -> DispatchResult {
+        let caller = ensure_signed(origin)?;
         if self.claims.contains_key(&claim) {
-            Err("this content is already claimed")?;
+            Err(Error::<T>::AlreadyClaimed)?;
         }
-        self.claims.insert(claim, caller);
+        self.claims.insert(&claim, &caller);
+        self.deposit_event(Event::ClaimCreated { who: caller, claim });
         Ok(())
     }
 
-    pub fn revoke_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
+    #[weight(5_000)]
+    pub fn revoke_claim(&mut self, origin: OriginFor<T>, claim: T::Content) -> DispatchResult {
+        let caller = ensure_signed(origin)?;
         let owner = self.claims.get(&claim);
         match owner {
             None => {
-                Err("Claim does not exist")?;
+                Err(Error::<T>::ClaimNotFound)?;
             }
             Some(owner) => {
-                if *owner != caller {
-                    Err("Claim does belong to caller")?;
+                if owner != caller {
+                    Err(Error::<T>::NotClaimOwner)?;
                 }
                 self.claims.remove(&claim);
             }
         }
+        self.deposit_event(Event::ClaimRevoked { who: caller, claim });
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Pallet;
+    use super::{Error, Event, Pallet};
+    use crate::storage::Storage;
+    use crate::support::PalletEvents;
+    use crate::system::RawOrigin;
 
+    #[derive(Debug, Clone, PartialEq)]
     struct TestConfig;
 
     impl super::Config for TestConfig {
-        type Content = &'static str;
+        type Content = String;
     }
 
     impl crate::system::Config for TestConfig {
-        type AccountId = &'static str;
+        type AccountId = String;
         type BlockNumber = u32;
         type Nonce = u32;
+        type RuntimeEvent = crate::system::Event;
+        type PalletInfo = ();
+        const MAX_BLOCK_WEIGHT: crate::support::Weight = crate::support::Weight::MAX;
+        const EXTRINSIC_BASE_WEIGHT: crate::support::Weight = 0;
+        const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+        type OnChargeTransaction = ();
     }
 
     #[test]
     fn basic_proof_of_existence() {
-        let mut poe = Pallet::<TestConfig>::new();
-        assert_eq!(poe._get_claim(&"Hello, world!"), None);
-        assert_eq!(poe.create_claim("alice", "Hello, world!"), Ok(()));
-        assert_eq!(poe._get_claim(&"Hello, world!"), Some(&"alice"));
-        assert_eq!(
-            poe.create_claim("bob", "Hello, world!"),
-            Err("this content is already claimed")
-        );
-        assert_eq!(poe.revoke_claim("alice", "Hello, world!"), Ok(()));
-        assert_eq!(poe.create_claim("bob", "Hello, world!"), Ok(()));
+        Storage::default().execute_with(|| {
+            let mut poe = Pallet::<TestConfig>::new();
+            let alice = "alice".to_string();
+            let bob = "bob".to_string();
+            let signed = |who: &String| RawOrigin::Signed(who.clone());
+            let claim = "Hello, world!".to_string();
+
+            assert_eq!(poe.get_claim(&claim), None);
+            assert_eq!(poe.create_claim(signed(&alice), claim.clone()), Ok(()));
+            assert_eq!(poe.get_claim(&claim), Some(alice.clone()));
+            assert_eq!(
+                poe.create_claim(signed(&bob), claim.clone()),
+                Err(Error::<TestConfig>::AlreadyClaimed.into())
+            );
+            assert_eq!(
+                poe.revoke_claim(signed(&bob), claim.clone()),
+                Err(Error::<TestConfig>::NotClaimOwner.into())
+            );
+            assert_eq!(poe.revoke_claim(signed(&alice), claim.clone()), Ok(()));
+            assert_eq!(
+                poe.revoke_claim(signed(&alice), claim.clone()),
+                Err(Error::<TestConfig>::ClaimNotFound.into())
+            );
+            assert_eq!(poe.create_claim(signed(&bob), claim.clone()), Ok(()));
+
+            assert_eq!(
+                poe.take_events(),
+                vec![
+                    Event::ClaimCreated {
+                        who: alice.clone(),
+                        claim: claim.clone()
+                    },
+                    Event::ClaimRevoked {
+                        who: alice,
+                        claim: claim.clone()
+                    },
+                    Event::ClaimCreated { who: bob, claim },
+                ]
+            );
+        });
     }
 }
@@ -0,0 +1,717 @@
+//! A JSON-RPC 2.0 server, for frontends to query the chain and submit extrinsics.
+//!
+//! Requests are served on a single local port, either over HTTP, as the body of a `POST`, or over
+//! WebSocket, one request per text message. Hashes, storage keys and values, and SCALE encoded
+//! extrinsics are written as `0x`-prefixed hex, and accounts the same way as in chain specs.
+//! Balances are decimal strings, since they can be larger than what JSON numbers hold exactly.
+//!
+//! Methods, whose parameters are positional:
+//! - `chain_getHeader([hash])` and `chain_getBlock([hash])` - the header or the block `hash`, or
+//!   the best one, or `null` if it is not known. A block comes with its justification, once it is
+//!   finalized.
+//! - `state_getStorage(key, [hash])` - the value at `key` in the state after the block `hash`, or
+//!   after the best block.
+//! - `system_accountNonce(account)`, `balances_freeBalance(account)` and `poe_getClaim(claim)` -
+//!   read from the state after the best block.
+//! - `author_submitExtrinsic(extrinsic)` - validate the extrinsic and add it to the transaction
+//!   pool, returning its hash.
+//!
+//! Every client is bounded: in the number of connections open at once, in the size of the head
+//! and the body of its requests, and in how long it may take to send each of them in full.
+
+use crate::backend::Backend;
+use crate::codec::{decode_all, Encode};
+use crate::crypto::{fmt_hex, parse_hex, Hash};
+use crate::database::Database;
+use crate::transaction_pool::TransactionPool;
+use crate::types::{AccountId, Content, Extrinsic, Header};
+use crate::Runtime;
+use core::fmt;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::{Role, WebSocketConfig};
+use tungstenite::{Message, WebSocket};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The extrinsic was not added to the pool.
+const INVALID_TRANSACTION: i64 = 1010;
+/// The state after the requested block is not kept.
+const UNKNOWN_STATE: i64 = 4003;
+
+/// The largest HTTP request body or WebSocket message served, in bytes.
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+/// The longest request line or header line served, in bytes.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+/// The most headers a request can have.
+const MAX_HEADERS: usize = 64;
+/// The most connections served at once. Connections beyond these are turned away.
+const MAX_CONNECTIONS: usize = 64;
+/// How long a client can take to send a whole request, head and body, or the next WebSocket
+/// message, before its connection is closed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the methods read and write: the chain kept by the node, and its transaction pool.
+#[derive(Debug)]
+pub struct Chain<D> {
+    pub backend: Backend<D>,
+    pub runtime: Runtime,
+    pub pool: TransactionPool,
+}
+
+/// An error response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Shows bytes as `0x`-prefixed hex.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(self.0, f)
+    }
+}
+
+fn hex(bytes: &[u8]) -> Value {
+    Value::String(Hex(bytes).to_string())
+}
+
+fn header_json(header: &Header) -> Value {
+    let logs = header
+        .digest
+        .logs
+        .iter()
+        .map(|log| hex(&log.encode()))
+        .collect::<Vec<_>>();
+    json!({
+        "parentHash": hex(&header.parent_hash),
+        "number": header.block_number,
+        "stateRoot": hex(&header.state_root),
+        "extrinsicsRoot": hex(&header.extrinsics_root),
+        "digest": { "logs": logs },
+    })
+}
+
+/// The parameter at `index`, which is required.
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<T, RpcError> {
+    let param = params
+        .get(index)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Missing parameter {index}")))?;
+    serde_json::from_value(param.clone())
+        .map_err(|error| RpcError::new(INVALID_PARAMS, format!("Parameter {index}: {error}")))
+}
+
+/// The parameter at `index`, as hex.
+fn bytes_param(params: &[Value], index: usize) -> Result<Vec<u8>, RpcError> {
+    parse_hex(&param::<String>(params, index)?).ok_or_else(|| {
+        RpcError::new(
+            INVALID_PARAMS,
+            format!("Parameter {index}: expected 0x-prefixed hex"),
+        )
+    })
+}
+
+/// The block hash at `index`, which is optional.
+fn hash_param(params: &[Value], index: usize) -> Result<Option<Hash>, RpcError> {
+    if params.get(index).is_none_or(Value::is_null) {
+        return Ok(None);
+    }
+    let hash = bytes_param(params, index)?.try_into().map_err(|_| {
+        RpcError::new(
+            INVALID_PARAMS,
+            format!("Parameter {index}: expected a 32 byte hash"),
+        )
+    })?;
+    Ok(Some(hash))
+}
+
+impl<D: Database> Chain<D> {
+    /// Answer the JSON-RPC `request`.
+    pub fn handle(&mut self, request: &str) -> String {
+        let (id, result) = match serde_json::from_str::<Value>(request) {
+            Err(_) => (Value::Null, Err(RpcError::new(PARSE_ERROR, "Parse error"))),
+            Ok(request) => {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                let method = request.get("method").and_then(Value::as_str);
+                let params = match request.get("params") {
+                    None => Some(Vec::new()),
+                    Some(Value::Array(params)) => Some(params.clone()),
+                    Some(_) => None,
+                };
+                let result = match (request.get("jsonrpc"), method, params) {
+                    (Some(version), Some(method), Some(params)) if version == "2.0" => {
+                        self.call(method, &params)
+                    }
+                    _ => Err(RpcError::new(INVALID_REQUEST, "Invalid request")),
+                };
+                (id, result)
+            }
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(RpcError { code, message }) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        response.to_string()
+    }
+
+    fn call(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "chain_getHeader" => {
+                let header = self.block_hash(hash_param(params, 0)?);
+                let header = header.and_then(|hash| self.backend.header(&hash));
+                Ok(header.map_or(Value::Null, |header| header_json(&header)))
+            }
+            "chain_getBlock" => {
+                let Some(hash) = self.block_hash(hash_param(params, 0)?) else {
+                    return Ok(Value::Null);
+                };
+                let Some(block) = self.backend.block(&hash) else {
+                    return Ok(Value::Null);
+                };
+                let extrinsics = block
+                    .extrinsics
+                    .iter()
+                    .map(|extrinsic| hex(&extrinsic.encode()))
+                    .collect::<Vec<_>>();
+                let justification = self.backend.justification(&hash);
+                Ok(json!({
+                    "block": { "header": header_json(&block.header), "extrinsics": extrinsics },
+                    "justification": justification.map(|justification| hex(&justification.encode())),
+                }))
+            }
+            "state_getStorage" => {
+                let key = bytes_param(params, 0)?;
+                let state = match hash_param(params, 1)? {
+                    None => Some(&*self.backend.state()),
+                    Some(hash) => self.backend.state_at(&hash),
+                };
+                let state = state.ok_or_else(|| {
+                    RpcError::new(UNKNOWN_STATE, "The state after the block is not kept")
+                })?;
+                Ok(state.get(&key).map_or(Value::Null, hex))
+            }
+            "system_accountNonce" => {
+                let account: AccountId = param(params, 0)?;
+                Ok(json!(self.query(|runtime| runtime.system.nonce(&account))))
+            }
+            "balances_freeBalance" => {
+                let account: AccountId = param(params, 0)?;
+                let balance = self.query(|runtime| runtime.balances.balance(&account));
+                Ok(json!(balance.to_string()))
+            }
+            "poe_getClaim" => {
+                let claim: Content = param(params, 0)?;
+                let owner = self.query(|runtime| runtime.proof_of_existence.get_claim(&claim));
+                Ok(json!(owner))
+            }
+            "author_submitExtrinsic" => {
+                let extrinsic: Extrinsic = decode_all(&bytes_param(params, 0)?)
+                    .map_err(|_| RpcError::new(INVALID_PARAMS, "Parameter 0: invalid extrinsic"))?;
+                let Self {
+                    backend,
+                    runtime,
+                    pool,
+                } = self;
+                let hash = backend
+                    .state()
+                    .execute_with(|| pool.submit(runtime, extrinsic))
+                    .map_err(|error| {
+                        RpcError::new(
+                            INVALID_TRANSACTION,
+                            format!("Invalid transaction: {error:?}"),
+                        )
+                    })?;
+                Ok(hex(&hash))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+        }
+    }
+
+    /// `hash`, or the hash of the best block.
+    fn block_hash(&self, hash: Option<Hash>) -> Option<Hash> {
+        hash.or_else(|| self.backend.best_header().map(|header| header.hash()))
+    }
+
+    /// Run `f` against the state after the best block.
+    fn query<R>(&mut self, f: impl FnOnce(&mut Runtime) -> R) -> R {
+        let Self {
+            backend, runtime, ..
+        } = self;
+        backend.state().execute_with(|| f(runtime))
+    }
+}
+
+/// Counts a connection being served, until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A connection whose reads fail once its deadline passes, however slowly the client trickles its
+/// bytes in: the timeout of the socket is the time left before each read.
+struct Deadline {
+    stream: TcpStream,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl Deadline {
+    /// `stream`, with `timeout` to send its first request.
+    fn new(stream: TcpStream, timeout: Duration) -> Self {
+        Self {
+            stream,
+            timeout,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Give the client another `timeout` to send its next request.
+    fn restart(&mut self) {
+        self.deadline = Instant::now() + self.timeout;
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        // A socket which times out reports `WouldBlock` on some platforms.
+        match self.stream.read(buf) {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::ErrorKind::TimedOut.into())
+            }
+            result => result,
+        }
+    }
+}
+
+impl Write for Deadline {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Serve the requests of every connection made to `listener`, each on its own thread, until the
+/// listener fails. At most `MAX_CONNECTIONS` are served at once.
+pub fn serve<D: Database + Send + 'static>(
+    listener: TcpListener,
+    chain: Arc<Mutex<Chain<D>>>,
+) -> io::Result<()> {
+    let connections = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, _) = listener.accept()?;
+        if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            let _ = respond(stream, "503 Service Unavailable", "");
+            continue;
+        }
+        connections.fetch_add(1, Ordering::SeqCst);
+        let connection = Connection(connections.clone());
+        let chain = chain.clone();
+        // A connection which fails only affects its own client.
+        thread::spawn(move || {
+            let _connection = connection;
+            let _ = serve_connection(stream, &chain, REQUEST_TIMEOUT);
+        });
+    }
+}
+
+/// Read a line of at most `MAX_LINE_LENGTH` bytes. `None` if it is longer.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let length = reader
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_line(&mut line)?;
+    Ok((length <= MAX_LINE_LENGTH).then_some(line))
+}
+
+/// The request line of a request, and its headers, with lowercase names.
+type Head = (String, Vec<(String, String)>);
+
+/// Read the head of a request. `None` if it has a line which is too long, or too many headers.
+fn read_head(reader: &mut impl BufRead) -> io::Result<Option<Head>> {
+    let Some(request_line) = read_line(reader)? else {
+        return Ok(None);
+    };
+    let mut headers = Vec::new();
+    loop {
+        let Some(line) = read_line(reader)? else {
+            return Ok(None);
+        };
+        if line.trim().is_empty() {
+            return Ok(Some((request_line, headers)));
+        }
+        if headers.len() == MAX_HEADERS {
+            return Ok(None);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+}
+
+/// Send an HTTP response with `status` and a JSON `body`, and close the connection.
+fn respond(mut stream: impl Write, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Serve one HTTP request, or every request sent over a WebSocket once the connection is upgraded.
+/// The connection is closed if the client takes longer than `timeout` to send a request.
+fn serve_connection<D: Database>(
+    stream: TcpStream,
+    chain: &Mutex<Chain<D>>,
+    timeout: Duration,
+) -> io::Result<()> {
+    let handle = |request: &str| {
+        let mut chain = chain.lock().unwrap_or_else(PoisonError::into_inner);
+        chain.handle(request)
+    };
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(Deadline::new(stream, timeout));
+    let Some((request_line, headers)) = read_head(&mut reader)? else {
+        return respond(
+            reader.into_inner(),
+            "431 Request Header Fields Too Large",
+            "",
+        );
+    };
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    if header("upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+        let key = header("sec-websocket-key")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no websocket key"))?;
+        // The client waits for the handshake before sending frames, so nothing is buffered.
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(key.as_bytes())
+        )?;
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(MAX_BODY_LENGTH))
+            .max_frame_size(Some(MAX_BODY_LENGTH));
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, Some(config));
+        loop {
+            socket.get_mut().restart();
+            match socket.read().map_err(io::Error::other)? {
+                Message::Text(request) => socket
+                    .send(Message::text(handle(request.as_str())))
+                    .map_err(io::Error::other)?,
+                Message::Close(_) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    let length = header("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    let (status, body) = if !request_line.starts_with("POST ") {
+        ("405 Method Not Allowed", String::new())
+    } else if length > MAX_BODY_LENGTH {
+        ("413 Payload Too Large", String::new())
+    } else {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        let request = String::from_utf8_lossy(&body);
+        ("200 OK", handle(&request))
+    };
+    respond(reader.into_inner(), status, &body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        serve, serve_connection, Chain, Hex, MAX_CONNECTIONS, MAX_HEADERS, MAX_LINE_LENGTH,
+    };
+    use crate::backend::Backend;
+    use crate::chain_spec::ChainSpec;
+    use crate::codec::Encode;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::database::MemoryDb;
+    use crate::transaction_pool::TransactionPool;
+    use crate::{balances, support, types, Runtime, RuntimeCall};
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn new_chain() -> Chain<MemoryDb> {
+        let mut backend = Backend::open(MemoryDb::default());
+        let runtime = Runtime::new();
+        let genesis = backend
+            .state()
+            .execute_with(|| ChainSpec::development().build_genesis(&runtime));
+        backend.commit_block(&genesis).unwrap();
+        Chain {
+            backend,
+            runtime,
+            pool: TransactionPool::new(),
+        }
+    }
+
+    /// Call `method` with `params`, and return the response.
+    fn call(chain: &mut Chain<MemoryDb>, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        serde_json::from_str(&chain.handle(&request.to_string())).unwrap()
+    }
+
+    #[test]
+    fn methods_read_the_chain() {
+        let mut chain = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let genesis = chain.backend.best_header().unwrap();
+        let genesis_hash = Hex(&genesis.hash()).to_string();
+
+        let header = call(&mut chain, "chain_getHeader", json!([]));
+        assert_eq!(header["result"]["number"], 0);
+        assert_eq!(
+            call(&mut chain, "chain_getHeader", json!([genesis_hash]))["result"],
+            header["result"]
+        );
+        let block = call(&mut chain, "chain_getBlock", json!([genesis_hash]));
+        assert_eq!(block["result"]["block"]["header"], header["result"]);
+        assert_eq!(block["result"]["block"]["extrinsics"], json!([]));
+        assert_eq!(block["result"]["justification"], Value::Null);
+        let unknown = format!("0x{}", "07".repeat(32));
+        assert_eq!(
+            call(&mut chain, "chain_getBlock", json!([unknown]))["result"],
+            Value::Null
+        );
+
+        let account = json!(alice.account());
+        assert_eq!(
+            call(&mut chain, "balances_freeBalance", json!([account]))["result"],
+            "1000000"
+        );
+        assert_eq!(
+            call(&mut chain, "system_accountNonce", json!([account]))["result"],
+            0
+        );
+        assert_eq!(
+            call(&mut chain, "poe_getClaim", json!(["Hello, world!"]))["result"],
+            account
+        );
+        assert_eq!(
+            call(&mut chain, "poe_getClaim", json!(["ggs"]))["result"],
+            Value::Null
+        );
+
+        // The balance of alice is stored SCALE encoded.
+        let key = chain.runtime.balances.balance_key(&alice.account());
+        let key = Hex(&key).to_string();
+        assert_eq!(
+            call(&mut chain, "state_getStorage", json!([key, genesis_hash]))["result"],
+            Hex(&1_000_000u128.encode()).to_string()
+        );
+        assert_eq!(
+            call(&mut chain, "state_getStorage", json!([key, unknown]))["error"]["code"],
+            4003
+        );
+    }
+
+    #[test]
+    fn extrinsics_are_submitted_to_the_pool() {
+        let mut chain = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        let genesis_hash = chain.backend.best_header().unwrap().hash();
+        let extrinsic: types::Extrinsic = support::Extrinsic::new_signed(
+            RuntimeCall::balances(balances::Call::transfer {
+                receiver: bob.account(),
+                amount: 10,
+            }),
+            &alice,
+            0,
+            &genesis_hash,
+        );
+        let encoded = Hex(&extrinsic.encode()).to_string();
+
+        let response = call(&mut chain, "author_submitExtrinsic", json!([encoded]));
+        assert!(response["result"].is_string());
+        assert_eq!(chain.pool.status().ready, 1);
+        let response = call(&mut chain, "author_submitExtrinsic", json!([encoded]));
+        assert_eq!(response["error"]["code"], 1010);
+        let response = call(&mut chain, "author_submitExtrinsic", json!(["0x00"]));
+        assert_eq!(response["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        let mut chain = new_chain();
+        let error = |chain: &mut Chain<MemoryDb>, request: &str| {
+            let response: Value = serde_json::from_str(&chain.handle(request)).unwrap();
+            response["error"]["code"].clone()
+        };
+        assert_eq!(error(&mut chain, "{"), -32700);
+        assert_eq!(error(&mut chain, r#"{"id": 1, "method": "x"}"#), -32600);
+        assert_eq!(
+            error(&mut chain, r#"{"jsonrpc": "2.0", "id": 1, "method": "x"}"#),
+            -32601
+        );
+        assert_eq!(
+            error(
+                &mut chain,
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "system_accountNonce"}"#
+            ),
+            -32602
+        );
+    }
+
+    #[test]
+    fn requests_are_served_over_http_and_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let chain = Arc::new(Mutex::new(new_chain()));
+        thread::spawn(move || serve(listener, chain));
+        let request = json!({ "jsonrpc": "2.0", "id": 7, "method": "chain_getHeader" }).to_string();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{request}",
+            request.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["id"], 7);
+        assert_eq!(body["result"]["number"], 0);
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{address}/"), stream).unwrap();
+        for _ in 0..2 {
+            socket
+                .send(tungstenite::Message::text(request.clone()))
+                .unwrap();
+            let response = socket.read().unwrap();
+            let response: Value = serde_json::from_str(response.to_text().unwrap()).unwrap();
+            assert_eq!(response, body);
+        }
+    }
+
+    #[test]
+    fn clients_are_bounded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let chain = Arc::new(Mutex::new(new_chain()));
+        thread::spawn(move || serve(listener, chain));
+        let send = |request: &[u8]| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let too_large = "HTTP/1.1 431 Request Header Fields Too Large\r\n";
+
+        let long_line = vec![b'G'; MAX_LINE_LENGTH + 1];
+        assert!(send(&long_line).starts_with(too_large));
+        let mut many_headers = b"POST / HTTP/1.1\r\n".to_vec();
+        for _ in 0..=MAX_HEADERS {
+            many_headers.extend_from_slice(b"X-Header: value\r\n");
+        }
+        many_headers.extend_from_slice(b"\r\n");
+        assert!(send(&many_headers).starts_with(too_large));
+
+        // Connections beyond the limit are turned away, until others are closed.
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "chain_getHeader" }).to_string();
+        let post = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{request}",
+            request.len()
+        );
+        let idle = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect::<Vec<_>>();
+        // Nothing is read from those, so the request is left out.
+        assert!(send(b"").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(idle);
+        let served = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            send(post.as_bytes()).starts_with("HTTP/1.1 200 OK\r\n")
+        });
+        assert!(served);
+    }
+
+    #[test]
+    fn slow_requests_are_cut_off() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let timeout = Duration::from_millis(300);
+        let server = thread::spawn(move || {
+            let chain = Mutex::new(new_chain());
+            serve_connection(stream, &chain, timeout)
+        });
+
+        // Every byte comes well within the timeout, but the request never ends.
+        let start = Instant::now();
+        let trickled = b"POST / HTTP/1.1\r\nX-Header: "
+            .iter()
+            .chain([b'a'].iter().cycle());
+        for byte in trickled {
+            if server.is_finished() || start.elapsed() > 20 * timeout {
+                break;
+            }
+            let _ = client.write_all(&[*byte]);
+            thread::sleep(Duration::from_millis(20));
+        }
+        let error = server.join().unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < 5 * timeout);
+        // The connection is closed.
+        client.set_read_timeout(Some(timeout)).unwrap();
+        assert!(matches!(client.read(&mut [0]), Ok(0) | Err(_)));
+    }
+}
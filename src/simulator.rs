@@ -0,0 +1,498 @@
+//! A deterministic, in-process network of nodes, to test consensus, fork choice and transaction
+//! gossip without a real network.
+//!
+//! Every node runs its own `Runtime` on top of its own `Backend`, starting from the development
+//! chain, and holds the key of one of the authorities. Time is simulated: at the start of each slot,
+//! the author of the slot builds a block out of its transaction pool and sends it to its peers.
+//! Messages arrive after a random latency, or get dropped, and never cross a partition. A node
+//! passes on every block and extrinsic it imports for the first time, and asks the sender for the
+//! parent of a block it can not import yet. Randomness comes from a generator seeded by the config,
+//! so a run only depends on its config and on what the test does.
+
+use crate::backend::{Backend, ImportError, ImportOutcome};
+use crate::chain_spec::ChainSpec;
+use crate::consensus::{ConsensusError, Poa};
+use crate::crypto::{dev_seed, Hash, Pair};
+use crate::database::MemoryDb;
+use crate::transaction_pool::{PoolError, TransactionPool};
+use crate::types::{Block, Extrinsic, Header};
+use crate::Runtime;
+use std::collections::BTreeMap;
+
+/// The shape of a simulated network.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How many nodes there are, each of which is an authority.
+    pub nodes: usize,
+    /// In milliseconds.
+    pub slot_duration: u64,
+    /// The latency of every message is drawn uniformly from this range, in milliseconds.
+    pub min_latency: u64,
+    pub max_latency: u64,
+    /// The probability that a message is lost.
+    pub drop_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nodes: 4,
+            slot_duration: 6_000,
+            min_latency: 100,
+            max_latency: 1_000,
+            drop_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// What nodes send each other.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Block(Block),
+    /// Ask for the block with this hash.
+    BlockRequest(Hash),
+    Transaction(Extrinsic),
+}
+
+/// A message on its way.
+#[derive(Debug, Clone)]
+struct Envelope {
+    from: usize,
+    to: usize,
+    message: Message,
+}
+
+/// A small random number generator (SplitMix64), so a run only depends on its seed.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `min..=max`.
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        min + self.next_u64() % (max - min + 1)
+    }
+
+    /// `true` with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// A simulated node.
+pub struct Node {
+    pub backend: Backend<MemoryDb>,
+    pub runtime: Runtime,
+    pub pool: TransactionPool,
+    key: Pair,
+    /// Blocks received before their parent, by the hash of their parent.
+    orphans: BTreeMap<Hash, Vec<Block>>,
+}
+
+impl Node {
+    fn new(key: Pair) -> Self {
+        let mut backend = Backend::open(MemoryDb::default());
+        let runtime = Runtime::new();
+        let genesis = backend
+            .state()
+            .execute_with(|| ChainSpec::development().build_genesis(&runtime));
+        backend
+            .commit_block(&genesis)
+            .expect("The memory database never fails.");
+        Self {
+            backend,
+            runtime,
+            pool: TransactionPool::new(),
+            key,
+            orphans: BTreeMap::new(),
+        }
+    }
+
+    pub fn best_header(&self) -> Header {
+        self.backend
+            .best_header()
+            .expect("The genesis block is committed.")
+    }
+
+    /// Build and import the block of `slot`, if this node is its author, at `now`.
+    fn author(&mut self, poa: &Poa, slot: u64, now: u64) -> Option<Block> {
+        let Self {
+            backend,
+            runtime,
+            pool,
+            key,
+            ..
+        } = self;
+        let block = backend
+            .state()
+            .execute_with(|| poa.propose(runtime, key, slot, pool.ready()))?;
+        self.import(poa, block.clone(), now).ok()?;
+        Some(block)
+    }
+
+    /// Import `block` at `now`. When it becomes the best block, the extrinsics of the retracted
+    /// blocks go back to the pool, and the pool is cleaned up against the new state.
+    fn import(
+        &mut self,
+        poa: &Poa,
+        block: Block,
+        now: u64,
+    ) -> Result<ImportOutcome, ImportError<ConsensusError>> {
+        let Self {
+            backend,
+            runtime,
+            pool,
+            ..
+        } = self;
+        let outcome =
+            backend.import_block(&block, |block| poa.import_block(runtime, block, now))?;
+        if outcome.is_best {
+            let retracted = outcome
+                .retracted
+                .iter()
+                .filter_map(|hash| backend.block(hash))
+                .flat_map(|block| block.extrinsics)
+                .collect::<Vec<_>>();
+            backend.state().execute_with(|| {
+                for extrinsic in retracted {
+                    let _ = pool.submit(runtime, extrinsic);
+                }
+                pool.maintain(runtime);
+            });
+        }
+        Ok(outcome)
+    }
+
+    fn submit(&mut self, extrinsic: Extrinsic) -> Result<Hash, PoolError> {
+        let Self {
+            backend,
+            runtime,
+            pool,
+            ..
+        } = self;
+        backend
+            .state()
+            .execute_with(|| pool.submit(runtime, extrinsic))
+    }
+}
+
+/// A simulated network. See the module docs.
+pub struct Network {
+    config: Config,
+    poa: Poa,
+    nodes: Vec<Node>,
+    /// In milliseconds.
+    now: u64,
+    /// The next slot to author a block in.
+    next_slot: u64,
+    /// The messages in flight, by arrival time, then in the order they were sent.
+    in_flight: BTreeMap<(u64, u64), Envelope>,
+    next_message: u64,
+    /// The side of the partition each node is on. Messages only go between nodes on the same side.
+    sides: Vec<usize>,
+    rng: Rng,
+    pub delivered: usize,
+    pub dropped: usize,
+}
+
+impl Network {
+    pub fn new(config: Config) -> Self {
+        assert!(config.min_latency <= config.max_latency);
+        let keys = (0..config.nodes)
+            .map(|i| Pair::ed25519(&dev_seed(&format!("node{i}"))))
+            .collect::<Vec<_>>();
+        let poa = Poa::new(
+            keys.iter().map(Pair::public).collect(),
+            config.slot_duration,
+        );
+        Self {
+            poa,
+            nodes: keys.into_iter().map(Node::new).collect(),
+            now: 0,
+            next_slot: 0,
+            in_flight: BTreeMap::new(),
+            next_message: 0,
+            sides: vec![0; config.nodes],
+            rng: Rng(config.seed),
+            delivered: 0,
+            dropped: 0,
+            config,
+        }
+    }
+
+    pub fn node(&self, index: usize) -> &Node {
+        &self.nodes[index]
+    }
+
+    pub fn set_drop_rate(&mut self, drop_rate: f64) {
+        self.config.drop_rate = drop_rate;
+    }
+
+    /// Split the network into `sides`, which no message crosses, including those already in
+    /// flight. Nodes which are in no side are on their own.
+    pub fn partition(&mut self, sides: &[&[usize]]) {
+        for (node, side) in self.sides.iter_mut().enumerate() {
+            *side = sides
+                .iter()
+                .position(|side| side.contains(&node))
+                .unwrap_or(sides.len() + node);
+        }
+    }
+
+    /// Join all the nodes back together.
+    pub fn heal(&mut self) {
+        self.sides.fill(0);
+    }
+
+    /// Submit `extrinsic` to the pool of the node `index`, which passes it on to its peers.
+    pub fn submit(&mut self, index: usize, extrinsic: Extrinsic) -> Result<Hash, PoolError> {
+        let hash = self.nodes[index].submit(extrinsic.clone())?;
+        self.broadcast(index, None, Message::Transaction(extrinsic));
+        Ok(hash)
+    }
+
+    /// Run the network up to `time`: author the blocks of the slots which start by then, and
+    /// deliver the messages which arrive by then, in order.
+    pub fn run_until(&mut self, time: u64) {
+        loop {
+            let slot_start = self.next_slot * self.config.slot_duration;
+            let arrival = self.in_flight.keys().next().map(|(arrival, _)| *arrival);
+            if arrival.is_some_and(|arrival| arrival <= time.min(slot_start)) {
+                let ((arrival, _), envelope) = self.in_flight.pop_first().expect("qed");
+                self.now = arrival;
+                self.deliver(envelope);
+            } else if slot_start <= time {
+                self.now = slot_start;
+                self.next_slot += 1;
+                self.author(self.next_slot - 1);
+            } else {
+                self.now = time;
+                return;
+            }
+        }
+    }
+
+    /// Run the network until the end of the next `slots` slots.
+    pub fn run_slots(&mut self, slots: u64) {
+        self.run_until((self.next_slot + slots) * self.config.slot_duration - 1);
+    }
+
+    /// Whether every node has the same best block, and the same state after it.
+    pub fn is_converged(&mut self) -> bool {
+        let best = self.nodes[0].best_header();
+        self.nodes.iter_mut().all(|node| {
+            node.best_header() == best && node.backend.state().root() == best.state_root
+        })
+    }
+
+    fn author(&mut self, slot: u64) {
+        let author = self
+            .nodes
+            .iter()
+            .position(|node| node.key.public() == *self.poa.author(slot))
+            .expect("Every authority runs a node.");
+        if let Some(block) = self.nodes[author].author(&self.poa, slot, self.now) {
+            self.broadcast(author, None, Message::Block(block));
+        }
+    }
+
+    fn deliver(&mut self, Envelope { from, to, message }: Envelope) {
+        if self.sides[from] != self.sides[to] {
+            self.dropped += 1;
+            return;
+        }
+        self.delivered += 1;
+        match message {
+            Message::Block(block) => self.receive_block(from, to, block),
+            Message::BlockRequest(hash) => {
+                if let Some(block) = self.nodes[to].backend.block(&hash) {
+                    self.send(to, from, Message::Block(block));
+                }
+            }
+            Message::Transaction(extrinsic) => {
+                if self.nodes[to].submit(extrinsic.clone()).is_ok() {
+                    self.broadcast(to, Some(from), Message::Transaction(extrinsic));
+                }
+            }
+        }
+    }
+
+    /// Import `block` on the node `to`, along with the orphans which were waiting for it, or ask
+    /// `from` for its parent.
+    fn receive_block(&mut self, from: usize, to: usize, block: Block) {
+        let node = &mut self.nodes[to];
+        let parent_hash = block.header.parent_hash;
+        if node.backend.header(&block.header.hash()).is_some() {
+            return;
+        }
+        if node.backend.state_at(&parent_hash).is_none() {
+            node.orphans.entry(parent_hash).or_default().push(block);
+            self.send(to, from, Message::BlockRequest(parent_hash));
+            return;
+        }
+        let mut blocks = vec![block];
+        while let Some(block) = blocks.pop() {
+            let node = &mut self.nodes[to];
+            let hash = block.header.hash();
+            if node.import(&self.poa, block.clone(), self.now).is_ok() {
+                blocks.extend(node.orphans.remove(&hash).unwrap_or_default());
+                self.broadcast(to, Some(from), Message::Block(block));
+            }
+        }
+    }
+
+    /// Send `message` from `from` to every other node, but `except`.
+    fn broadcast(&mut self, from: usize, except: Option<usize>, message: Message) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != except {
+                self.send(from, to, message.clone());
+            }
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        if self.rng.chance(self.config.drop_rate) {
+            self.dropped += 1;
+            return;
+        }
+        let latency = self
+            .rng
+            .range(self.config.min_latency, self.config.max_latency);
+        self.in_flight.insert(
+            (self.now + latency, self.next_message),
+            Envelope { from, to, message },
+        );
+        self.next_message += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Network, Node};
+    use crate::balances;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::support::Extrinsic;
+    use crate::types::{self, Balance, Nonce};
+    use crate::RuntimeCall;
+
+    /// A transfer of `amount` from alice to charlie.
+    fn transfer(network: &Network, nonce: Nonce, amount: Balance) -> types::Extrinsic {
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        let genesis_hash = network.node(0).backend.block_hash(0).unwrap();
+        Extrinsic::new_signed(
+            RuntimeCall::balances(balances::Call::transfer {
+                receiver: charlie.account(),
+                amount,
+            }),
+            &alice,
+            nonce,
+            &genesis_hash,
+        )
+    }
+
+    fn charlie_balance(network: &mut Network, index: usize) -> Balance {
+        let charlie = Pair::ed25519(&dev_seed("charlie")).account();
+        let Node {
+            backend, runtime, ..
+        } = &mut network.nodes[index];
+        backend
+            .state()
+            .execute_with(|| runtime.balances.balance(&charlie))
+    }
+
+    #[test]
+    fn nodes_agree_on_the_best_chain() {
+        let mut network = Network::new(Config::default());
+        // Extrinsics reach the author of the next slot through gossip.
+        network.submit(3, transfer(&network, 0, 10)).unwrap();
+        network.submit(2, transfer(&network, 1, 20)).unwrap();
+        network.run_slots(8);
+
+        assert!(network.is_converged());
+        assert_eq!(network.node(0).best_header().block_number, 8);
+        for index in 0..4 {
+            assert_eq!(charlie_balance(&mut network, index), 30);
+            assert_eq!(network.node(index).pool.status().ready, 0);
+        }
+        assert_eq!(network.dropped, 0);
+    }
+
+    #[test]
+    fn partitions_heal_onto_the_longest_fork() {
+        let mut network = Network::new(Config {
+            nodes: 3,
+            ..Config::default()
+        });
+        network.run_slots(2);
+        assert!(network.is_converged());
+
+        // Two authorities out of three author more blocks than the third one on its own.
+        network.partition(&[&[0, 1], &[2]]);
+        network.submit(2, transfer(&network, 0, 10)).unwrap();
+        network.run_slots(6);
+        assert!(!network.is_converged());
+        assert_eq!(charlie_balance(&mut network, 2), 10);
+        assert_eq!(charlie_balance(&mut network, 0), 0);
+
+        // The third authority learns about the longer fork when it receives its next block.
+        network.heal();
+        network.run_slots(3);
+        assert!(network.is_converged());
+        assert_eq!(network.node(0).best_header().block_number, 8);
+        // The transfer of the lost fork went back to the pool of its author, and makes it into the
+        // best chain in its next slot.
+        assert_eq!(charlie_balance(&mut network, 2), 0);
+        network.run_slots(3);
+        for index in 0..3 {
+            assert_eq!(charlie_balance(&mut network, index), 10);
+        }
+    }
+
+    #[test]
+    fn lost_messages_are_recovered() {
+        let mut network = Network::new(Config {
+            drop_rate: 0.3,
+            seed: 7,
+            ..Config::default()
+        });
+        network.submit(0, transfer(&network, 0, 10)).unwrap();
+        network.run_slots(12);
+        assert!(network.dropped > 0);
+
+        // Once the network is reliable again, the next block brings everyone up to date.
+        network.set_drop_rate(0.0);
+        network.run_slots(2);
+        assert!(network.is_converged());
+        for index in 0..4 {
+            assert_eq!(charlie_balance(&mut network, index), 10);
+        }
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let run = |seed| {
+            let mut network = Network::new(Config {
+                drop_rate: 0.2,
+                seed,
+                ..Config::default()
+            });
+            network.run_slots(10);
+            let bests = (0..4)
+                .map(|index| network.node(index).best_header().hash())
+                .collect::<Vec<_>>();
+            (bests, network.delivered, network.dropped)
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+}
@@ -0,0 +1,531 @@
+//! The state of the chain: a single key-value store, shared by all pallets.
+//!
+//! Pallets do not own their state. Instead, they declare typed storage items, `StorageValue` and
+//! `StorageMap`, which encode their keys and values, and read and write them in the storage the
+//! runtime is executing against. That storage is set with `Storage::execute_with`, and changes can
+//! be grouped in transactions with `with_transaction`.
+//!
+//! The storage commits to all of its entries through the root of a sparse Merkle trie, see
+//! `trie`, which is the state root in the header of a block. `Storage::read_proof` proves single
+//! entries against it.
+
+use crate::codec::{decode_all, Decode, Encode};
+use crate::crypto::{blake2_128, Hash};
+use crate::trie;
+use core::fmt;
+use core::marker::PhantomData;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+thread_local! {
+    /// The storage the runtime is executing against on this thread, if any.
+    static ENVIRONMENT: RefCell<Option<Environment>> = const { RefCell::new(None) };
+}
+
+/// The committed entries of the storage.
+#[derive(Debug, Clone, Default)]
+pub struct Storage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The trie holding `entries`, which keeps its root up to date as they change.
+    trie: trie::Trie,
+    /// The entries changed since the last `clear_changes`.
+    changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl Storage {
+    /// The root of the trie holding every entry.
+    pub fn root(&self) -> Hash {
+        self.trie.root()
+    }
+
+    /// Storage made of `entries`, for example as loaded from a database.
+    pub fn from_entries(entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Self {
+        let entries = entries.into_iter().collect::<BTreeMap<_, _>>();
+        let trie = trie::Trie::from_entries(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        );
+        Self {
+            entries,
+            trie,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    /// The committed value at `key`, read from outside of the runtime, like for RPC queries.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    /// The entries changed since the last `clear_changes`, to persist them. `None` is a removed
+    /// entry.
+    pub fn changes(&self) -> &BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        &self.changes
+    }
+
+    /// Forget the changes made so far, once they are persisted.
+    pub fn clear_changes(&mut self) {
+        self.changes.clear();
+    }
+
+    /// The changes which turn the entries of `base` into the entries of `self`, like `changes`.
+    /// For switching between the states of two blocks which are not built on one another.
+    pub fn diff(&self, base: &Storage) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        let removed = base
+            .entries
+            .keys()
+            .filter(|key| !self.entries.contains_key(*key))
+            .map(|key| (key.clone(), None));
+        let changed = self
+            .entries
+            .iter()
+            .filter(|(key, value)| base.entries.get(*key) != Some(value))
+            .map(|(key, value)| (key.clone(), Some(value.clone())));
+        removed.chain(changed).collect()
+    }
+
+    /// Prove the value at `key`, or its absence, against `self.root()`. See
+    /// `light_client::verify_storage` to check the proof.
+    pub fn read_proof(&self, key: &[u8]) -> trie::Proof {
+        trie::prove(self.iter(), key)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    /// Run `f` with `self` as the storage of every storage item.
+    ///
+    /// Storage items can only be used inside of `f`, and panic otherwise. Calls can be nested: the
+    /// inner storage is used until the inner call returns.
+    pub fn execute_with<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        /// Gives the storage back, even if `f` panics.
+        struct Restore<'a> {
+            storage: &'a mut Storage,
+            previous: Option<Environment>,
+        }
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let environment = ENVIRONMENT.with(|env| env.replace(self.previous.take()));
+                // Transactions can only be left open by a panic, and are dropped with it.
+                *self.storage = environment.expect("set by `execute_with`; qed").storage;
+            }
+        }
+
+        let environment = Environment {
+            storage: std::mem::take(self),
+            transactions: Vec::new(),
+        };
+        let previous = ENVIRONMENT.with(|env| env.replace(Some(environment)));
+        let _restore = Restore {
+            storage: self,
+            previous,
+        };
+        f()
+    }
+
+    /// Set `key` to `value`, or remove it if `value` is `None`.
+    fn apply(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.trie.set(&key, value.as_deref());
+        match &value {
+            Some(value) => self.entries.insert(key.clone(), value.clone()),
+            None => self.entries.remove(&key),
+        };
+        self.changes.insert(key, value);
+    }
+}
+
+/// The changes made by an open transaction: `None` is a removed key.
+type Overlay = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// The storage which is being executed against, along with the changes of the open transactions,
+/// innermost last.
+struct Environment {
+    storage: Storage,
+    transactions: Vec<Overlay>,
+}
+
+impl Environment {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        for overlay in self.transactions.iter().rev() {
+            if let Some(value) = overlay.get(key) {
+                return value.clone();
+            }
+        }
+        self.storage.entries.get(key).cloned()
+    }
+
+    fn write(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        match self.transactions.last_mut() {
+            Some(overlay) => {
+                overlay.insert(key, value);
+            }
+            None => self.storage.apply(key, value),
+        }
+    }
+
+    /// Close the innermost transaction, and apply its changes to the one around it, if any.
+    fn commit(&mut self) {
+        let overlay = self.transactions.pop().expect("a transaction is open; qed");
+        for (key, value) in overlay {
+            self.write(key, value);
+        }
+    }
+
+    /// The root of the storage with the changes of the open transactions. They are set in the
+    /// trie of the storage for as long as it takes to read the root, and then set back.
+    fn root(&mut self) -> Hash {
+        let mut changes = BTreeMap::new();
+        for overlay in self.transactions.iter().rev() {
+            for (key, value) in overlay {
+                changes.entry(key.as_slice()).or_insert(value.as_deref());
+            }
+        }
+        for (key, value) in &changes {
+            self.storage.trie.set(key, *value);
+        }
+        let root = self.storage.root();
+        for key in changes.keys() {
+            let value = self.storage.entries.get(*key).map(Vec::as_slice);
+            self.storage.trie.set(key, value);
+        }
+        root
+    }
+}
+
+/// Run `f` against the storage being executed against.
+fn with_environment<R>(f: impl FnOnce(&mut Environment) -> R) -> R {
+    ENVIRONMENT.with(|env| {
+        let mut env = env.borrow_mut();
+        f(env
+            .as_mut()
+            .expect("storage is only available inside of `Storage::execute_with`"))
+    })
+}
+
+fn get(key: &[u8]) -> Option<Vec<u8>> {
+    with_environment(|env| env.get(key))
+}
+
+fn set(key: Vec<u8>, value: Vec<u8>) {
+    with_environment(|env| env.write(key, Some(value)))
+}
+
+fn remove(key: Vec<u8>) {
+    with_environment(|env| env.write(key, None))
+}
+
+/// The state root of the storage, including the changes of the open transactions.
+pub fn root() -> Hash {
+    with_environment(|env| env.root())
+}
+
+/// Run `f` as a single storage transaction.
+///
+/// Every change `f` makes to storage is kept if it returns `Ok`, and thrown away if it returns
+/// `Err`. Transactions can be nested: a failing inner scope only rolls back its own changes, and
+/// the outer scope is free to carry on or fail in turn.
+pub fn with_transaction<R, E>(f: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+    with_environment(|env| env.transactions.push(Overlay::new()));
+    let result = f();
+    with_environment(|env| match result {
+        Ok(_) => env.commit(),
+        Err(_) => {
+            env.transactions.pop();
+        }
+    });
+    result
+}
+
+/// Run `f`, and throw away every change it makes to storage.
+pub fn dry_run<R>(f: impl FnOnce() -> R) -> R {
+    let result: Result<(), R> = with_transaction(|| Err(f()));
+    result.unwrap_err()
+}
+
+/// Where the items of `pallet` named `name` are stored: every key of the item starts with this.
+fn prefix(pallet: &str, name: &str) -> Vec<u8> {
+    let mut prefix = blake2_128(pallet.as_bytes()).to_vec();
+    prefix.extend_from_slice(&blake2_128(name.as_bytes()));
+    prefix
+}
+
+/// Read the value at `key`, which was written by a typed storage item.
+fn get_decoded<V: Decode>(key: &[u8]) -> Option<V> {
+    get(key).map(|value| decode_all(&value).expect("storage items only hold their own type; qed"))
+}
+
+/// A single value of type `V` in storage.
+pub struct StorageValue<V> {
+    pallet: &'static str,
+    name: &'static str,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<V> StorageValue<V> {
+    /// The value named `name` in the storage of `pallet`.
+    pub const fn new(pallet: &'static str, name: &'static str) -> Self {
+        Self {
+            pallet,
+            name,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<V: Encode + Decode> StorageValue<V> {
+    fn key(&self) -> Vec<u8> {
+        prefix(self.pallet, self.name)
+    }
+
+    pub fn get(&self) -> Option<V> {
+        get_decoded(&self.key())
+    }
+
+    pub fn put(&self, value: &V) {
+        set(self.key(), value.encode())
+    }
+
+    pub fn kill(&self) {
+        remove(self.key())
+    }
+
+    /// Remove the value, and return what it was.
+    pub fn take(&self) -> Option<V> {
+        let value = self.get();
+        self.kill();
+        value
+    }
+}
+
+/// A map from keys of type `K` to values of type `V` in storage. Each entry is stored under the
+/// prefix of the map followed by the encoding of its key.
+pub struct StorageMap<K, V> {
+    pallet: &'static str,
+    name: &'static str,
+    _entry: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> StorageMap<K, V> {
+    /// The map named `name` in the storage of `pallet`.
+    pub const fn new(pallet: &'static str, name: &'static str) -> Self {
+        Self {
+            pallet,
+            name,
+            _entry: PhantomData,
+        }
+    }
+}
+
+impl<K: Encode, V: Encode + Decode> StorageMap<K, V> {
+    /// The storage key of the entry at `key`. It does not depend on the contents of storage, so
+    /// this can be used anywhere, for example to ask for a `Storage::read_proof`.
+    pub fn hashed_key_for(&self, key: &K) -> Vec<u8> {
+        let mut storage_key = prefix(self.pallet, self.name);
+        key.encode_to(&mut storage_key);
+        storage_key
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        get_decoded(&self.hashed_key_for(key))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        get(&self.hashed_key_for(key)).is_some()
+    }
+
+    pub fn insert(&self, key: &K, value: &V) {
+        set(self.hashed_key_for(key), value.encode())
+    }
+
+    pub fn remove(&self, key: &K) {
+        remove(self.hashed_key_for(key))
+    }
+}
+
+// Storage items only hold their name, so they are `Clone` and `Debug` whatever their types are.
+
+impl<V> Clone for StorageValue<V> {
+    fn clone(&self) -> Self {
+        Self::new(self.pallet, self.name)
+    }
+}
+
+impl<V> fmt::Debug for StorageValue<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StorageValue({}::{})", self.pallet, self.name)
+    }
+}
+
+impl<K, V> Clone for StorageMap<K, V> {
+    fn clone(&self) -> Self {
+        Self::new(self.pallet, self.name)
+    }
+}
+
+impl<K, V> fmt::Debug for StorageMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StorageMap({}::{})", self.pallet, self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dry_run, root, with_transaction, Storage, StorageMap, StorageValue};
+
+    const VALUE: StorageValue<u32> = StorageValue::new("Test", "Value");
+    const MAP: StorageMap<String, u32> = StorageMap::new("Test", "Map");
+
+    #[test]
+    fn typed_storage_items() {
+        let mut storage = Storage::default();
+        let empty_root = storage.root();
+
+        storage.execute_with(|| {
+            assert_eq!(VALUE.get(), None);
+            VALUE.put(&1);
+            MAP.insert(&"alice".to_string(), &2);
+            assert_eq!(VALUE.get(), Some(1));
+            assert_eq!(MAP.get(&"alice".to_string()), Some(2));
+            assert!(!MAP.contains_key(&"bob".to_string()));
+        });
+
+        // The changes stick to `storage`, and its root commits to them.
+        let root_after = storage.root();
+        assert_ne!(root_after, empty_root);
+        storage.execute_with(|| {
+            assert_eq!(root(), root_after);
+            assert_eq!(VALUE.take(), Some(1));
+            MAP.remove(&"alice".to_string());
+            assert_eq!(root(), empty_root);
+        });
+        assert_eq!(storage.root(), empty_root);
+
+        // Every entry which was touched is a change, down to the ones which are gone again.
+        assert_eq!(storage.changes().len(), 2);
+        assert!(storage.changes().values().all(Option::is_none));
+        storage.clear_changes();
+        assert!(storage.changes().is_empty());
+    }
+
+    #[test]
+    fn root_includes_the_open_transactions() {
+        let mut storage = Storage::from_entries([(b"alice".to_vec(), b"100".to_vec())]);
+        let committed = storage.root();
+        let (inner, outer) = storage.execute_with(|| {
+            let roots = dry_run(|| {
+                VALUE.put(&1);
+                let inner = dry_run(|| {
+                    VALUE.put(&2);
+                    MAP.insert(&"alice".to_string(), &3);
+                    root()
+                });
+                (inner, root())
+            });
+            assert_eq!(root(), committed);
+            roots
+        });
+        assert_eq!(storage.root(), committed);
+
+        // The roots are those of the storage the transactions would have left behind.
+        let mut expected = storage.clone();
+        expected.execute_with(|| VALUE.put(&1));
+        assert_eq!(outer, expected.root());
+        expected.execute_with(|| {
+            VALUE.put(&2);
+            MAP.insert(&"alice".to_string(), &3);
+        });
+        assert_eq!(inner, expected.root());
+    }
+
+    #[test]
+    fn diff_between_storages() {
+        let mut base = Storage::default();
+        base.execute_with(|| {
+            VALUE.put(&1);
+            MAP.insert(&"alice".to_string(), &2);
+            MAP.insert(&"bob".to_string(), &3);
+        });
+        let mut other = base.clone();
+        other.execute_with(|| {
+            VALUE.kill();
+            MAP.insert(&"alice".to_string(), &4);
+            MAP.insert(&"charlie".to_string(), &5);
+        });
+
+        let diff = other.diff(&base);
+        assert_eq!(diff.len(), 3);
+        assert_eq!(diff.get(&VALUE.key()), Some(&None));
+        assert!(base.diff(&base).is_empty());
+
+        // Applying the diff to `base` gives `other`.
+        for (key, value) in diff {
+            base.apply(key, value);
+        }
+        assert_eq!(base.root(), other.root());
+    }
+
+    #[test]
+    fn transaction_rolls_back_on_error() {
+        Storage::default().execute_with(|| {
+            VALUE.put(&1);
+
+            let result: Result<(), &str> = with_transaction(|| {
+                VALUE.put(&2);
+                Err("failed")
+            });
+            assert_eq!(result, Err("failed"));
+            assert_eq!(VALUE.get(), Some(1));
+
+            let result: Result<(), &str> = with_transaction(|| {
+                VALUE.put(&2);
+                Ok(())
+            });
+            assert_eq!(result, Ok(()));
+            assert_eq!(VALUE.get(), Some(2));
+
+            assert_eq!(
+                dry_run(|| {
+                    VALUE.kill();
+                    VALUE.get()
+                }),
+                None
+            );
+            assert_eq!(VALUE.get(), Some(2));
+        });
+    }
+
+    #[test]
+    fn nested_transactions() {
+        Storage::default().execute_with(|| {
+            VALUE.put(&1);
+
+            let result: Result<(), &str> = with_transaction(|| {
+                VALUE.put(&2);
+                let inner: Result<(), &str> = with_transaction(|| {
+                    MAP.insert(&"alice".to_string(), &3);
+                    Err("inner failed")
+                });
+                assert_eq!(inner, Err("inner failed"));
+                assert_eq!(MAP.get(&"alice".to_string()), None);
+                Ok(())
+            });
+            assert_eq!(result, Ok(()));
+            assert_eq!(VALUE.get(), Some(2));
+
+            let result: Result<(), &str> = with_transaction(|| {
+                with_transaction(|| {
+                    MAP.insert(&"alice".to_string(), &3);
+                    Ok::<(), &str>(())
+                })?;
+                Err("outer failed")
+            });
+            assert_eq!(result, Err("outer failed"));
+            assert_eq!(MAP.get(&"alice".to_string()), None);
+        });
+    }
+}
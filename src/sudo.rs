@@ -0,0 +1,255 @@
+//! Sudo: a single account which may dispatch any call as root.
+//!
+//! Calls for root only, like `balances::force_set_balance`, can not be made by any signed
+//! extrinsic. The sudo key is the one account which can still make them, by wrapping them in
+//! `sudo`. It can also dispatch a call on behalf of any other account with `sudo_as`, and hand the
+//! key over to another account with `set_key`.
+//!
+//! The wrapped call is dispatched through the runtime, in its own storage transaction: if it fails,
+//! its changes are rolled back, but the sudo call itself succeeds, and its event records the
+//! failure.
+
+use crate::codec::{Decode, Encode};
+use crate::storage::StorageValue;
+use crate::support::{
+    BuildGenesisConfig, DispatchError, DispatchNested, DispatchResult, DispatchResultWithPostInfo,
+    Hooks, PalletEvents, PostDispatchInfo, ValidateUnsigned, Weight,
+};
+use crate::system::{ensure_signed, OriginFor, RawOrigin};
+use serde::{Deserialize, Serialize};
+
+/// Implemented by the runtime, which dispatches the calls of the sudo key.
+pub trait Config: crate::system::Config + DispatchNested<RuntimeOrigin = OriginFor<Self>> {}
+
+/// The weight of a sudo call on top of the weight of the call it wraps.
+const SUDO_WEIGHT: Weight = 1_000;
+
+/// Events emitted by the sudo pallet.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    /// The sudo key dispatched a call as root, with `result`.
+    Sudid { result: DispatchResult },
+    /// The sudo key dispatched a call on behalf of another account, with `result`.
+    SudoAsDone { result: DispatchResult },
+    /// The sudo key moved from `old` to `new`.
+    KeyChanged {
+        old: T::AccountId,
+        new: T::AccountId,
+    },
+}
+
+/// Errors which can be raised by the sudo pallet.
+#[macros::error]
+pub enum Error<T> {
+    /// The caller is not the sudo key.
+    RequireSudo,
+}
+
+/// The initial state of the sudo pallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    /// The account which holds the sudo key from the start, if any.
+    pub key: Option<T::AccountId>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self { key: None }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {
+        if let Some(key) = &self.key {
+            Pallet::<T>::new().key.put(key);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+    key: StorageValue<T::AccountId>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self {
+            key: StorageValue::new("Sudo", "Key"),
+        }
+    }
+
+    /// The account which signed the extrinsic, if it holds the sudo key.
+    fn ensure_sudo(&self, origin: OriginFor<T>) -> Result<T::AccountId, DispatchError> {
+        let caller = ensure_signed(origin)?;
+        if self.key.get().as_ref() != Some(&caller) {
+            Err(Error::<T>::RequireSudo)?;
+        }
+        Ok(caller)
+    }
+}
+
+/// The post info of a sudo call which dispatched a call using `weight`.
+fn used(weight: Weight) -> PostDispatchInfo {
+    PostDispatchInfo {
+        actual_weight: Some(SUDO_WEIGHT.saturating_add(weight)),
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "Sudo";
+    type Event = Event<T>;
+}
+
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
+
+#[macros::call]
+impl<T: Config> Pallet<T> {
+    /// Dispatch `call` as root. Only the sudo key can do this.
+    // The call is boxed in the `Call` enum, which would otherwise contain itself.
+    #[allow(clippy::boxed_local)]
+    #[weight(SUDO_WEIGHT.saturating_add(T::weight(call)))]
+    pub fn sudo(&mut self, origin: OriginFor<T>, call: Box<T::Call>) -> DispatchResultWithPostInfo {
+        self.ensure_sudo(origin)?;
+        let (result, weight) = T::dispatch_nested(RawOrigin::Root, *call);
+        self.deposit_event(Event::Sudid { result });
+        Ok(used(weight))
+    }
+
+    /// Dispatch `call` on behalf of `who`, as if `who` had signed it. Only the sudo key can do
+    /// this.
+    #[allow(clippy::boxed_local)]
+    #[weight(SUDO_WEIGHT.saturating_add(T::weight(call)))]
+    pub fn sudo_as(
+        &mut self,
+        origin: OriginFor<T>,
+        who: T::AccountId,
+        call: Box<T::Call>,
+    ) -> DispatchResultWithPostInfo {
+        self.ensure_sudo(origin)?;
+        let (result, weight) = T::dispatch_nested(RawOrigin::Signed(who), *call);
+        self.deposit_event(Event::SudoAsDone { result });
+        Ok(used(weight))
+    }
+
+    /// Hand the sudo key over to `new`. Only the sudo key can do this.
+    #[weight(SUDO_WEIGHT)]
+    pub fn set_key(&mut self, origin: OriginFor<T>, new: T::AccountId) -> DispatchResult {
+        let old = self.ensure_sudo(origin)?;
+        self.key.put(&new);
+        self.deposit_event(Event::KeyChanged { old, new });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Event, GenesisConfig, Pallet};
+    use crate::balances;
+    use crate::mock::{TestConfig, ALICE, BOB, CHARLIE};
+    use crate::storage::Storage;
+    use crate::support::{BuildGenesisConfig, DispatchError, PalletEvents};
+    use crate::system::RawOrigin;
+
+    fn setup() -> (Pallet<TestConfig>, balances::Pallet<TestConfig>) {
+        GenesisConfig::<TestConfig> { key: Some(ALICE) }.build();
+        (Pallet::new(), balances::Pallet::new())
+    }
+
+    #[test]
+    fn sudo_dispatches_calls_as_root() {
+        Storage::default().execute_with(|| {
+            let (mut sudo, mut balances) = setup();
+            let alice = RawOrigin::Signed(ALICE);
+            let force_set_balance = Box::new(balances::Call::force_set_balance {
+                who: BOB,
+                amount: 100,
+            });
+
+            // Only the sudo key can use it.
+            assert_eq!(
+                sudo.sudo(RawOrigin::Signed(BOB), force_set_balance.clone()),
+                Err(Error::<TestConfig>::RequireSudo.into())
+            );
+            assert_eq!(
+                sudo.sudo(RawOrigin::Root, force_set_balance.clone()),
+                Err(DispatchError::BadOrigin.into())
+            );
+            assert_eq!(balances.balance(&BOB), 0);
+
+            assert!(sudo.sudo(alice.clone(), force_set_balance).is_ok());
+            assert_eq!(balances.balance(&BOB), 100);
+            assert_eq!(sudo.take_events(), vec![Event::Sudid { result: Ok(()) }]);
+
+            // A failing call is rolled back, but the sudo call itself succeeds.
+            let transfer = Box::new(balances::Call::transfer {
+                receiver: BOB,
+                amount: 10,
+            });
+            assert!(sudo.sudo(alice, transfer).is_ok());
+            assert_eq!(
+                sudo.take_events(),
+                vec![Event::Sudid {
+                    result: Err(DispatchError::BadOrigin)
+                }]
+            );
+            assert_eq!(balances.balance(&BOB), 100);
+        });
+    }
+
+    #[test]
+    fn sudo_as_dispatches_calls_for_other_accounts() {
+        Storage::default().execute_with(|| {
+            let (mut sudo, mut balances) = setup();
+            balances.set_balance(&BOB, 100);
+            let transfer = Box::new(balances::Call::transfer {
+                receiver: CHARLIE,
+                amount: 30,
+            });
+
+            assert!(sudo
+                .sudo_as(RawOrigin::Signed(ALICE), BOB, transfer.clone())
+                .is_ok());
+            assert_eq!(balances.balance(&BOB), 70);
+            assert_eq!(balances.balance(&CHARLIE), 30);
+            assert_eq!(
+                sudo.take_events(),
+                vec![Event::SudoAsDone { result: Ok(()) }]
+            );
+
+            assert_eq!(
+                sudo.sudo_as(RawOrigin::Signed(BOB), BOB, transfer),
+                Err(Error::<TestConfig>::RequireSudo.into())
+            );
+        });
+    }
+
+    #[test]
+    fn sudo_key_can_be_handed_over() {
+        Storage::default().execute_with(|| {
+            let (mut sudo, _) = setup();
+
+            assert_eq!(
+                sudo.set_key(RawOrigin::Signed(BOB), BOB),
+                Err(Error::<TestConfig>::RequireSudo.into())
+            );
+            assert_eq!(sudo.set_key(RawOrigin::Signed(ALICE), BOB), Ok(()));
+            assert_eq!(sudo.key.get(), Some(BOB));
+            assert_eq!(
+                sudo.take_events(),
+                vec![Event::KeyChanged {
+                    old: ALICE,
+                    new: BOB
+                }]
+            );
+
+            // Alice gave the key away.
+            assert_eq!(
+                sudo.set_key(RawOrigin::Signed(ALICE), ALICE),
+                Err(Error::<TestConfig>::RequireSudo.into())
+            );
+        });
+    }
+}
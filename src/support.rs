@@ -1,23 +1,493 @@
+use crate::codec::{Decode, Encode};
+use crate::crypto::{blake2_256, Hash, MultiSignature, MultiSigner, Pair};
+use crate::storage::{with_transaction, StorageValue};
+use core::fmt::Debug;
+
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Block<Header, Extrinsic> {
     pub header: Header,
     pub extrinsics: Vec<Extrinsic>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct Header<BlockNumber> {
+    /// The hash of the header of the previous block.
+    pub parent_hash: Hash,
     pub block_number: BlockNumber,
+    /// The root of the state after executing this block.
+    pub state_root: Hash,
+    /// The root of the extrinsics in this block, see `extrinsics_root`.
+    pub extrinsics_root: Hash,
+    /// Extra data for consensus, like the slot of the block and the seal of its author.
+    pub digest: Digest,
+}
+
+impl<BlockNumber: Encode + Clone> Header<BlockNumber> {
+    /// The hash of this header, which identifies its block.
+    ///
+    /// The seals of the digest are left out, since they sign this hash.
+    pub fn hash(&self) -> Hash {
+        let mut header = self.clone();
+        header.digest = header.digest.without_seals();
+        blake2_256(&header.encode())
+    }
+}
+
+/// Identifies the consensus engine a digest item is meant for.
+pub type ConsensusEngineId = [u8; 4];
+
+/// The digest of a header: the items consensus engines put in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Digest {
+    pub logs: Vec<DigestItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum DigestItem {
+    /// Put in by the author before the block is built, like the slot it was authored in. The
+    /// runtime records these along with the rest of the header.
+    PreRuntime(ConsensusEngineId, Vec<u8>),
+    /// Put in by the author once the block is built, signing the hash of the header. It is never
+    /// seen by the runtime, and is not part of the hash of the header.
+    Seal(ConsensusEngineId, Vec<u8>),
+}
+
+impl Digest {
+    /// The data of the pre-runtime item for `engine`.
+    pub fn pre_runtime(&self, engine: ConsensusEngineId) -> Option<&[u8]> {
+        self.logs.iter().find_map(|item| match item {
+            DigestItem::PreRuntime(id, data) if *id == engine => Some(&data[..]),
+            _ => None,
+        })
+    }
+
+    /// The data of the seal for `engine`.
+    pub fn seal(&self, engine: ConsensusEngineId) -> Option<&[u8]> {
+        self.logs.iter().find_map(|item| match item {
+            DigestItem::Seal(id, data) if *id == engine => Some(&data[..]),
+            _ => None,
+        })
+    }
+
+    /// This digest, without its seals: what the runtime sees.
+    pub fn without_seals(&self) -> Self {
+        Self {
+            logs: self
+                .logs
+                .iter()
+                .filter(|item| !matches!(item, DigestItem::Seal(..)))
+                .cloned()
+                .collect(),
+        }
+    }
 }
 
-pub struct Extrinsic<Caller, Call> {
-    pub caller: Caller,
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Extrinsic<Signer, Signature, Nonce, Tip, Call> {
+    /// The signature of the extrinsic, or `None` if it is unsigned.
+    pub signature: Option<ExtrinsicSignature<Signer, Signature, Nonce, Tip>>,
     pub call: Call,
 }
 
-pub type DispatchResult = Result<(), &'static str>;
+/// Proof that the account of `signer` submitted an extrinsic.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct ExtrinsicSignature<Signer, Signature, Nonce, Tip> {
+    /// The public key of the account which submitted the extrinsic.
+    pub signer: Signer,
+    /// The signature of `signing_payload` by `signer`.
+    pub signature: Signature,
+    /// Must match the nonce stored for the account of `signer` in the system pallet.
+    pub nonce: Nonce,
+    /// Paid on top of the fee, to get the extrinsic included sooner.
+    pub tip: Tip,
+}
+
+/// The root of the extrinsics of a block, which commits the header to them in order.
+pub fn extrinsics_root<E: Encode>(extrinsics: &[E]) -> Hash {
+    blake2_256(&extrinsics.encode())
+}
+
+impl<Signer, Signature, Nonce, Tip, Call> Extrinsic<Signer, Signature, Nonce, Tip, Call> {
+    /// An extrinsic for `call`, without a signature. Only calls marked `#[unsigned]` can be
+    /// submitted this way.
+    pub fn new_unsigned(call: Call) -> Self {
+        Self {
+            signature: None,
+            call,
+        }
+    }
+}
+
+impl<Nonce: Encode, Tip: Encode + Default, Call: Encode>
+    Extrinsic<MultiSigner, MultiSignature, Nonce, Tip, Call>
+{
+    /// An extrinsic for `call`, signed by `pair`, without a tip.
+    pub fn new_signed(call: Call, pair: &Pair, nonce: Nonce, genesis_hash: &Hash) -> Self {
+        Self::new_signed_with_tip(call, pair, nonce, Tip::default(), genesis_hash)
+    }
+
+    /// An extrinsic for `call`, signed by `pair`, which pays `tip` on top of its fee.
+    pub fn new_signed_with_tip(
+        call: Call,
+        pair: &Pair,
+        nonce: Nonce,
+        tip: Tip,
+        genesis_hash: &Hash,
+    ) -> Self {
+        let signature = pair.sign(&signing_payload(&call, &nonce, &tip, genesis_hash));
+        Self {
+            signature: Some(ExtrinsicSignature {
+                signer: pair.public(),
+                signature,
+                nonce,
+                tip,
+            }),
+            call,
+        }
+    }
+}
+
+/// The bytes the signer of an extrinsic signs.
+///
+/// Signing the nonce stops the extrinsic from being replayed on the same chain, and signing the
+/// genesis hash stops it from being replayed on a different one.
+pub fn signing_payload(
+    call: &impl Encode,
+    nonce: &impl Encode,
+    tip: &impl Encode,
+    genesis_hash: &Hash,
+) -> Vec<u8> {
+    (call, nonce, tip, genesis_hash).encode()
+}
+
+/// A signature which can be checked against the public key of its signer.
+pub trait Verify {
+    type Signer;
+
+    /// Whether `self` is a valid signature of `message` by `signer`.
+    fn verify(&self, message: &[u8], signer: &Self::Signer) -> bool;
+}
+
+/// A public key which identifies an account.
+pub trait IdentifyAccount {
+    type AccountId;
+
+    fn into_account(self) -> Self::AccountId;
+}
+
+/// The reason an extrinsic can not be included in a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidTransaction {
+    /// The nonce of the extrinsic was already used by the caller.
+    Stale,
+    /// The nonce of the extrinsic is ahead of the next nonce of the caller.
+    Future,
+    /// The signature of the extrinsic is not valid.
+    BadProof,
+    /// The extrinsic is unsigned, but its call must be signed.
+    UnsignedNotAllowed,
+    /// The extrinsic is unsigned, and its call fails. Unsigned extrinsics pay no fee, so only
+    /// those whose call succeeds are included.
+    Call,
+    /// The extrinsic is unsigned, and the pallet of its call does not let it in the transaction
+    /// pool: only the author of a block can include it, see `ValidateUnsigned`.
+    NoUnsignedValidator,
+    /// The extrinsic does not fit in the block: its weight would take the block over the maximum.
+    ExhaustsResources,
+    /// The caller can not pay the fee of the extrinsic.
+    Payment,
+    /// The nonce of the extrinsic is the last one, so no extrinsic of the caller could follow it.
+    NonceOverflow,
+}
+
+/// The reason a block was rejected by `execute_block`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The block number does not follow the number of the last executed block.
+    UnexpectedBlockNumber,
+    /// The parent hash is not the hash of the last executed block.
+    ParentHashMismatch,
+    /// The extrinsics root does not match the extrinsics of the block.
+    ExtrinsicsRootMismatch,
+    /// The state root does not match the state after executing the block.
+    StateRootMismatch,
+    /// The extrinsic at `index` in the block can not be included.
+    InvalidTransaction {
+        index: u32,
+        error: InvalidTransaction,
+    },
+}
+
+/// The reason a call failed to dispatch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum DispatchError {
+    /// The call was dispatched on behalf of the wrong kind of origin, for example a call for root
+    /// only by a signed account.
+    BadOrigin,
+    /// An error from the `Error` enum of a pallet.
+    Module(ModuleError),
+}
+
+/// An error raised by a pallet, identified by where the pallet and the error are declared. The
+/// runtime can turn it back into the typed error, see `RuntimeError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct ModuleError {
+    /// The index of the pallet in the runtime.
+    pub index: u8,
+    /// The index of the variant in the `Error` enum of the pallet.
+    pub error: u8,
+}
+
+pub type DispatchResult = Result<(), DispatchError>;
+
+/// What is known about a call once it was dispatched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostDispatchInfo {
+    /// The weight the call actually used, if it is less than the weight it declared.
+    pub actual_weight: Option<Weight>,
+}
+
+impl PostDispatchInfo {
+    /// The weight used by a call which declared `weight`. A call never uses more than it declared.
+    pub fn calc_actual_weight(&self, weight: Weight) -> Weight {
+        self.actual_weight
+            .map_or(weight, |actual| actual.min(weight))
+    }
+}
+
+/// A call which returns nothing used the weight it declared.
+impl From<()> for PostDispatchInfo {
+    fn from(_: ()) -> Self {
+        Self::default()
+    }
+}
+
+/// A call which failed to dispatch, along with what is known about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchErrorWithPostInfo {
+    pub post_info: PostDispatchInfo,
+    pub error: DispatchError,
+}
+
+impl<E: Into<DispatchError>> From<E> for DispatchErrorWithPostInfo {
+    fn from(error: E) -> Self {
+        Self {
+            post_info: PostDispatchInfo::default(),
+            error: error.into(),
+        }
+    }
+}
+
+/// The result of a call which can tell how much weight it actually used. Calls may return either
+/// this or a `DispatchResult`.
+pub type DispatchResultWithPostInfo = Result<PostDispatchInfo, DispatchErrorWithPostInfo>;
+
+/// The `Error` enum of a pallet. Implemented by `#[macros::error]`.
+pub trait PalletError: Sized {
+    /// The index of this error in the `Error` enum.
+    fn index(&self) -> u8;
+    /// The name of this error.
+    fn as_str(&self) -> &'static str;
+    /// The error at `index` in the `Error` enum, if any.
+    fn from_index(index: u8) -> Option<Self>;
+}
+
+/// Gives the index of each pallet included in a runtime. Implemented by `#[macros::runtime]`.
+pub trait PalletInfo {
+    /// The index of the pallet `P` in the runtime, or `None` if it is not included.
+    fn index<P: 'static>() -> Option<u8>;
+}
+
+/// Used by pallet tests, where the pallet is alone: every pallet has index 0.
+impl PalletInfo for () {
+    fn index<P: 'static>() -> Option<u8> {
+        Some(0)
+    }
+}
 
+/// The time it takes to execute some runtime logic, in abstract units.
+pub type Weight = u64;
+
+/// Logic a pallet runs at the boundaries of every block. Every pallet of a runtime implements it,
+/// and `#[macros::runtime]` calls each hook on every pallet, in the order they are declared. Hooks
+/// do nothing by default.
+pub trait Hooks<BlockNumber> {
+    /// Called at the start of block `n`, before its extrinsics. Returns the weight it used.
+    fn on_initialize(&mut self, _n: BlockNumber) -> Weight {
+        0
+    }
+
+    /// Called at the end of block `n`, after its extrinsics, with the weight left in the block.
+    /// Returns the weight it used, which must not be more than `remaining_weight`.
+    fn on_idle(&mut self, _n: BlockNumber, _remaining_weight: Weight) -> Weight {
+        0
+    }
+
+    /// Called at the end of block `n`, after `on_idle`. Nothing runs after it in the block.
+    fn on_finalize(&mut self, _n: BlockNumber) {}
+}
+
+/// The events of a pallet. Every pallet of a runtime but system implements it, and
+/// `#[macros::runtime]` collects the events of each pallet once an extrinsic is done. Events are
+/// queued up in storage, so the events of a failing extrinsic are rolled back with it.
+pub trait PalletEvents {
+    /// The name of the pallet in storage, which the events are queued up under.
+    const PALLET: &'static str;
+    type Event: Encode + Decode;
+
+    /// Queue an event to be collected by the runtime once the current extrinsic is done.
+    fn deposit_event(&mut self, event: Self::Event) {
+        let queue = StorageValue::<Vec<Self::Event>>::new(Self::PALLET, "Events");
+        let mut events = queue.get().unwrap_or_default();
+        events.push(event);
+        queue.put(&events);
+    }
+
+    /// Drain the events deposited since the last call.
+    fn take_events(&mut self) -> Vec<Self::Event> {
+        StorageValue::<Vec<Self::Event>>::new(Self::PALLET, "Events")
+            .take()
+            .unwrap_or_default()
+    }
+}
+
+/// Writes the initial state of a pallet into storage when a chain starts. Implemented by the
+/// `GenesisConfig` of each pallet, and by the `RuntimeGenesisConfig` of `#[macros::runtime]`.
+pub trait BuildGenesisConfig {
+    fn build(&self);
+}
+
+/// Charges signed extrinsics a fee for their weight and length. The runtime picks an
+/// implementation through `system::Config::OnChargeTransaction`.
+pub trait OnChargeTransaction<AccountId> {
+    /// The type of the tips of extrinsics.
+    type Balance;
+
+    /// Withdraw from `who` the fee of an extrinsic of `len` bytes, which declares `weight`, along
+    /// with its `tip`. This happens before dispatch, and fails if `who` can not pay.
+    fn withdraw_fee(
+        who: &AccountId,
+        weight: Weight,
+        len: u32,
+        tip: &Self::Balance,
+    ) -> Result<(), InvalidTransaction>;
+
+    /// Refund `who` the part of the fee paid for `weight` which the extrinsic did not use, since
+    /// it only used `actual_weight`. This happens after dispatch.
+    fn refund_fee(who: &AccountId, weight: Weight, actual_weight: Weight);
+
+    /// The priority of an extrinsic which declares `weight` and pays `tip`, in the transaction
+    /// pool.
+    fn priority(weight: Weight, tip: &Self::Balance) -> TransactionPriority;
+}
+
+/// Used by pallet tests, and by chains where extrinsics are free. Tips are ignored.
+impl<AccountId> OnChargeTransaction<AccountId> for () {
+    type Balance = u128;
+
+    fn withdraw_fee(_: &AccountId, _: Weight, _: u32, _: &u128) -> Result<(), InvalidTransaction> {
+        Ok(())
+    }
+
+    fn refund_fee(_: &AccountId, _: Weight, _: Weight) {}
+
+    fn priority(_: Weight, _: &u128) -> TransactionPriority {
+        0
+    }
+}
+
+/// Extrinsics with a higher priority are included in blocks first.
+pub type TransactionPriority = u64;
+
+/// Tags order the extrinsics of the transaction pool, see `ValidTransaction`.
+pub type TransactionTag = Vec<u8>;
+
+/// An extrinsic which can be included in a block now, or once the extrinsics it requires are.
+/// Returned by the `validate_transaction` of `#[macros::runtime]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidTransaction {
+    pub priority: TransactionPriority,
+    /// Tags which must be provided by extrinsics included before this one.
+    pub requires: Vec<TransactionTag>,
+    /// Tags this extrinsic provides once it is included. Two extrinsics which provide the same tag
+    /// can not both be included.
+    pub provides: Vec<TransactionTag>,
+}
 
 pub trait Dispatch {
-    type Caller;
+    /// Who calls are dispatched on behalf of, see `system::RawOrigin`.
+    type RuntimeOrigin;
     type Call;
 
-    fn dispatch(&mut self,caller: Self::Caller,call: Self::Call) -> DispatchResult;
-}
\ No newline at end of file
+    fn dispatch(
+        &mut self,
+        origin: Self::RuntimeOrigin,
+        call: Self::Call,
+    ) -> DispatchResultWithPostInfo;
+
+    /// Whether `call` may be submitted without a signature.
+    fn allows_unsigned(call: &Self::Call) -> bool;
+
+    /// The weight `call` declares, which is the most it can use once dispatched.
+    fn weight(call: &Self::Call) -> Weight;
+}
+
+/// How a pallet lets its unsigned calls into the transaction pool. Every pallet of a runtime but
+/// system implements it. Unsigned extrinsics pay no fee, and have no signer whose extrinsics the
+/// pool could limit, so by default the pool takes none of them: such a call is an inherent, which
+/// only the author of a block puts in it.
+pub trait ValidateUnsigned: Dispatch {
+    /// Whether the unsigned extrinsic of `call` can go in the transaction pool, and how it is
+    /// ordered there. Its call must also succeed in a dry run.
+    fn validate_unsigned(
+        &self,
+        _call: &Self::Call,
+    ) -> Result<ValidTransaction, InvalidTransaction> {
+        Err(InvalidTransaction::NoUnsignedValidator)
+    }
+}
+
+/// A runtime which pallets can dispatch calls through, for pallets which wrap other calls, like
+/// sudo, utility and multisig. The runtime holds no state, so it is built with `Default` to
+/// dispatch a call.
+pub trait DispatchNested: Dispatch<Call: Debug + Clone + Encode + Decode> + Default {
+    /// Dispatch `call` with `origin`, rolling its changes back if it fails. Returns its result,
+    /// and the weight it actually used.
+    fn dispatch_nested(origin: Self::RuntimeOrigin, call: Self::Call) -> (DispatchResult, Weight) {
+        let weight = Self::weight(&call);
+        match with_transaction(|| Self::default().dispatch(origin, call)) {
+            Ok(post_info) => (Ok(()), post_info.calc_actual_weight(weight)),
+            Err(error) => (Err(error.error), error.post_info.calc_actual_weight(weight)),
+        }
+    }
+}
+
+impl<T: Dispatch<Call: Debug + Clone + Encode + Decode> + Default> DispatchNested for T {}
+
+#[cfg(test)]
+mod test {
+    use super::{DispatchError, DispatchErrorWithPostInfo, DispatchResult, PostDispatchInfo};
+
+    #[test]
+    fn actual_weight_is_capped_by_declared_weight() {
+        assert_eq!(PostDispatchInfo::default().calc_actual_weight(100), 100);
+        let less = PostDispatchInfo {
+            actual_weight: Some(40),
+        };
+        assert_eq!(less.calc_actual_weight(100), 40);
+        let more = PostDispatchInfo {
+            actual_weight: Some(400),
+        };
+        assert_eq!(more.calc_actual_weight(100), 100);
+
+        // A call returning a `DispatchResult` used all the weight it declared.
+        let result: DispatchResult = Err(DispatchError::BadOrigin);
+        assert_eq!(
+            result.map_err(DispatchErrorWithPostInfo::from),
+            Err(DispatchErrorWithPostInfo {
+                post_info: PostDispatchInfo::default(),
+                error: DispatchError::BadOrigin
+            })
+        );
+    }
+}
@@ -1,60 +1,486 @@
-use num::{one, zero, One, Zero};
-use std::collections::BTreeMap;
+use crate::codec::{Decode, Encode};
+use crate::crypto::Hash;
+use crate::storage::{StorageMap, StorageValue};
+use crate::support::{
+    BuildGenesisConfig, Digest, DispatchError, DispatchResult, Header, Hooks, InvalidTransaction,
+    Weight,
+};
+use core::fmt::Debug;
+use num::{one, zero, CheckedAdd, One, Zero};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::ops::AddAssign;
 
-pub trait Config {
-    type AccountId: Ord + Clone;
-    type BlockNumber: Zero + One + AddAssign + Copy;
-    type Nonce: Zero + One + Copy;
+pub trait Config: 'static {
+    type AccountId: Ord + Clone + Encode + Decode + Serialize + DeserializeOwned;
+    type BlockNumber: Zero + One + AddAssign + Copy + Encode + Decode + Serialize + DeserializeOwned;
+    type Nonce: Zero + One + CheckedAdd + Copy + Ord + Encode + Decode;
+    /// The aggregated event type of the runtime, which every pallet's `Event` converts into.
+    type RuntimeEvent: From<Event> + Debug + Clone + Encode + Decode;
+    /// Tells each pallet its index in the runtime, to tag the errors it raises.
+    type PalletInfo: crate::support::PalletInfo;
+    /// The most weight a block can use, across its hooks and extrinsics.
+    const MAX_BLOCK_WEIGHT: Weight;
+    /// The weight of every extrinsic on top of its call, for checking its signature and nonce.
+    const EXTRINSIC_BASE_WEIGHT: Weight;
+    /// The most bytes the encoded extrinsics of a block can take.
+    const MAX_BLOCK_LENGTH: u32;
+    /// Charges signed extrinsics a fee.
+    type OnChargeTransaction: crate::support::OnChargeTransaction<Self::AccountId>;
 }
 
-#[derive(Debug)]
+/// Events emitted by the system pallet while executing a block.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event {
+    /// An extrinsic was dispatched successfully, using `weight`.
+    ExtrinsicSuccess { weight: Weight },
+    /// An extrinsic failed, and all of its changes were rolled back. It still used `weight`.
+    ExtrinsicFailed {
+        error: DispatchError,
+        weight: Weight,
+    },
+}
+
+/// The part of a block being executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Phase {
+    /// The `on_initialize` hooks, before any extrinsic.
+    Initialization,
+    /// The extrinsic at this index in the block.
+    ApplyExtrinsic(u32),
+    /// The `on_idle` and `on_finalize` hooks, after every extrinsic.
+    Finalization,
+}
+
+/// A runtime event, along with the part of the block which emitted it.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct EventRecord<E> {
+    pub phase: Phase,
+    pub event: E,
+}
+
+/// Who a call is dispatched on behalf of. Calls check it with `ensure_signed` or `ensure_root`,
+/// which fail with `DispatchError::BadOrigin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawOrigin<AccountId> {
+    /// The account which signed the extrinsic.
+    Signed(AccountId),
+    /// The chain itself, which may do anything. No extrinsic has this origin on its own.
+    Root,
+    /// An extrinsic without a signature.
+    None,
+}
+
+/// The origin of the calls of a pallet.
+pub type OriginFor<T> = RawOrigin<<T as Config>::AccountId>;
+
+/// The account which signed the extrinsic, or `BadOrigin` for any other origin.
+pub fn ensure_signed<AccountId>(origin: RawOrigin<AccountId>) -> Result<AccountId, DispatchError> {
+    match origin {
+        RawOrigin::Signed(who) => Ok(who),
+        _ => Err(DispatchError::BadOrigin),
+    }
+}
+
+/// `BadOrigin` unless the origin is root.
+pub fn ensure_root<AccountId>(origin: RawOrigin<AccountId>) -> DispatchResult {
+    match origin {
+        RawOrigin::Root => Ok(()),
+        _ => Err(DispatchError::BadOrigin),
+    }
+}
+
+/// `BadOrigin` unless the extrinsic is unsigned.
+pub fn ensure_none<AccountId>(origin: RawOrigin<AccountId>) -> DispatchResult {
+    match origin {
+        RawOrigin::None => Ok(()),
+        _ => Err(DispatchError::BadOrigin),
+    }
+}
+
+/// The initial state of the system pallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    /// The number of the genesis block. The first block built on top of it has the next number.
+    pub block_number: T::BlockNumber,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self {
+            block_number: zero(),
+        }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {
+        Pallet::<T>::new().block_number.put(&self.block_number);
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pallet<T: Config> {
-    block_number: T::BlockNumber,
-    nonce: BTreeMap<T::AccountId, T::Nonce>,
+    /// The hash of the genesis block, recorded when the first block on top of it starts.
+    genesis_hash: StorageValue<Hash>,
+    /// The parent hash of the current block, or of the last executed one between blocks.
+    parent_hash: StorageValue<Hash>,
+    /// The digest of the current block, or of the last executed one between blocks, without its
+    /// seals.
+    digest: StorageValue<Digest>,
+    block_number: StorageValue<T::BlockNumber>,
+    /// The extrinsics root of the last executed block. It is recorded once the block is finalized.
+    extrinsics_root: StorageValue<Hash>,
+    nonce: StorageMap<T::AccountId, T::Nonce>,
+    /// The weight used so far by the current block.
+    block_weight: StorageValue<Weight>,
+    /// The length of the extrinsics applied so far in the current block.
+    block_length: StorageValue<u32>,
+    /// The events of the current block. They are part of the state until the next block starts.
+    events: StorageValue<Vec<EventRecord<T::RuntimeEvent>>>,
 }
 
 impl<T: Config> Pallet<T> {
     pub fn new() -> Self {
         Self {
-            block_number: zero(),
-            nonce: BTreeMap::new(),
+            genesis_hash: StorageValue::new("System", "GenesisHash"),
+            parent_hash: StorageValue::new("System", "ParentHash"),
+            digest: StorageValue::new("System", "Digest"),
+            block_number: StorageValue::new("System", "BlockNumber"),
+            extrinsics_root: StorageValue::new("System", "ExtrinsicsRoot"),
+            nonce: StorageMap::new("System", "Nonce"),
+            block_weight: StorageValue::new("System", "BlockWeight"),
+            block_length: StorageValue::new("System", "BlockLength"),
+            events: StorageValue::new("System", "Events"),
         }
     }
 
+    /// The hash of the genesis block, which identifies the chain. Until a block is executed on top
+    /// of it, the current state is the genesis state, and this is the hash of `header()`.
+    pub fn genesis_hash(&self) -> Hash {
+        self.genesis_hash.get().unwrap_or_else(|| self.block_hash())
+    }
+
+    /// The parent hash of the current block.
+    pub fn parent_hash(&self) -> Hash {
+        self.parent_hash.get().unwrap_or_default()
+    }
+
+    /// The digest of the current block, without its seals.
+    pub fn digest(&self) -> Digest {
+        self.digest.get().unwrap_or_default()
+    }
+
+    /// The extrinsics root of the last executed block.
+    pub fn extrinsics_root(&self) -> Hash {
+        self.extrinsics_root.get().unwrap_or_default()
+    }
+
+    /// Record the parent hash and digest of the block which starts executing.
+    pub fn initialize(&mut self, parent_hash: Hash, digest: &Digest) {
+        if self.genesis_hash.get().is_none() {
+            // This is the first block, so it builds on the genesis block.
+            self.genesis_hash.put(&parent_hash);
+        }
+        self.parent_hash.put(&parent_hash);
+        self.digest.put(&digest.without_seals());
+        self.block_weight.kill();
+        self.block_length.kill();
+    }
+
+    /// Record the extrinsics root of the block which finishes executing.
+    pub fn finalize(&mut self, extrinsics_root: Hash) {
+        self.extrinsics_root.put(&extrinsics_root);
+    }
+
+    /// The header of the last executed block, or of the genesis block if there is none yet. The
+    /// genesis block has no parent and no extrinsics.
+    ///
+    /// Nothing is written to storage once a block is finalized, so the state root of that header
+    /// is the root of the current storage. This is only meaningful between blocks.
+    pub fn header(&self) -> Header<T::BlockNumber> {
+        Header {
+            parent_hash: self.parent_hash(),
+            block_number: self.block_number(),
+            state_root: crate::storage::root(),
+            extrinsics_root: self.extrinsics_root(),
+            digest: self.digest(),
+        }
+    }
+
+    /// The hash of `header()`, which the next block builds on.
+    pub fn block_hash(&self) -> Hash {
+        self.header().hash()
+    }
+
     pub fn block_number(&self) -> T::BlockNumber {
-        self.block_number
+        self.block_number.get().unwrap_or_else(zero)
     }
 
     pub fn inc_block_number(&mut self) {
-        self.block_number += one();
+        let mut block_number = self.block_number();
+        block_number += one();
+        self.block_number.put(&block_number);
     }
 
+    /// The nonce the next extrinsic of `who` must use.
+    pub fn nonce(&self, who: &T::AccountId) -> T::Nonce {
+        self.nonce.get(who).unwrap_or_else(zero)
+    }
+
+    /// Check that `nonce` is the next nonce of `who`, and that there is a nonce after it.
+    pub fn check_nonce(
+        &self,
+        who: &T::AccountId,
+        nonce: T::Nonce,
+    ) -> Result<(), InvalidTransaction> {
+        let expected = self.nonce(who);
+        if nonce < expected {
+            return Err(InvalidTransaction::Stale);
+        }
+        if nonce > expected {
+            return Err(InvalidTransaction::Future);
+        }
+        if nonce.checked_add(&one()).is_none() {
+            return Err(InvalidTransaction::NonceOverflow);
+        }
+        Ok(())
+    }
+
+    /// Move `who` on to its next nonce. Once it reached the last nonce, it stays there, and
+    /// `check_nonce` rejects every further extrinsic of `who`.
     pub fn inc_nonce(&mut self, who: &T::AccountId) {
-        let binding = zero();
-        let current_nonce = self.nonce.get(who).unwrap_or(&binding);
-        self.nonce.insert(who.clone(), *current_nonce + one());
+        if let Some(next) = self.nonce(who).checked_add(&one()) {
+            self.nonce.insert(who, &next);
+        }
+    }
+
+    /// The weight used so far by the current block.
+    pub fn block_weight(&self) -> Weight {
+        self.block_weight.get().unwrap_or_default()
+    }
+
+    /// Add `weight` to the weight used by the current block.
+    pub fn register_weight(&mut self, weight: Weight) {
+        self.block_weight
+            .put(&self.block_weight().saturating_add(weight));
+    }
+
+    /// The length of the extrinsics applied so far in the current block.
+    pub fn block_length(&self) -> u32 {
+        self.block_length.get().unwrap_or_default()
+    }
+
+    /// Add an extrinsic of `len` bytes to the length of the current block.
+    pub fn register_length(&mut self, len: u32) {
+        self.block_length
+            .put(&self.block_length().saturating_add(len));
+    }
+
+    /// The events deposited so far in the current block.
+    pub fn events(&self) -> Vec<EventRecord<T::RuntimeEvent>> {
+        self.events.get().unwrap_or_default()
+    }
+
+    /// Record an event emitted during `phase` of the current block.
+    pub fn deposit_event(&mut self, phase: Phase, event: impl Into<T::RuntimeEvent>) {
+        let mut events = self.events();
+        events.push(EventRecord {
+            phase,
+            event: event.into(),
+        });
+        self.events.put(&events);
+    }
+
+    /// Clear the events of the previous block. Called when a new block starts executing.
+    pub fn reset_events(&mut self) {
+        self.events.kill();
     }
 }
+
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
 #[cfg(test)]
 mod test {
-    use crate::system::{Config, Pallet};
+    use crate::storage::{with_transaction, Storage};
+    use crate::support::{DispatchError, InvalidTransaction, ModuleError};
+    use crate::system::{
+        ensure_none, ensure_root, ensure_signed, Config, Event, EventRecord, Pallet, Phase,
+        RawOrigin,
+    };
 
     struct TestConfig {}
     impl Config for TestConfig {
         type AccountId = String;
         type BlockNumber = u32;
         type Nonce = u32;
+        type RuntimeEvent = Event;
+        type PalletInfo = ();
+        const MAX_BLOCK_WEIGHT: crate::support::Weight = crate::support::Weight::MAX;
+        const EXTRINSIC_BASE_WEIGHT: crate::support::Weight = 0;
+        const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+        type OnChargeTransaction = ();
+    }
+    #[test]
+    fn origins_are_checked() {
+        let alice = "alice".to_string();
+        assert_eq!(ensure_signed(RawOrigin::Signed(alice.clone())), Ok(alice));
+        assert_eq!(
+            ensure_signed(RawOrigin::<String>::Root),
+            Err(DispatchError::BadOrigin)
+        );
+        assert_eq!(
+            ensure_signed(RawOrigin::<String>::None),
+            Err(DispatchError::BadOrigin)
+        );
+        assert_eq!(ensure_root(RawOrigin::<String>::Root), Ok(()));
+        assert_eq!(
+            ensure_root(RawOrigin::Signed("alice".to_string())),
+            Err(DispatchError::BadOrigin)
+        );
+        assert_eq!(
+            ensure_root(RawOrigin::<String>::None),
+            Err(DispatchError::BadOrigin)
+        );
+        assert_eq!(ensure_none(RawOrigin::<String>::None), Ok(()));
+        assert_eq!(
+            ensure_none(RawOrigin::<String>::Root),
+            Err(DispatchError::BadOrigin)
+        );
     }
+
     #[test]
     fn init_system() {
-        let mut system: Pallet<TestConfig> = Pallet::new();
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+
+            system.inc_block_number();
+            system.inc_nonce(&"alice".to_string());
+
+            assert_eq!(system.block_number(), 1);
+            assert_eq!(system.nonce.get(&"alice".to_string()), Some(1));
+            assert_eq!(system.nonce.get(&"bob".to_string()), None);
+        });
+    }
+
+    #[test]
+    fn changes_are_rolled_back_with_the_transaction() {
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+            let alice = "alice".to_string();
+
+            let result: Result<(), &str> = with_transaction(|| {
+                system.inc_block_number();
+                system.inc_nonce(&alice);
+                Err("failed")
+            });
+            assert_eq!(result, Err("failed"));
+            assert_eq!(system.block_number(), 0);
+            assert_eq!(system.nonce(&alice), 0);
+
+            let result: Result<(), &str> = with_transaction(|| {
+                system.inc_nonce(&alice);
+                Ok(())
+            });
+            assert_eq!(result, Ok(()));
+            assert_eq!(system.nonce(&alice), 1);
+        });
+    }
+
+    #[test]
+    fn check_nonce() {
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+            let alice = "alice".to_string();
+
+            assert_eq!(system.check_nonce(&alice, 0), Ok(()));
+            assert_eq!(
+                system.check_nonce(&alice, 1),
+                Err(InvalidTransaction::Future)
+            );
+
+            system.inc_nonce(&alice);
+
+            assert_eq!(system.nonce(&alice), 1);
+            assert_eq!(
+                system.check_nonce(&alice, 0),
+                Err(InvalidTransaction::Stale)
+            );
+            assert_eq!(system.check_nonce(&alice, 1), Ok(()));
+        });
+    }
+
+    #[test]
+    fn the_last_nonce_can_not_be_used() {
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+            let alice = "alice".to_string();
+            system.nonce.insert(&alice, &(u32::MAX - 1));
+
+            assert_eq!(system.check_nonce(&alice, u32::MAX - 1), Ok(()));
+            system.inc_nonce(&alice);
+            assert_eq!(
+                system.check_nonce(&alice, u32::MAX),
+                Err(InvalidTransaction::NonceOverflow)
+            );
+            system.inc_nonce(&alice);
+            assert_eq!(system.nonce(&alice), u32::MAX);
+        });
+    }
+
+    #[test]
+    fn block_weight() {
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+
+            system.register_weight(10);
+            system.register_weight(5);
+            assert_eq!(system.block_weight(), 15);
+            system.register_weight(u64::MAX);
+            assert_eq!(system.block_weight(), u64::MAX);
+            system.register_length(100);
+            system.register_length(20);
+            assert_eq!(system.block_length(), 120);
+
+            // Every block starts from zero.
+            system.initialize([1; 32], &Default::default());
+            assert_eq!(system.block_weight(), 0);
+            assert_eq!(system.block_length(), 0);
+        });
+    }
+
+    #[test]
+    fn deposit_events() {
+        Storage::default().execute_with(|| {
+            let mut system: Pallet<TestConfig> = Pallet::new();
+            let error = DispatchError::Module(ModuleError { index: 1, error: 0 });
+
+            system.deposit_event(
+                Phase::ApplyExtrinsic(0),
+                Event::ExtrinsicSuccess { weight: 10 },
+            );
+            system.deposit_event(
+                Phase::ApplyExtrinsic(1),
+                Event::ExtrinsicFailed { error, weight: 20 },
+            );
 
-        system.inc_block_number();
-        system.inc_nonce(&"alice".to_string());
+            assert_eq!(
+                system.events(),
+                vec![
+                    EventRecord {
+                        phase: Phase::ApplyExtrinsic(0),
+                        event: Event::ExtrinsicSuccess { weight: 10 }
+                    },
+                    EventRecord {
+                        phase: Phase::ApplyExtrinsic(1),
+                        event: Event::ExtrinsicFailed { error, weight: 20 }
+                    },
+                ]
+            );
 
-        assert_eq!(system.block_number, 1);
-        assert_eq!(system.nonce.get(&"alice".to_string()), Some(&1));
-        assert_eq!(system.nonce.get(&"bob".to_string()), None);
+            system.reset_events();
+            assert!(system.events().is_empty());
+        });
     }
 }
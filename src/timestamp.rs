@@ -0,0 +1,164 @@
+//! Timestamp: the time each block was authored at.
+//!
+//! The author of a block puts the time in it with `set`, in an unsigned extrinsic, as it is not on
+//! behalf of any account. The time can be set at most once per block, and only forward, so an old
+//! `set` can never be included again. No one else can submit `set`: the transaction pool does not
+//! take it, and consensus rejects a block whose time is not in its slot.
+
+use crate::codec::{Decode, Encode};
+use crate::storage::StorageValue;
+use crate::support::{BuildGenesisConfig, DispatchResult, Hooks, PalletEvents, ValidateUnsigned};
+use crate::system::{ensure_none, OriginFor};
+use core::convert::Infallible;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use serde::{Deserialize, Serialize};
+
+pub trait Config: crate::system::Config {
+    /// A point in time, like milliseconds since the unix epoch.
+    type Moment: Debug + Clone + Default + Ord + Encode + Decode;
+}
+
+/// The timestamp pallet emits no events.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    #[doc(hidden)]
+    __Ignore(PhantomData<T>, Infallible),
+}
+
+/// Errors which can be raised by the timestamp pallet.
+#[macros::error]
+pub enum Error<T> {
+    /// The time was already set in this block.
+    AlreadySet,
+    /// The time is not after the time of the last block.
+    NotIncreasing,
+}
+
+/// The timestamp pallet has no initial state: the genesis block has no time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    #[serde(skip)]
+    _config: PhantomData<T>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self {
+            _config: PhantomData,
+        }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+    /// The time of the last block which set it.
+    now: StorageValue<T::Moment>,
+    /// Whether the time was set in the current block. Cleared once the block is finalized.
+    did_update: StorageValue<bool>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self {
+            now: StorageValue::new("Timestamp", "Now"),
+            did_update: StorageValue::new("Timestamp", "DidUpdate"),
+        }
+    }
+
+    /// The time of the last block which set it, or the default if none did.
+    pub fn now(&self) -> T::Moment {
+        self.now.get().unwrap_or_default()
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "Timestamp";
+    type Event = Event<T>;
+}
+
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {
+    fn on_finalize(&mut self, _n: T::BlockNumber) {
+        self.did_update.kill();
+    }
+}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
+
+#[macros::call]
+impl<T: Config> Pallet<T> {
+    /// Set the time of the current block to `now`. Only an unsigned extrinsic can do this.
+    #[unsigned]
+    #[weight(1_000)]
+    pub fn set(&mut self, origin: OriginFor<T>, now: T::Moment) -> DispatchResult {
+        ensure_none(origin)?;
+        if self.did_update.get().unwrap_or_default() {
+            Err(Error::<T>::AlreadySet)?;
+        }
+        if now <= self.now() {
+            Err(Error::<T>::NotIncreasing)?;
+        }
+        self.now.put(&now);
+        self.did_update.put(&true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Pallet};
+    use crate::storage::Storage;
+    use crate::support::{DispatchError, Hooks};
+    use crate::system::RawOrigin;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct TestConfig;
+
+    impl super::Config for TestConfig {
+        type Moment = u64;
+    }
+
+    impl crate::system::Config for TestConfig {
+        type AccountId = String;
+        type BlockNumber = u32;
+        type Nonce = u32;
+        type RuntimeEvent = crate::system::Event;
+        type PalletInfo = ();
+        const MAX_BLOCK_WEIGHT: crate::support::Weight = crate::support::Weight::MAX;
+        const EXTRINSIC_BASE_WEIGHT: crate::support::Weight = 0;
+        const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+        type OnChargeTransaction = ();
+    }
+
+    #[test]
+    fn time_is_set_once_per_block_and_goes_forward() {
+        Storage::default().execute_with(|| {
+            let mut timestamp: Pallet<TestConfig> = Pallet::new();
+            assert_eq!(timestamp.now(), 0);
+
+            assert_eq!(
+                timestamp.set(RawOrigin::Signed("alice".to_string()), 1_000),
+                Err(DispatchError::BadOrigin)
+            );
+            assert_eq!(timestamp.set(RawOrigin::None, 1_000), Ok(()));
+            assert_eq!(timestamp.now(), 1_000);
+            assert_eq!(
+                timestamp.set(RawOrigin::None, 2_000),
+                Err(Error::<TestConfig>::AlreadySet.into())
+            );
+
+            timestamp.on_finalize(1);
+            assert_eq!(
+                timestamp.set(RawOrigin::None, 1_000),
+                Err(Error::<TestConfig>::NotIncreasing.into())
+            );
+            assert_eq!(timestamp.set(RawOrigin::None, 2_000), Ok(()));
+            assert_eq!(timestamp.now(), 2_000);
+        });
+    }
+}
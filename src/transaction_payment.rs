@@ -0,0 +1,174 @@
+//! Fees for signed extrinsics, paid from the balances pallet.
+//!
+//! The fee of an extrinsic is made of a fee for the weight it declares, and a fee for its encoded
+//! length. It is withdrawn from the caller before dispatch, even if the call then fails, so every
+//! extrinsic in a block was paid for. The part of the fee paid for weight the call did not use is
+//! refunded after dispatch. Fees are burned.
+//!
+//! An extrinsic can also pay a tip on top of its fee, to be included sooner. Its priority in the
+//! transaction pool is its tip for each part of the block it takes: a block full of extrinsics of
+//! the same priority pays the same tips, whatever their weights.
+
+use crate::balances;
+use crate::support::{InvalidTransaction, OnChargeTransaction, TransactionPriority, Weight};
+use core::marker::PhantomData;
+use num::{zero, CheckedAdd, CheckedSub, ToPrimitive, Zero};
+
+pub trait Config: balances::Config<Balance: ToPrimitive> {
+    /// The fee for `weight`. It must not decrease as the weight goes up.
+    fn weight_to_fee(weight: Weight) -> Self::Balance;
+    /// The fee for an extrinsic of `len` bytes.
+    fn length_to_fee(len: u32) -> Self::Balance;
+}
+
+/// Charges the fees of extrinsics to the balances pallet. See the module docs.
+#[derive(Debug, Clone, Copy)]
+pub struct ChargeTransactionPayment<T>(PhantomData<T>);
+
+impl<T: Config> ChargeTransactionPayment<T> {
+    /// The fee of an extrinsic of `len` bytes, which declares `weight`. `None` if it overflows, in
+    /// which case no one can pay it.
+    pub fn compute_fee(weight: Weight, len: u32) -> Option<T::Balance> {
+        T::weight_to_fee(weight).checked_add(&T::length_to_fee(len))
+    }
+}
+
+impl<T: Config> OnChargeTransaction<T::AccountId> for ChargeTransactionPayment<T> {
+    type Balance = T::Balance;
+
+    fn withdraw_fee(
+        who: &T::AccountId,
+        weight: Weight,
+        len: u32,
+        tip: &T::Balance,
+    ) -> Result<(), InvalidTransaction> {
+        let fee = Self::compute_fee(weight, len)
+            .and_then(|fee| fee.checked_add(tip))
+            .ok_or(InvalidTransaction::Payment)?;
+        balances::Pallet::<T>::new()
+            .withdraw(who, fee)
+            .map_err(|_| InvalidTransaction::Payment)
+    }
+
+    fn refund_fee(who: &T::AccountId, weight: Weight, actual_weight: Weight) {
+        let refund = T::weight_to_fee(weight)
+            .checked_sub(&T::weight_to_fee(actual_weight))
+            .unwrap_or_else(zero);
+        if refund.is_zero() {
+            return;
+        }
+        // This can only fail if the call itself took the balance of `who` close to the maximum, in
+        // which case the refund is lost.
+        let _ = balances::Pallet::<T>::new().deposit(who, refund);
+    }
+
+    fn priority(weight: Weight, tip: &T::Balance) -> TransactionPriority {
+        let tip = tip.to_u128().unwrap_or(u128::MAX);
+        let priority = tip.saturating_mul(T::MAX_BLOCK_WEIGHT.into()) / weight.max(1) as u128;
+        priority.try_into().unwrap_or(TransactionPriority::MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChargeTransactionPayment, Config};
+    use crate::balances::{Event, Pallet};
+    use crate::storage::Storage;
+    use crate::support::{InvalidTransaction, OnChargeTransaction, PalletEvents, Weight};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct TestConfig;
+
+    impl crate::system::Config for TestConfig {
+        type AccountId = String;
+        type BlockNumber = u32;
+        type Nonce = u32;
+        type RuntimeEvent = crate::system::Event;
+        type PalletInfo = ();
+        const MAX_BLOCK_WEIGHT: Weight = 1_000_000;
+        const EXTRINSIC_BASE_WEIGHT: Weight = 0;
+        const MAX_BLOCK_LENGTH: u32 = u32::MAX;
+        type OnChargeTransaction = ChargeTransactionPayment<Self>;
+    }
+
+    impl crate::balances::Config for TestConfig {
+        type Balance = u128;
+    }
+
+    impl Config for TestConfig {
+        fn weight_to_fee(weight: Weight) -> u128 {
+            weight as u128 / 10
+        }
+
+        fn length_to_fee(len: u32) -> u128 {
+            len as u128
+        }
+    }
+
+    type Charge = ChargeTransactionPayment<TestConfig>;
+
+    #[test]
+    fn fees_are_withdrawn_and_refunded() {
+        Storage::default().execute_with(|| {
+            let mut balances = Pallet::<TestConfig>::new();
+            let alice = "alice".to_string();
+            balances.set_balance(&alice, 100);
+
+            assert_eq!(Charge::compute_fee(500, 20), Some(70));
+            assert_eq!(Charge::withdraw_fee(&alice, 500, 20, &0), Ok(()));
+            assert_eq!(balances.balance(&alice), 30);
+
+            // The call only used 200 of the 500 weight it declared.
+            Charge::refund_fee(&alice, 500, 200);
+            assert_eq!(balances.balance(&alice), 60);
+            // Nothing is refunded for a call which used all of its weight.
+            Charge::refund_fee(&alice, 500, 500);
+            assert_eq!(balances.balance(&alice), 60);
+
+            assert_eq!(
+                balances.take_events(),
+                vec![
+                    Event::Withdraw {
+                        who: alice.clone(),
+                        amount: 70
+                    },
+                    Event::Deposit {
+                        who: alice.clone(),
+                        amount: 30
+                    },
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn fees_must_be_affordable() {
+        Storage::default().execute_with(|| {
+            let mut balances = Pallet::<TestConfig>::new();
+            let alice = "alice".to_string();
+            balances.set_balance(&alice, 69);
+
+            assert_eq!(
+                Charge::withdraw_fee(&alice, 500, 20, &0),
+                Err(InvalidTransaction::Payment)
+            );
+            // The tip is paid along with the fee.
+            balances.set_balance(&alice, 70);
+            assert_eq!(
+                Charge::withdraw_fee(&alice, 500, 20, &1),
+                Err(InvalidTransaction::Payment)
+            );
+            assert_eq!(balances.balance(&alice), 70);
+            assert!(balances.take_events().is_empty());
+        });
+    }
+
+    #[test]
+    fn priority_is_the_tip_per_weight() {
+        assert_eq!(Charge::priority(1_000, &0), 0);
+        // Half the weight for the same tip is twice the priority.
+        assert!(Charge::priority(500, &10) > Charge::priority(1_000, &10));
+        assert_eq!(Charge::priority(500, &10), 2 * Charge::priority(1_000, &10));
+        assert_eq!(Charge::priority(0, &u128::MAX), u64::MAX);
+    }
+}
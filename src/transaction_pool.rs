@@ -0,0 +1,783 @@
+//! The transaction pool: extrinsics waiting to be included in a block.
+//!
+//! Extrinsics are validated against the current state when they are submitted. Those which could
+//! be included in the next block are ready. Those which require an extrinsic that is not in the
+//! pool yet, like one with an earlier nonce of the same account, wait in the future queue until it
+//! arrives. Ready extrinsics are pulled for the next block by priority, then by tip, then in the
+//! order they arrived, and always after the extrinsics they require.
+//!
+//! The pool is bounded, both in the number of extrinsics and in their size, and so is the number
+//! of extrinsics of each signer. A signer must also be able to pay the fees of all of its
+//! extrinsics in the pool at once, so one account can not fill it with extrinsics it could never
+//! pay for. When the pool is full, a new extrinsic takes the place of the worst future ones, if it
+//! beats them.
+//!
+//! After a block is imported, every extrinsic in the pool is validated again against the new state,
+//! which evicts the extrinsics the block included, along with any which became invalid.
+
+use crate::codec::Encode;
+use crate::crypto::{blake2_256, Hash};
+use crate::support::{
+    Dispatch, IdentifyAccount, InvalidTransaction, TransactionPriority, TransactionTag,
+    ValidTransaction,
+};
+use crate::system::Config;
+use crate::transaction_payment::ChargeTransactionPayment;
+use crate::types::{AccountId, Balance, Extrinsic};
+use crate::Runtime;
+use core::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+/// Why an extrinsic was not added to the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    /// The extrinsic is already in the pool.
+    AlreadyImported,
+    /// The extrinsic can not be included on top of the current state.
+    Invalid(InvalidTransaction),
+    /// An extrinsic which provides the same tag, like one with the same signer and nonce, is in the
+    /// pool with the same or a higher priority and tip.
+    TooLowPriority,
+    /// The pool is full, and the extrinsic does not beat any future extrinsic it could replace.
+    Full,
+    /// The signer of the extrinsic already has as many extrinsics in the pool as it may.
+    TooManyFromSigner,
+}
+
+/// The number of extrinsics in each queue of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub ready: usize,
+    pub future: usize,
+}
+
+/// How much the pool holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+    /// The most extrinsics in the pool.
+    pub count: usize,
+    /// The most bytes the encoded extrinsics in the pool can take.
+    pub bytes: usize,
+    /// The most extrinsics of one signer in the pool.
+    pub per_signer: usize,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            count: 8_192,
+            bytes: 16 * 1024 * 1024,
+            per_signer: 64,
+        }
+    }
+}
+
+/// Extrinsics with a greater key are included first, see `PoolTransaction::key`.
+type Key = ((TransactionPriority, Balance), Reverse<u64>);
+
+#[derive(Debug, Clone)]
+struct PoolTransaction {
+    extrinsic: Extrinsic,
+    valid: ValidTransaction,
+    /// When the extrinsic was submitted, to keep the order of extrinsics of the same priority.
+    insertion: u64,
+    /// The length of the encoded extrinsic.
+    len: usize,
+    /// The account which signed the extrinsic, along with the fee and tip it pays.
+    payer: Option<(AccountId, Balance)>,
+}
+
+impl PoolTransaction {
+    fn new(extrinsic: Extrinsic, valid: ValidTransaction, insertion: u64) -> Self {
+        let len = extrinsic.encode().len();
+        let payer = extrinsic.signature.as_ref().map(|signature| {
+            let weight = <Runtime as Config>::EXTRINSIC_BASE_WEIGHT
+                .saturating_add(<Runtime as Dispatch>::weight(&extrinsic.call));
+            // Valid extrinsics have a fee which does not overflow.
+            let fee = ChargeTransactionPayment::<Runtime>::compute_fee(weight, len as u32)
+                .unwrap_or(Balance::MAX)
+                .saturating_add(signature.tip);
+            (signature.signer.into_account(), fee)
+        });
+        Self {
+            extrinsic,
+            valid,
+            insertion,
+            len,
+            payer,
+        }
+    }
+
+    fn tip(&self) -> Balance {
+        self.extrinsic
+            .signature
+            .as_ref()
+            .map_or(0, |signature| signature.tip)
+    }
+
+    fn signer(&self) -> Option<&AccountId> {
+        self.payer.as_ref().map(|(signer, _)| signer)
+    }
+
+    /// What decides between extrinsics which provide the same tag.
+    fn priority(&self) -> (TransactionPriority, Balance) {
+        (self.valid.priority, self.tip())
+    }
+
+    /// Extrinsics with a greater key are included first.
+    fn key(&self) -> Key {
+        (self.priority(), Reverse(self.insertion))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TransactionPool {
+    limits: PoolLimits,
+    /// Every extrinsic in the pool, by the hash of its encoding.
+    transactions: BTreeMap<Hash, PoolTransaction>,
+    /// The bytes taken by the encoded extrinsics in the pool.
+    bytes: usize,
+    /// The extrinsic which provides each tag, for every tag provided in the pool.
+    provided: BTreeMap<TransactionTag, Hash>,
+    /// The extrinsics which require each tag, for every tag required in the pool.
+    required_by: BTreeMap<TransactionTag, BTreeSet<Hash>>,
+    /// The extrinsics of each signer in the pool.
+    by_signer: BTreeMap<AccountId, BTreeSet<Hash>>,
+    /// The extrinsics whose required tags are all provided by other ready extrinsics.
+    ready: BTreeSet<Hash>,
+    /// The extrinsics waiting for one they require, from the worst to the best.
+    future: BTreeSet<(Key, Hash)>,
+    next_insertion: u64,
+}
+
+impl TransactionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate `extrinsic` against the current state, and add it to the pool. Returns the hash of
+    /// the extrinsic. Must run inside `Storage::execute_with`.
+    pub fn submit(
+        &mut self,
+        runtime: &mut Runtime,
+        extrinsic: Extrinsic,
+    ) -> Result<Hash, PoolError> {
+        let hash = blake2_256(&extrinsic.encode());
+        if self.transactions.contains_key(&hash) {
+            return Err(PoolError::AlreadyImported);
+        }
+        let valid = runtime
+            .validate_transaction(&extrinsic)
+            .map_err(PoolError::Invalid)?;
+        let transaction = PoolTransaction::new(extrinsic, valid, self.next_insertion);
+        self.next_insertion += 1;
+        self.insert(runtime, hash, transaction)?;
+        Ok(hash)
+    }
+
+    /// The ready extrinsics, in the order to include them in the next block.
+    ///
+    /// The best extrinsic is picked first among those whose required tags were provided by the ones
+    /// picked before, until none is left. A block builder can stop at any point, and skip the
+    /// extrinsics which turn out to be invalid, along with those which require them.
+    pub fn ready(&self) -> Vec<Extrinsic> {
+        // The number of tags each ready extrinsic still waits for.
+        let mut missing = BTreeMap::new();
+        let mut best = BinaryHeap::new();
+        for hash in &self.ready {
+            let transaction = &self.transactions[hash];
+            match transaction.valid.requires.len() {
+                0 => best.push((transaction.key(), *hash)),
+                count => {
+                    missing.insert(*hash, count);
+                }
+            }
+        }
+        let mut extrinsics = Vec::with_capacity(self.ready.len());
+        while let Some((_, hash)) = best.pop() {
+            let transaction = &self.transactions[&hash];
+            for dependent in self.dependents(transaction) {
+                let Some(count) = missing.get_mut(dependent) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count == 0 {
+                    missing.remove(dependent);
+                    best.push((self.transactions[dependent].key(), *dependent));
+                }
+            }
+            extrinsics.push(transaction.extrinsic.clone());
+        }
+        extrinsics
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            ready: self.ready.len(),
+            future: self.future.len(),
+        }
+    }
+
+    /// Validate every extrinsic in the pool again, against the state after a block was imported,
+    /// and evict those which are no longer valid, like the ones the block included. Returns the
+    /// hashes of the evicted extrinsics. Must run inside `Storage::execute_with`.
+    pub fn maintain(&mut self, runtime: &mut Runtime) -> Vec<Hash> {
+        let mut transactions = std::mem::take(&mut self.transactions)
+            .into_iter()
+            .collect::<Vec<_>>();
+        transactions.sort_by_key(|(_, transaction)| transaction.insertion);
+        *self = Self {
+            limits: self.limits,
+            next_insertion: self.next_insertion,
+            ..Self::default()
+        };
+
+        let mut evicted = Vec::new();
+        for (hash, mut transaction) in transactions {
+            let inserted = match runtime.validate_transaction(&transaction.extrinsic) {
+                Ok(valid) => {
+                    transaction.valid = valid;
+                    self.insert(runtime, hash, transaction).is_ok()
+                }
+                Err(_) => false,
+            };
+            if !inserted {
+                evicted.push(hash);
+            }
+        }
+        evicted
+    }
+
+    /// Add `transaction` to the pool, replacing the extrinsics which provide the same tags if it
+    /// beats all of them, and making room for it if the pool is full.
+    fn insert(
+        &mut self,
+        runtime: &mut Runtime,
+        hash: Hash,
+        transaction: PoolTransaction,
+    ) -> Result<(), PoolError> {
+        let replaced = transaction
+            .valid
+            .provides
+            .iter()
+            .filter_map(|tag| self.provided.get(tag).copied())
+            .collect::<BTreeSet<_>>();
+        if replaced
+            .iter()
+            .any(|other| self.transactions[other].priority() >= transaction.priority())
+        {
+            return Err(PoolError::TooLowPriority);
+        }
+        if let Some((signer, fee)) = &transaction.payer {
+            let others = self
+                .by_signer
+                .get(signer)
+                .into_iter()
+                .flatten()
+                .filter(|other| !replaced.contains(*other))
+                .map(|other| &self.transactions[other])
+                .collect::<Vec<_>>();
+            if others.len() >= self.limits.per_signer {
+                return Err(PoolError::TooManyFromSigner);
+            }
+            // The signer pays for all of its extrinsics in the pool, not only this one.
+            let fees = others
+                .iter()
+                .filter_map(|other| other.payer.as_ref())
+                .fold(*fee, |fees, (_, fee)| fees.saturating_add(*fee));
+            if fees > runtime.balances.balance(signer) {
+                return Err(PoolError::Invalid(InvalidTransaction::Payment));
+            }
+        }
+        let evicted = self.make_room(&transaction, &replaced)?;
+        for other in replaced.iter().chain(&evicted) {
+            self.remove(other);
+        }
+
+        for tag in &transaction.valid.provides {
+            self.provided.insert(tag.clone(), hash);
+        }
+        for tag in &transaction.valid.requires {
+            self.required_by
+                .entry(tag.clone())
+                .or_default()
+                .insert(hash);
+        }
+        if let Some(signer) = transaction.signer() {
+            self.by_signer.entry(*signer).or_default().insert(hash);
+        }
+        self.bytes += transaction.len;
+        self.future.insert((transaction.key(), hash));
+        self.transactions.insert(hash, transaction);
+        if self.is_satisfied(&hash) {
+            self.promote(hash);
+        }
+        Ok(())
+    }
+
+    /// The future extrinsics to evict so that `transaction` fits in the pool once `replaced` is
+    /// gone. Only the worst future extrinsics are evicted, and only if `transaction` beats them.
+    fn make_room(
+        &self,
+        transaction: &PoolTransaction,
+        replaced: &BTreeSet<Hash>,
+    ) -> Result<Vec<Hash>, PoolError> {
+        let mut count = self.transactions.len() - replaced.len() + 1;
+        let mut bytes = self.bytes + transaction.len
+            - replaced
+                .iter()
+                .map(|other| self.transactions[other].len)
+                .sum::<usize>();
+        let mut worst = self
+            .future
+            .iter()
+            .filter(|(_, other)| !replaced.contains(other));
+        let mut evicted = Vec::new();
+        while count > self.limits.count || bytes > self.limits.bytes {
+            match worst.next() {
+                Some((key, other)) if *key < transaction.key() => {
+                    count -= 1;
+                    bytes -= self.transactions[other].len;
+                    evicted.push(*other);
+                }
+                _ => return Err(PoolError::Full),
+            }
+        }
+        Ok(evicted)
+    }
+
+    fn remove(&mut self, hash: &Hash) {
+        let Some(transaction) = self.transactions.remove(hash) else {
+            return;
+        };
+        self.bytes -= transaction.len;
+        for tag in &transaction.valid.provides {
+            self.provided.remove(tag);
+        }
+        for tag in &transaction.valid.requires {
+            if let Some(dependents) = self.required_by.get_mut(tag) {
+                dependents.remove(hash);
+                if dependents.is_empty() {
+                    self.required_by.remove(tag);
+                }
+            }
+        }
+        if let Some(signer) = transaction.signer() {
+            if let Some(hashes) = self.by_signer.get_mut(signer) {
+                hashes.remove(hash);
+                if hashes.is_empty() {
+                    self.by_signer.remove(signer);
+                }
+            }
+        }
+        self.future.remove(&(transaction.key(), *hash));
+        if self.ready.remove(hash) {
+            self.demote_dependents(&transaction);
+        }
+    }
+
+    /// The extrinsics which require a tag `transaction` provides.
+    fn dependents<'a>(
+        &'a self,
+        transaction: &'a PoolTransaction,
+    ) -> impl Iterator<Item = &'a Hash> + 'a {
+        transaction
+            .valid
+            .provides
+            .iter()
+            .filter_map(|tag| self.required_by.get(tag))
+            .flatten()
+    }
+
+    /// Whether every tag the extrinsic with `hash` requires is provided by a ready extrinsic.
+    fn is_satisfied(&self, hash: &Hash) -> bool {
+        self.transactions[hash].valid.requires.iter().all(|tag| {
+            self.provided
+                .get(tag)
+                .is_some_and(|provider| self.ready.contains(provider))
+        })
+    }
+
+    /// Move the extrinsic with `hash` to the ready queue, along with the future extrinsics this
+    /// makes ready, in turn.
+    fn promote(&mut self, hash: Hash) {
+        let mut promoted = vec![hash];
+        while let Some(hash) = promoted.pop() {
+            let transaction = &self.transactions[&hash];
+            self.future.remove(&(transaction.key(), hash));
+            self.ready.insert(hash);
+            promoted.extend(
+                self.dependents(transaction)
+                    .filter(|dependent| !self.ready.contains(*dependent))
+                    .filter(|dependent| self.is_satisfied(dependent)),
+            );
+        }
+    }
+
+    /// Move the ready extrinsics which require `transaction`, directly or not, back to the future
+    /// queue, once it left the ready queue.
+    fn demote_dependents(&mut self, transaction: &PoolTransaction) {
+        let mut demoted = self
+            .dependents(transaction)
+            .filter(|dependent| self.ready.contains(*dependent))
+            .copied()
+            .collect::<Vec<_>>();
+        while let Some(hash) = demoted.pop() {
+            if !self.ready.remove(&hash) {
+                continue;
+            }
+            let transaction = &self.transactions[&hash];
+            self.future.insert((transaction.key(), hash));
+            demoted.extend(
+                self.dependents(transaction)
+                    .filter(|dependent| self.ready.contains(*dependent)),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PoolError, PoolLimits, PoolStatus, PoolTransaction, TransactionPool};
+    use crate::block_builder::build_block;
+    use crate::chain_spec::ChainSpec;
+    use crate::codec::Encode;
+    use crate::crypto::{dev_seed, Pair};
+    use crate::storage::Storage;
+    use crate::support::{Digest, Extrinsic, InvalidTransaction, ValidTransaction};
+    use crate::types::{Balance, Nonce};
+    use crate::{balances, proof_of_existence, types, Runtime, RuntimeCall};
+
+    /// A runtime on top of the genesis state of the development chain.
+    fn new_chain() -> (Storage, Runtime) {
+        let mut state = Storage::default();
+        let runtime = Runtime::new();
+        state.execute_with(|| ChainSpec::development().build_genesis(&runtime));
+        (state, runtime)
+    }
+
+    fn transfer(runtime: &Runtime, caller: &Pair, nonce: Nonce, tip: Balance) -> types::Extrinsic {
+        Extrinsic::new_signed_with_tip(
+            RuntimeCall::balances(balances::Call::transfer {
+                receiver: Pair::ed25519(&dev_seed("charlie")).account(),
+                amount: 10,
+            }),
+            caller,
+            nonce,
+            tip,
+            &runtime.system.genesis_hash(),
+        )
+    }
+
+    #[test]
+    fn future_extrinsics_wait_for_the_ones_they_require() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let second = transfer(&runtime, &alice, 1, 0);
+            let third = transfer(&runtime, &alice, 2, 0);
+            assert!(pool.submit(&mut runtime, third.clone()).is_ok());
+            assert!(pool.submit(&mut runtime, second.clone()).is_ok());
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 0,
+                    future: 2
+                }
+            );
+            assert!(pool.ready().is_empty());
+
+            // The first extrinsic makes the whole chain of nonces ready, in order.
+            let first = transfer(&runtime, &alice, 0, 0);
+            assert!(pool.submit(&mut runtime, first.clone()).is_ok());
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 3,
+                    future: 0
+                }
+            );
+            let order = pool
+                .ready()
+                .iter()
+                .map(|extrinsic| extrinsic.signature.as_ref().unwrap().nonce)
+                .collect::<Vec<_>>();
+            assert_eq!(order, vec![0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn extrinsics_are_ordered_by_priority_and_tip() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let genesis_hash = runtime.system.genesis_hash();
+            let no_tip = transfer(&runtime, &alice, 0, 0);
+            let tip = transfer(&runtime, &bob, 0, 10);
+            // The same tip for less weight is a higher priority.
+            let claim = Extrinsic::new_signed_with_tip(
+                RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+                    claim: "ggs".to_string(),
+                }),
+                &alice,
+                1,
+                10,
+                &genesis_hash,
+            );
+            for extrinsic in [&no_tip, &claim, &tip] {
+                assert!(pool.submit(&mut runtime, extrinsic.clone()).is_ok());
+            }
+            // The claim requires the transfer of alice before it, even though it pays more.
+            let order = pool.ready().iter().map(|e| e.encode()).collect::<Vec<_>>();
+            assert_eq!(order, vec![tip.encode(), no_tip.encode(), claim.encode()]);
+
+            // Without tips, extrinsics keep the order they arrived in.
+            let mut pool = TransactionPool::new();
+            let first = transfer(&runtime, &bob, 0, 0);
+            let second = transfer(&runtime, &alice, 0, 0);
+            assert!(pool.submit(&mut runtime, first.clone()).is_ok());
+            assert!(pool.submit(&mut runtime, second.clone()).is_ok());
+            let order = pool.ready().iter().map(|e| e.encode()).collect::<Vec<_>>();
+            assert_eq!(order, vec![first.encode(), second.encode()]);
+        });
+    }
+
+    #[test]
+    fn higher_tips_replace_extrinsics_with_the_same_nonce() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let extrinsic = transfer(&runtime, &alice, 0, 1);
+            assert!(pool.submit(&mut runtime, extrinsic.clone()).is_ok());
+            assert_eq!(
+                pool.submit(&mut runtime, extrinsic.clone()),
+                Err(PoolError::AlreadyImported)
+            );
+            let lower = transfer(&runtime, &alice, 0, 0);
+            assert_eq!(
+                pool.submit(&mut runtime, lower),
+                Err(PoolError::TooLowPriority)
+            );
+
+            let replacement = transfer(&runtime, &alice, 0, 5);
+            assert!(pool.submit(&mut runtime, replacement.clone()).is_ok());
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 1,
+                    future: 0
+                }
+            );
+            assert_eq!(pool.ready()[0].encode(), replacement.encode());
+        });
+    }
+
+    #[test]
+    fn invalid_extrinsics_are_rejected() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let charlie = Pair::ed25519(&dev_seed("charlie"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let mut forged = transfer(&runtime, &alice, 0, 0);
+            forged.signature.as_mut().unwrap().tip = 100;
+            assert_eq!(
+                pool.submit(&mut runtime, forged),
+                Err(PoolError::Invalid(InvalidTransaction::BadProof))
+            );
+            // Charlie has nothing to pay the fee with.
+            let broke = transfer(&runtime, &charlie, 0, 0);
+            assert_eq!(
+                pool.submit(&mut runtime, broke),
+                Err(PoolError::Invalid(InvalidTransaction::Payment))
+            );
+            // Validating never changes the state.
+            assert_eq!(runtime.balances.balance(&alice.account()), 1_000_000);
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 0,
+                    future: 0
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn imported_blocks_evict_stale_extrinsics() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let pooled = transfer(&runtime, &alice, 0, 0);
+            let next = transfer(&runtime, &alice, 1, 0);
+            let included = transfer(&runtime, &bob, 0, 0);
+            for extrinsic in [&pooled, &next, &included] {
+                assert!(pool.submit(&mut runtime, extrinsic.clone()).is_ok());
+            }
+
+            // A block from elsewhere uses nonce 0 of alice for another extrinsic.
+            let other = transfer(&runtime, &alice, 0, 3);
+            let block = build_block(&mut runtime, Digest::default(), vec![other, included]);
+            assert_eq!(runtime.execute_block(block), Ok(()));
+
+            let evicted = pool.maintain(&mut runtime);
+            assert_eq!(evicted.len(), 2);
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 1,
+                    future: 0
+                }
+            );
+            // The next extrinsic of alice no longer requires anything.
+            assert_eq!(pool.ready()[0].encode(), next.encode());
+            let block = build_block(&mut runtime, Digest::default(), pool.ready());
+            assert_eq!(runtime.execute_block(block), Ok(()));
+            assert_eq!(pool.maintain(&mut runtime).len(), 1);
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 0,
+                    future: 0
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn the_last_nonce_is_rejected() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let last = transfer(&runtime, &alice, Nonce::MAX, 0);
+            assert_eq!(
+                pool.submit(&mut runtime, last),
+                Err(PoolError::Invalid(InvalidTransaction::NonceOverflow))
+            );
+        });
+    }
+
+    #[test]
+    fn signers_pay_for_all_of_their_extrinsics() {
+        let (mut state, mut runtime) = new_chain();
+        let dave = Pair::ed25519(&dev_seed("dave"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let valid = ValidTransaction {
+                priority: 0,
+                requires: Vec::new(),
+                provides: Vec::new(),
+            };
+            let transaction = PoolTransaction::new(transfer(&runtime, &dave, 0, 0), valid, 0);
+            let (_, fee) = transaction.payer.unwrap();
+            // Dave can pay for each extrinsic on its own, but only for two of them at once.
+            runtime.balances.set_balance(&dave.account(), 2 * fee + 1);
+
+            let extrinsics = (0..3)
+                .map(|nonce| transfer(&runtime, &dave, nonce, 0))
+                .collect::<Vec<_>>();
+            assert!(pool.submit(&mut runtime, extrinsics[0].clone()).is_ok());
+            assert!(pool.submit(&mut runtime, extrinsics[1].clone()).is_ok());
+            assert_eq!(
+                pool.submit(&mut runtime, extrinsics[2].clone()),
+                Err(PoolError::Invalid(InvalidTransaction::Payment))
+            );
+            // Replacing an extrinsic only pays for the replacement.
+            let replacement = transfer(&runtime, &dave, 1, 1);
+            assert!(pool.submit(&mut runtime, replacement).is_ok());
+            assert_eq!(pool.status().ready, 2);
+        });
+    }
+
+    #[test]
+    fn the_pool_is_bounded() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        let bob = Pair::sr25519(&dev_seed("bob"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool {
+                limits: PoolLimits {
+                    count: 3,
+                    bytes: usize::MAX,
+                    per_signer: 2,
+                },
+                ..TransactionPool::new()
+            };
+            let future = (1..4)
+                .map(|nonce| transfer(&runtime, &alice, nonce, 0))
+                .collect::<Vec<_>>();
+            assert!(pool.submit(&mut runtime, future[0].clone()).is_ok());
+            assert!(pool.submit(&mut runtime, future[1].clone()).is_ok());
+            assert_eq!(
+                pool.submit(&mut runtime, future[2].clone()),
+                Err(PoolError::TooManyFromSigner)
+            );
+
+            // A full pool keeps its best extrinsics, and evicts the worst future ones for better
+            // ones.
+            let ready = transfer(&runtime, &bob, 0, 0);
+            assert!(pool.submit(&mut runtime, ready.clone()).is_ok());
+            let untipped = transfer(&runtime, &bob, 1, 0);
+            assert_eq!(pool.submit(&mut runtime, untipped), Err(PoolError::Full));
+            let tipped = transfer(&runtime, &bob, 1, 5);
+            assert!(pool.submit(&mut runtime, tipped.clone()).is_ok());
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 2,
+                    future: 1
+                }
+            );
+            let order = pool.ready().iter().map(|e| e.encode()).collect::<Vec<_>>();
+            assert_eq!(order, vec![ready.encode(), tipped.encode()]);
+
+            // Nothing can replace a ready extrinsic.
+            let mut pool = TransactionPool {
+                limits: PoolLimits {
+                    count: 1,
+                    ..PoolLimits::default()
+                },
+                ..TransactionPool::new()
+            };
+            assert!(pool.submit(&mut runtime, ready).is_ok());
+            let tipped = transfer(&runtime, &alice, 0, 100);
+            assert_eq!(pool.submit(&mut runtime, tipped), Err(PoolError::Full));
+        });
+    }
+
+    #[test]
+    fn removing_an_extrinsic_moves_the_ones_which_require_it_back() {
+        let (mut state, mut runtime) = new_chain();
+        let alice = Pair::ed25519(&dev_seed("alice"));
+        state.execute_with(|| {
+            let mut pool = TransactionPool::new();
+            let extrinsics = (0..3)
+                .map(|nonce| transfer(&runtime, &alice, nonce, 0))
+                .collect::<Vec<_>>();
+            let hashes = extrinsics
+                .into_iter()
+                .map(|extrinsic| pool.submit(&mut runtime, extrinsic).unwrap())
+                .collect::<Vec<_>>();
+            pool.remove(&hashes[0]);
+            assert_eq!(
+                pool.status(),
+                PoolStatus {
+                    ready: 0,
+                    future: 2
+                }
+            );
+            assert!(pool.ready().is_empty());
+
+            let replacement = transfer(&runtime, &alice, 0, 1);
+            assert!(pool.submit(&mut runtime, replacement).is_ok());
+            assert_eq!(pool.ready().len(), 3);
+        });
+    }
+}
@@ -0,0 +1,463 @@
+//! A sparse Merkle trie, which commits to a set of key-value pairs with a single hash.
+//!
+//! Every key has a leaf in a binary tree of depth 256, found by following the bits of
+//! `blake2_256(key)` from the root, most significant first. Nearly all of the tree is empty, so it
+//! is never built in full:
+//! - the root of an empty subtree is `EMPTY`.
+//! - the root of a subtree holding a single entry is the hash of its leaf, however deep the leaf
+//!   sits below.
+//! - only subtrees holding two entries or more are hashed from their two children.
+//!
+//! The root only depends on the entries, not on the order they were inserted in. A `Trie` keeps
+//! the roots of its subtrees, so changing an entry only hashes the nodes along its path again.
+//!
+//! A `Proof` shows what the trie holds at a key, given only its root: the value at the key, or that
+//! there is none. It holds the roots of the siblings along the path of the key, down to where the
+//! path ends: at the leaf of the key, at the leaf of another key, or at an empty subtree.
+
+use crate::codec::{Decode, Encode};
+use crate::crypto::{blake2_256, Hash};
+
+/// The root of a subtree without any entries.
+pub const EMPTY: Hash = [0; 32];
+
+/// Prefixes the hash of a leaf, so it can never be mistaken for an inner node.
+const LEAF_PREFIX: u8 = 0;
+/// Prefixes the hash of an inner node.
+const NODE_PREFIX: u8 = 1;
+
+/// The nodes of a trie, with the roots of their subtrees, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Trie {
+    root: Node,
+}
+
+impl Trie {
+    /// The trie holding `entries`. Keys must be unique.
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Self {
+        Self {
+            root: Node::build(&leaves(entries), 0),
+        }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root.hash()
+    }
+
+    /// Set the value at `key`, or remove it if `value` is `None`.
+    pub fn set(&mut self, key: &[u8], value: Option<&[u8]>) {
+        let path = blake2_256(key);
+        let root = std::mem::take(&mut self.root);
+        self.root = match value {
+            Some(value) => root.insert(
+                Leaf {
+                    path,
+                    value_hash: blake2_256(value),
+                },
+                0,
+            ),
+            None => root.remove(&path, 0),
+        };
+    }
+}
+
+/// A subtree of a `Trie`. Like the root, a subtree holding a single entry is its leaf, so only the
+/// subtrees holding two entries or more are branches.
+#[derive(Debug, Clone, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf(Leaf),
+    Branch {
+        /// The root of the subtree.
+        hash: Hash,
+        /// The left and right subtrees.
+        children: Box<[Node; 2]>,
+    },
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Empty => EMPTY,
+            Node::Leaf(leaf) => leaf.hash(),
+            Node::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn branch(children: [Node; 2]) -> Self {
+        Node::Branch {
+            hash: node_hash(&children[0].hash(), &children[1].hash()),
+            children: Box::new(children),
+        }
+    }
+
+    /// The subtree at `depth` holding `leaves`, see `subtree_root`.
+    fn build(leaves: &[Leaf], depth: usize) -> Self {
+        match leaves {
+            [] => Node::Empty,
+            [leaf] => Node::Leaf(*leaf),
+            _ => {
+                let (left, right) = split(leaves, depth);
+                Node::branch([Node::build(left, depth + 1), Node::build(right, depth + 1)])
+            }
+        }
+    }
+
+    /// This subtree at `depth`, with `leaf` added, or replacing the leaf with the same path.
+    fn insert(self, leaf: Leaf, depth: usize) -> Self {
+        let mut children = match self {
+            Node::Empty => return Node::Leaf(leaf),
+            Node::Leaf(other) if other.path == leaf.path => return Node::Leaf(leaf),
+            Node::Leaf(other) => {
+                let mut children = [Node::Empty, Node::Empty];
+                children[bit(&other.path, depth) as usize] = Node::Leaf(other);
+                children
+            }
+            Node::Branch { children, .. } => *children,
+        };
+        let side = bit(&leaf.path, depth) as usize;
+        children[side] = std::mem::take(&mut children[side]).insert(leaf, depth + 1);
+        Node::branch(children)
+    }
+
+    /// This subtree at `depth`, without the leaf at `path`.
+    fn remove(self, path: &Hash, depth: usize) -> Self {
+        match self {
+            Node::Leaf(leaf) if leaf.path == *path => Node::Empty,
+            Node::Branch { children, .. } => {
+                let mut children = *children;
+                let side = bit(path, depth) as usize;
+                children[side] = std::mem::take(&mut children[side]).remove(path, depth + 1);
+                match children {
+                    // A single entry is left, whose leaf moves up.
+                    [Node::Empty, Node::Leaf(leaf)] | [Node::Leaf(leaf), Node::Empty] => {
+                        Node::Leaf(leaf)
+                    }
+                    children => Node::branch(children),
+                }
+            }
+            node => node,
+        }
+    }
+}
+
+/// A leaf of the trie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Leaf {
+    /// `blake2_256` of the key.
+    path: Hash,
+    /// `blake2_256` of the value.
+    value_hash: Hash,
+}
+
+impl Leaf {
+    /// The hash of the leaf. It commits to its full path, since it may sit anywhere below it.
+    fn hash(&self) -> Hash {
+        let mut data = vec![LEAF_PREFIX];
+        data.extend_from_slice(&self.path);
+        data.extend_from_slice(&self.value_hash);
+        blake2_256(&data)
+    }
+}
+
+/// The leaves of `entries`, sorted by path.
+fn leaves<'a>(entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Vec<Leaf> {
+    let mut leaves = entries
+        .into_iter()
+        .map(|(key, value)| Leaf {
+            path: blake2_256(key),
+            value_hash: blake2_256(value),
+        })
+        .collect::<Vec<_>>();
+    leaves.sort_unstable();
+    leaves
+}
+
+/// The hash of an inner node.
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut data = vec![NODE_PREFIX];
+    data.extend_from_slice(left);
+    data.extend_from_slice(right);
+    blake2_256(&data)
+}
+
+/// The bit of `path` at `depth`: `false` to go left, `true` to go right.
+fn bit(path: &Hash, depth: usize) -> bool {
+    path[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+/// The root of the subtree at `depth` holding `leaves`, which are sorted by path, and all share
+/// the first `depth` bits of their path.
+fn subtree_root(leaves: &[Leaf], depth: usize) -> Hash {
+    match leaves {
+        [] => EMPTY,
+        [leaf] => leaf.hash(),
+        _ => {
+            let (left, right) = split(leaves, depth);
+            node_hash(
+                &subtree_root(left, depth + 1),
+                &subtree_root(right, depth + 1),
+            )
+        }
+    }
+}
+
+/// Split `leaves` into the left and right subtrees below `depth`. Sorted paths put every leaf on
+/// the left before any leaf on the right.
+fn split(leaves: &[Leaf], depth: usize) -> (&[Leaf], &[Leaf]) {
+    leaves.split_at(leaves.partition_point(|leaf| !bit(&leaf.path, depth)))
+}
+
+/// Proof of the value at a key of a trie, or of its absence, against the root of the trie.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Proof {
+    /// The roots of the siblings along the path of the key, from the top down.
+    pub siblings: Vec<Hash>,
+    /// What the path of the key ends at, below the last sibling. `None` for an empty subtree.
+    pub leaf: Option<ProofLeaf>,
+}
+
+/// The leaf a path ends at, see `Proof`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum ProofLeaf {
+    /// The leaf of the key itself, which holds `value`.
+    Value(Vec<u8>),
+    /// The leaf of another key, which shares the path of the key down to here. The key is not in
+    /// the trie.
+    Other { path: Hash, value_hash: Hash },
+}
+
+/// The reason a `Proof` was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofError {
+    /// The proof has more siblings than there are bits in a path.
+    TooDeep,
+    /// The leaf of another key is not on the path of the key, or is the leaf of the key itself.
+    UnrelatedLeaf,
+    /// The proof does not lead to the expected root.
+    RootMismatch,
+}
+
+/// Prove the value at `key` in the trie holding `entries`, or its absence.
+pub fn prove<'a>(entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>, key: &[u8]) -> Proof {
+    let mut value = None;
+    let leaves = leaves(entries.into_iter().inspect(|(entry_key, entry_value)| {
+        if *entry_key == key {
+            value = Some(entry_value.to_vec());
+        }
+    }));
+
+    let path = blake2_256(key);
+    let mut siblings = Vec::new();
+    let mut subtree = &leaves[..];
+    loop {
+        let leaf = match subtree {
+            [] => None,
+            [leaf] if leaf.path == path => Some(ProofLeaf::Value(
+                value.expect("the key has a leaf, so it is one of the entries; qed"),
+            )),
+            [leaf] => Some(ProofLeaf::Other {
+                path: leaf.path,
+                value_hash: leaf.value_hash,
+            }),
+            _ => {
+                // Follow the path of the key, and keep the root of the subtree we did not take.
+                let depth = siblings.len();
+                let (left, right) = split(subtree, depth);
+                let (next, sibling) = if bit(&path, depth) {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                siblings.push(subtree_root(sibling, depth + 1));
+                subtree = next;
+                continue;
+            }
+        };
+        return Proof { siblings, leaf };
+    }
+}
+
+/// Check `proof` against `root`, and return the value it proves at `key`: `None` if the key is not
+/// in the trie.
+pub fn verify_proof(root: &Hash, key: &[u8], proof: &Proof) -> Result<Option<Vec<u8>>, ProofError> {
+    let path = blake2_256(key);
+    let depth = proof.siblings.len();
+    if depth > 8 * path.len() {
+        return Err(ProofError::TooDeep);
+    }
+
+    let (mut hash, value) = match &proof.leaf {
+        None => (EMPTY, None),
+        Some(ProofLeaf::Value(value)) => {
+            let leaf = Leaf {
+                path,
+                value_hash: blake2_256(value),
+            };
+            (leaf.hash(), Some(value.clone()))
+        }
+        Some(ProofLeaf::Other {
+            path: other,
+            value_hash,
+        }) => {
+            if *other == path || (0..depth).any(|i| bit(other, i) != bit(&path, i)) {
+                return Err(ProofError::UnrelatedLeaf);
+            }
+            let leaf = Leaf {
+                path: *other,
+                value_hash: *value_hash,
+            };
+            (leaf.hash(), None)
+        }
+    };
+
+    // Hash our way back up to the root, with the siblings on the other side of the path.
+    for (depth, sibling) in proof.siblings.iter().enumerate().rev() {
+        hash = if bit(&path, depth) {
+            node_hash(sibling, &hash)
+        } else {
+            node_hash(&hash, sibling)
+        };
+    }
+    if hash != *root {
+        return Err(ProofError::RootMismatch);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prove, verify_proof, Leaf, ProofError, ProofLeaf, Trie, EMPTY};
+    use crate::crypto::blake2_256;
+
+    fn root<'a>(entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> [u8; 32] {
+        Trie::from_entries(entries).root()
+    }
+
+    #[test]
+    fn root_of_entries() {
+        assert_eq!(root([]), EMPTY);
+
+        // A single entry is its own root.
+        let single = root([(&b"alice"[..], &b"100"[..])]);
+        let leaf = Leaf {
+            path: blake2_256(b"alice"),
+            value_hash: blake2_256(b"100"),
+        };
+        assert_eq!(single, leaf.hash());
+
+        let entries = [
+            (&b"alice"[..], &b"100"[..]),
+            (&b"bob"[..], &b"50"[..]),
+            (&b"charlie"[..], &b"0"[..]),
+        ];
+        let all = root(entries);
+        assert_ne!(all, single);
+
+        // The order of the entries does not matter, but every key and value does.
+        let mut reversed = entries;
+        reversed.reverse();
+        assert_eq!(root(reversed), all);
+
+        let mut changed = entries;
+        changed[1].1 = &b"51"[..];
+        assert_ne!(root(changed), all);
+        assert_ne!(root(entries[..2].iter().copied()), all);
+    }
+
+    #[test]
+    fn inclusion_and_non_inclusion_proofs() {
+        let entries = (0u32..50)
+            .map(|i| (i.to_le_bytes().to_vec(), (i * 10).to_le_bytes().to_vec()))
+            .collect::<Vec<_>>();
+        let entries = || entries.iter().map(|(k, v)| (k.as_slice(), v.as_slice()));
+        let root = root(entries());
+
+        for (key, value) in entries() {
+            let proof = prove(entries(), key);
+            assert_eq!(proof.leaf, Some(ProofLeaf::Value(value.to_vec())));
+            assert_eq!(verify_proof(&root, key, &proof), Ok(Some(value.to_vec())));
+        }
+
+        // Absent keys end up either at an empty subtree, or at the leaf of another key.
+        let mut ends = Vec::new();
+        for i in 50u32..100 {
+            let key = i.to_le_bytes();
+            let proof = prove(entries(), &key);
+            assert_eq!(verify_proof(&root, &key, &proof), Ok(None));
+            ends.push(proof.leaf.is_none());
+        }
+        assert!(ends.contains(&true) && ends.contains(&false));
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let entries = [
+            (&b"alice"[..], &b"100"[..]),
+            (&b"bob"[..], &b"50"[..]),
+            (&b"charlie"[..], &b"0"[..]),
+        ];
+        let root = root(entries);
+
+        // A different value for the key.
+        let mut proof = prove(entries, b"alice");
+        proof.leaf = Some(ProofLeaf::Value(b"1000".to_vec()));
+        assert_eq!(
+            verify_proof(&root, b"alice", &proof),
+            Err(ProofError::RootMismatch)
+        );
+
+        // The proof of another key, passed off as proof that `alice` is absent.
+        let bob = prove(entries, b"bob");
+        let Some(ProofLeaf::Value(value)) = &bob.leaf else {
+            panic!("bob is in the trie");
+        };
+        let forged = super::Proof {
+            siblings: bob.siblings.clone(),
+            leaf: Some(ProofLeaf::Other {
+                path: blake2_256(b"bob"),
+                value_hash: blake2_256(value),
+            }),
+        };
+        assert!(verify_proof(&root, b"alice", &forged).is_err());
+
+        // A proof against another root.
+        assert_eq!(
+            verify_proof(&EMPTY, b"bob", &bob),
+            Err(ProofError::RootMismatch)
+        );
+        assert_eq!(verify_proof(&EMPTY, b"bob", &prove([], b"bob")), Ok(None));
+    }
+
+    #[test]
+    fn changing_entries_updates_the_root() {
+        let mut entries = (0u32..50)
+            .map(|i| (i.to_le_bytes().to_vec(), (i * 10).to_le_bytes().to_vec()))
+            .collect::<Vec<_>>();
+        let mut trie = Trie::default();
+        assert_eq!(trie.root(), EMPTY);
+        for (key, value) in &entries {
+            trie.set(key, Some(value));
+        }
+        let root_of = |entries: &[(Vec<u8>, Vec<u8>)]| {
+            root(entries.iter().map(|(k, v)| (k.as_slice(), v.as_slice())))
+        };
+        assert_eq!(trie.root(), root_of(&entries));
+
+        // Changing a value, then removing entries down to a single one, and then none.
+        entries[7].1 = b"changed".to_vec();
+        trie.set(&entries[7].0, Some(&entries[7].1));
+        assert_eq!(trie.root(), root_of(&entries));
+        while let Some((key, _)) = entries.pop() {
+            trie.set(&key, None);
+            assert_eq!(trie.root(), root_of(&entries));
+        }
+        assert_eq!(trie.root(), EMPTY);
+
+        // Removing a key which is not in the trie changes nothing.
+        trie.set(b"alice", Some(b"100"));
+        trie.set(b"bob", None);
+        assert_eq!(trie.root(), root([(&b"alice"[..], &b"100"[..])]));
+    }
+}
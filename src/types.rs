@@ -1,10 +1,14 @@
+use crate::crypto::{AccountId32, MultiSignature, MultiSigner};
 use crate::RuntimeCall;
 
-pub type AccountId= String;
+pub type AccountId = AccountId32;
 pub type Balance = u128;
 pub type BlockNumber = u32;
 pub type Nonce = u32;
-pub type Extrinsic = super::support::Extrinsic<AccountId, RuntimeCall>;
+pub type Extrinsic =
+    super::support::Extrinsic<MultiSigner, MultiSignature, Nonce, Balance, RuntimeCall>;
 pub type Header = super::support::Header<BlockNumber>;
 pub type Block = super::support::Block<Header, Extrinsic>;
-pub type Content = &'static str;
\ No newline at end of file
+pub type Content = String;
+/// Milliseconds since the unix epoch.
+pub type Moment = u64;
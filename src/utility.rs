@@ -0,0 +1,322 @@
+//! Utility: dispatch many calls in one extrinsic.
+//!
+//! Every call of a batch is dispatched through the runtime with the origin of the batch, in order.
+//! The batch calls differ in how they handle a call which fails:
+//! - `batch` stops at the first failing call. The calls before it keep their changes.
+//! - `batch_all` fails as a whole, so none of the calls keep their changes.
+//! - `force_batch` carries on with the next call.
+//!
+//! A failing call never keeps its own changes. Each call which was dispatched gets an
+//! `ItemCompleted` or `ItemFailed` event, so it is clear how far the batch went.
+
+use crate::codec::{Decode, Encode};
+use crate::support::{
+    BuildGenesisConfig, DispatchError, DispatchErrorWithPostInfo, DispatchNested, DispatchResult,
+    DispatchResultWithPostInfo, Hooks, PalletEvents, PostDispatchInfo, ValidateUnsigned, Weight,
+};
+use crate::system::OriginFor;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use serde::{Deserialize, Serialize};
+
+/// Implemented by the runtime, which dispatches the calls of a batch.
+pub trait Config: crate::system::Config + DispatchNested<RuntimeOrigin = OriginFor<Self>> {}
+
+/// The weight of a batch on top of the weights of its calls.
+const BATCH_WEIGHT: Weight = 1_000;
+
+/// Events emitted by the utility pallet.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event<T: Config> {
+    /// The call at `index` of a `batch` failed with `error`, so the calls after it were not
+    /// dispatched.
+    BatchInterrupted { index: u32, error: DispatchError },
+    /// Every call of the batch was dispatched successfully.
+    BatchCompleted,
+    /// Every call of a `force_batch` was dispatched, but some of them failed.
+    BatchCompletedWithErrors,
+    /// A call of the batch was dispatched successfully.
+    ItemCompleted,
+    /// A call of the batch failed with `error`, and was rolled back.
+    ItemFailed { error: DispatchError },
+    #[doc(hidden)]
+    __Ignore(PhantomData<T>, Infallible),
+}
+
+/// The utility pallet raises no errors of its own: a batch fails with the error of one of its
+/// calls.
+#[macros::error]
+pub enum Error<T> {}
+
+/// The utility pallet has no initial state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "", deny_unknown_fields)]
+pub struct GenesisConfig<T: Config> {
+    #[serde(skip)]
+    _config: PhantomData<T>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+    fn default() -> Self {
+        Self {
+            _config: PhantomData,
+        }
+    }
+}
+
+impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+    fn build(&self) {}
+}
+
+/// The utility pallet keeps nothing in storage but its events.
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+    _config: PhantomData<T>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self {
+            _config: PhantomData,
+        }
+    }
+
+    /// The weight a batch of `calls` declares: the weights of all of them.
+    fn batch_weight(calls: &[T::Call]) -> Weight {
+        calls
+            .iter()
+            .map(T::weight)
+            .fold(BATCH_WEIGHT, Weight::saturating_add)
+    }
+
+    /// Dispatch `call` with `origin` through the runtime, and record its outcome. Returns its
+    /// result, and the weight it used.
+    fn dispatch_item(&mut self, origin: OriginFor<T>, call: T::Call) -> (DispatchResult, Weight) {
+        let (result, weight) = T::dispatch_nested(origin, call);
+        match result {
+            Ok(()) => self.deposit_event(Event::ItemCompleted),
+            Err(error) => self.deposit_event(Event::ItemFailed { error }),
+        }
+        (result, weight)
+    }
+}
+
+/// The post info of a batch which used `weight`.
+fn used(weight: Weight) -> PostDispatchInfo {
+    PostDispatchInfo {
+        actual_weight: Some(weight),
+    }
+}
+
+impl<T: Config> PalletEvents for Pallet<T> {
+    const PALLET: &'static str = "Utility";
+    type Event = Event<T>;
+}
+
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> ValidateUnsigned for Pallet<T> {}
+
+#[macros::call]
+impl<T: Config> Pallet<T> {
+    /// Dispatch `calls` in order, until one of them fails. The batch itself succeeds either way.
+    #[weight(Self::batch_weight(calls))]
+    pub fn batch(
+        &mut self,
+        origin: OriginFor<T>,
+        calls: Vec<T::Call>,
+    ) -> DispatchResultWithPostInfo {
+        let mut weight = BATCH_WEIGHT;
+        for (index, call) in calls.into_iter().enumerate() {
+            let (result, call_weight) = self.dispatch_item(origin.clone(), call);
+            weight = weight.saturating_add(call_weight);
+            if let Err(error) = result {
+                let index = index as u32;
+                self.deposit_event(Event::BatchInterrupted { index, error });
+                return Ok(used(weight));
+            }
+        }
+        self.deposit_event(Event::BatchCompleted);
+        Ok(used(weight))
+    }
+
+    /// Dispatch `calls` in order. If one of them fails, the batch fails with its error, and all
+    /// of them are rolled back.
+    #[weight(Self::batch_weight(calls))]
+    pub fn batch_all(
+        &mut self,
+        origin: OriginFor<T>,
+        calls: Vec<T::Call>,
+    ) -> DispatchResultWithPostInfo {
+        let mut weight = BATCH_WEIGHT;
+        for call in calls {
+            let (result, call_weight) = self.dispatch_item(origin.clone(), call);
+            weight = weight.saturating_add(call_weight);
+            if let Err(error) = result {
+                return Err(DispatchErrorWithPostInfo {
+                    post_info: used(weight),
+                    error,
+                });
+            }
+        }
+        self.deposit_event(Event::BatchCompleted);
+        Ok(used(weight))
+    }
+
+    /// Dispatch all of `calls` in order, whether the ones before failed or not.
+    #[weight(Self::batch_weight(calls))]
+    pub fn force_batch(
+        &mut self,
+        origin: OriginFor<T>,
+        calls: Vec<T::Call>,
+    ) -> DispatchResultWithPostInfo {
+        let mut weight = BATCH_WEIGHT;
+        let mut failed = false;
+        for call in calls {
+            let (result, call_weight) = self.dispatch_item(origin.clone(), call);
+            weight = weight.saturating_add(call_weight);
+            failed |= result.is_err();
+        }
+        if failed {
+            self.deposit_event(Event::BatchCompletedWithErrors);
+        } else {
+            self.deposit_event(Event::BatchCompleted);
+        }
+        Ok(used(weight))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Pallet};
+    use crate::balances;
+    use crate::mock::{TestConfig, ALICE, BOB};
+    use crate::storage::{with_transaction, Storage};
+    use crate::support::{Dispatch, DispatchError, PalletEvents};
+    use crate::system::{OriginFor, RawOrigin};
+
+    /// Three transfers from alice, of 10, 1000 and 20, where she can only pay for 10 and 20.
+    fn setup() -> (
+        Pallet<TestConfig>,
+        balances::Pallet<TestConfig>,
+        Vec<balances::Call<TestConfig>>,
+    ) {
+        let mut balances = balances::Pallet::new();
+        balances.set_balance(&ALICE, 100);
+        let transfer = |amount| balances::Call::transfer {
+            receiver: BOB,
+            amount,
+        };
+        let calls = vec![transfer(10), transfer(1_000), transfer(20)];
+        (Pallet::new(), balances, calls)
+    }
+
+    fn alice() -> OriginFor<TestConfig> {
+        RawOrigin::Signed(ALICE)
+    }
+
+    #[test]
+    fn batch_stops_at_the_first_error() {
+        Storage::default().execute_with(|| {
+            let (mut utility, mut balances, calls) = setup();
+            // Three transfers, and the batch itself.
+            assert_eq!(
+                <Pallet<TestConfig> as Dispatch>::weight(&super::Call::batch {
+                    calls: calls.clone()
+                }),
+                31_000
+            );
+
+            assert!(utility.batch(alice(), calls).is_ok());
+            assert_eq!(balances.balance(&ALICE), 90);
+            assert_eq!(balances.balance(&BOB), 10);
+            assert_eq!(
+                utility.take_events(),
+                vec![
+                    Event::ItemCompleted,
+                    Event::ItemFailed {
+                        error: balances::Error::<TestConfig>::InsufficientBalance.into()
+                    },
+                    Event::BatchInterrupted {
+                        index: 1,
+                        error: balances::Error::<TestConfig>::InsufficientBalance.into()
+                    },
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn batch_all_is_all_or_nothing() {
+        Storage::default().execute_with(|| {
+            let (mut utility, mut balances, mut calls) = setup();
+
+            // The runtime rolls back a failing call, as it does every extrinsic.
+            let result = with_transaction(|| utility.batch_all(alice(), calls.clone()));
+            assert_eq!(
+                result.map_err(|error| error.error),
+                Err(balances::Error::<TestConfig>::InsufficientBalance.into())
+            );
+            assert_eq!(balances.balance(&ALICE), 100);
+            assert_eq!(balances.balance(&BOB), 0);
+            assert!(utility.take_events().is_empty());
+
+            calls.remove(1);
+            assert!(utility.batch_all(alice(), calls).is_ok());
+            assert_eq!(balances.balance(&ALICE), 70);
+            assert_eq!(balances.balance(&BOB), 30);
+            assert_eq!(
+                utility.take_events(),
+                vec![
+                    Event::ItemCompleted,
+                    Event::ItemCompleted,
+                    Event::BatchCompleted
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn force_batch_continues_past_errors() {
+        Storage::default().execute_with(|| {
+            let (mut utility, mut balances, calls) = setup();
+
+            assert!(utility.force_batch(alice(), calls).is_ok());
+            assert_eq!(balances.balance(&ALICE), 70);
+            assert_eq!(balances.balance(&BOB), 30);
+            assert_eq!(
+                utility.take_events(),
+                vec![
+                    Event::ItemCompleted,
+                    Event::ItemFailed {
+                        error: balances::Error::<TestConfig>::InsufficientBalance.into()
+                    },
+                    Event::ItemCompleted,
+                    Event::BatchCompletedWithErrors,
+                ]
+            );
+
+            // Calls are dispatched with the origin of the batch.
+            let force_set_balance = balances::Call::force_set_balance {
+                who: ALICE,
+                amount: 1_000,
+            };
+            assert!(utility
+                .force_batch(alice(), vec![force_set_balance.clone()])
+                .is_ok());
+            assert_eq!(
+                utility.take_events(),
+                vec![
+                    Event::ItemFailed {
+                        error: DispatchError::BadOrigin
+                    },
+                    Event::BatchCompletedWithErrors,
+                ]
+            );
+            assert!(utility
+                .force_batch(RawOrigin::Root, vec![force_set_balance])
+                .is_ok());
+            assert_eq!(balances.balance(&ALICE), 1_000);
+        });
+    }
+}